@@ -16,6 +16,8 @@
  * @property {boolean} allow_delete
  * @property {boolean} allow_search
  * @property {boolean} allow_archive
+ * @property {boolean} allow_extract
+ * @property {boolean} in_archive
  * @property {boolean} auth
  * @property {string} user
  * @property {boolean} dir_exists
@@ -24,6 +26,16 @@
 
 var DUFS_MAX_UPLOADINGS = 1;
 
+/**
+ * Files larger than this are uploaded in chunks through an upload session
+ */
+var DUFS_UPLOAD_CHUNK_SIZE = 8 * 1024 * 1024;
+
+/**
+ * Number of chunks of a file uploaded in parallel
+ */
+var DUFS_UPLOAD_CHUNK_PARALLEL = 4;
+
 /**
  * @type {DATA} DATA
  */
@@ -62,6 +74,8 @@ const ICONS = {
   edit: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M12.146.146a.5.5 0 0 1 .708 0l3 3a.5.5 0 0 1 0 .708l-10 10a.5.5 0 0 1-.168.11l-5 2a.5.5 0 0 1-.65-.65l2-5a.5.5 0 0 1 .11-.168l10-10zM11.207 2.5 13.5 4.793 14.793 3.5 12.5 1.207 11.207 2.5zm1.586 3L10.5 3.207 4 9.707V10h.5a.5.5 0 0 1 .5.5v.5h.5a.5.5 0 0 1 .5.5v.5h.293l6.5-6.5zm-9.761 5.175-.106.106-1.528 3.821 3.821-1.528.106-.106A.5.5 0 0 1 5 12.5V12h-.5a.5.5 0 0 1-.5-.5V11h-.5a.5.5 0 0 1-.468-.325z"/></svg>`,
   delete: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M6.854 7.146a.5.5 0 1 0-.708.708L7.293 9l-1.147 1.146a.5.5 0 0 0 .708.708L8 9.707l1.146 1.147a.5.5 0 0 0 .708-.708L8.707 9l1.147-1.146a.5.5 0 0 0-.708-.708L8 8.293 6.854 7.146z"/><path d="M14 14V4.5L9.5 0H4a2 2 0 0 0-2 2v12a2 2 0 0 0 2 2h8a2 2 0 0 0 2-2zM9.5 3A1.5 1.5 0 0 0 11 4.5h2V14a1 1 0 0 1-1 1H4a1 1 0 0 1-1-1V2a1 1 0 0 1 1-1h5.5v2z"/></svg>`,
   view: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M4 0a2 2 0 0 0-2 2v12a2 2 0 0 0 2 2h8a2 2 0 0 0 2-2V2a2 2 0 0 0-2-2zm0 1h8a1 1 0 0 1 1 1v12a1 1 0 0 1-1 1H4a1 1 0 0 1-1-1V2a1 1 0 0 1 1-1"/></svg>`,
+  browse: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M1 3.5A1.5 1.5 0 0 1 2.5 2h3.4l1.5 1.5h6.1A1.5 1.5 0 0 1 15 5v7.5a1.5 1.5 0 0 1-1.5 1.5h-11A1.5 1.5 0 0 1 1 12.5zm1.5-.5a.5.5 0 0 0-.5.5v9a.5.5 0 0 0 .5.5h11a.5.5 0 0 0 .5-.5V5a.5.5 0 0 0-.5-.5H7l-1.5-1.5zM7 6h2v1H7zm0 2h2v1H7zm0 2h2v2H7z"/></svg>`,
+  extract: `<svg width="16" height="16" viewBox="0 0 16 16"><path d="M1 2h14v3H1zm1 1v1h12V3zm0 3h12v8a1 1 0 0 1-1 1H3a1 1 0 0 1-1-1zm1 1v7h10V7zm3 1h4v1H6z"/></svg>`,
 }
 
 /**
@@ -159,6 +173,11 @@ async function ready() {
     document.querySelector(".editor-page").classList.remove("hidden");
 
     await setupEditorPage();
+  } else if (DATA.kind === "Upload") {
+    document.title = `Upload to ${DATA.href} - Dufs`;
+    document.querySelector(".index-page").classList.remove("hidden");
+
+    await setupUploadPage();
   }
 }
 
@@ -180,6 +199,14 @@ class Uploader {
     this.idx = Uploader.globalIdx++;
     this.file = file;
     this.url = newUrl(this.name);
+    /**
+     * @type {string | null}
+     */
+    this.sessionUrl = null;
+    /**
+     * @type {[number, number][]}
+     */
+    this.received = [];
   }
 
   upload() {
@@ -215,6 +242,11 @@ class Uploader {
   ajax() {
     const { url } = this;
 
+    if (this.file.size > DUFS_UPLOAD_CHUNK_SIZE && this.uploadOffset == 0) {
+      this.uploadChunks();
+      return;
+    }
+
     this.uploaded = 0;
     this.lastUptime = Date.now();
 
@@ -223,7 +255,7 @@ class Uploader {
     ajax.addEventListener("readystatechange", () => {
       if (ajax.readyState === 4) {
         if (ajax.status >= 200 && ajax.status < 300) {
-          this.complete();
+          this.complete(ajax.getResponseHeader("x-upload-name"));
         } else {
           if (ajax.status != 0) {
             this.fail(`${ajax.status} ${ajax.statusText}`);
@@ -236,16 +268,125 @@ class Uploader {
     if (this.uploadOffset > 0) {
       ajax.open("PATCH", url);
       ajax.setRequestHeader("X-Update-Range", "append");
+      ajax.setRequestHeader("X-Mtime", this.mtime());
       ajax.send(this.file.slice(this.uploadOffset));
     } else {
       ajax.open("PUT", url);
+      ajax.setRequestHeader("X-Upload-Conflict", Uploader.conflict);
+      ajax.setRequestHeader("X-Mtime", this.mtime());
       ajax.send(this.file);
       // setTimeout(() => ajax.abort(), 3000);
     }
   }
 
+  async uploadChunks() {
+    this.uploaded = 0;
+    this.lastUptime = Date.now();
+    try {
+      if (!this.sessionUrl) {
+        const res = await fetch(this.url + "?session", {
+          method: "POST",
+          headers: {
+            "X-Upload-Length": `${this.file.size}`,
+            "X-Upload-Conflict": Uploader.conflict,
+          },
+        });
+        await assertResOK(res);
+        this.sessionUrl = new URL(res.headers.get("location"), location.href).href;
+        this.received = [];
+      }
+      const chunks = [];
+      let doneBytes = 0;
+      for (let start = 0; start < this.file.size; start += DUFS_UPLOAD_CHUNK_SIZE) {
+        const end = Math.min(start + DUFS_UPLOAD_CHUNK_SIZE, this.file.size);
+        if (this.received.some(([from, to]) => from <= start && end <= to)) {
+          doneBytes += end - start;
+        } else {
+          chunks.push([start, end]);
+        }
+      }
+      const loading = new Map();
+      const onProgress = () => {
+        let loaded = doneBytes;
+        for (const v of loading.values()) loaded += v;
+        this.progress({ loaded, total: this.file.size });
+      };
+      let next = 0;
+      const worker = async () => {
+        while (next < chunks.length) {
+          const [start, end] = chunks[next++];
+          await this.uploadChunk(start, end, loaded => {
+            loading.set(start, loaded);
+            onProgress();
+          });
+          loading.delete(start);
+          doneBytes += end - start;
+          this.received.push([start, end]);
+        }
+      };
+      await Promise.all(Array.from({ length: DUFS_UPLOAD_CHUNK_PARALLEL }, worker));
+      const res = await fetch(this.sessionUrl, {
+        method: "POST",
+        headers: {
+          "X-Mtime": this.mtime(),
+        },
+      });
+      await assertResOK(res);
+      this.complete(res.headers.get("x-upload-name"));
+    } catch (err) {
+      this.fail(err.message);
+    }
+  }
+
+  /**
+   * Modification time of the file in unix seconds, kept by the server
+   */
+  mtime() {
+    return `${this.file.lastModified / 1000}`;
+  }
+
+  /**
+   * @param {number} start
+   * @param {number} end
+   * @param {(loaded: number) => void} onProgress
+   */
+  uploadChunk(start, end, onProgress) {
+    return new Promise((resolve, reject) => {
+      const ajax = new XMLHttpRequest();
+      ajax.upload.addEventListener("progress", e => onProgress(e.loaded), false);
+      ajax.addEventListener("load", () => {
+        if (ajax.status >= 200 && ajax.status < 300) {
+          resolve();
+        } else {
+          reject(new Error(`${ajax.status} ${ajax.statusText}`));
+        }
+      });
+      ajax.addEventListener("error", () => reject(new Error("Network error")), false);
+      ajax.addEventListener("abort", () => reject(new Error("Aborted")), false);
+      ajax.open("PUT", this.sessionUrl);
+      ajax.setRequestHeader("X-Update-Range", `bytes=${start}-${end - 1}`);
+      ajax.send(this.file.slice(start, end));
+    });
+  }
+
   async retry() {
     const { url } = this;
+    if (this.sessionUrl) {
+      const res = await fetch(this.sessionUrl, {
+        method: "HEAD",
+      });
+      if (res.status == 200) {
+        const value = res.headers.get("x-upload-received") || "";
+        this.received = value.split(",").filter(v => v).map(v => {
+          const [start, end] = v.split("-").map(v => parseInt(v));
+          return [start, end + 1];
+        });
+      } else {
+        this.sessionUrl = null;
+      }
+      this.uploadChunks();
+      return;
+    }
     let res = await fetch(url, {
       method: "HEAD",
     });
@@ -272,7 +413,17 @@ class Uploader {
     this.lastUptime = now;
   }
 
-  complete() {
+  /**
+   * @param {string | null} storedName
+   */
+  complete(storedName = null) {
+    if (storedName) {
+      const parts = this.name.split("/");
+      parts[parts.length - 1] = decodeURIComponent(storedName);
+      const $link = document.querySelector(`#upload${this.idx} .cell-name a`);
+      $link.href = newUrl(parts.join("/"));
+      $link.textContent = parts.join("/");
+    }
     const $uploadStatusNew = this.$uploadStatus.cloneNode(true);
     $uploadStatusNew.innerHTML = `✓`;
     this.$uploadStatus.parentNode.replaceChild($uploadStatusNew, this.$uploadStatus);
@@ -296,6 +447,8 @@ Uploader.runnings = 0;
 
 Uploader.auth = false;
 
+Uploader.conflict = "overwrite";
+
 /**
  * @type Uploader[]
  */
@@ -379,6 +532,10 @@ async function setupIndexPage() {
     setupSearch();
   }
 
+  if (DATA.allow_archive) {
+    setupDownloadSelected();
+  }
+
   renderPathsTableHead();
   renderPathsTableBody();
 
@@ -387,6 +544,23 @@ async function setupIndexPage() {
   }
 }
 
+async function setupUploadPage() {
+  if (DATA.allow_upload) {
+    setupDropzone();
+    setupUploadFile();
+    // Uploads are always renamed on conflict here
+    document.querySelector(".upload-conflict").classList.add("hidden");
+    Uploader.conflict = "rename";
+  }
+
+  if (DATA.auth) {
+    await setupAuth();
+  }
+
+  $emptyFolder.textContent = "This folder is upload-only, drop files here or use the upload button. Uploaded files are not listed.";
+  $emptyFolder.classList.remove("hidden");
+}
+
 /**
  * Render path table thead
  */
@@ -408,8 +582,10 @@ function renderPathsTableHead() {
       text: "Size",
     }
   ];
+  const selectAll = DATA.allow_archive ? `<th class="cell-select"><input type="checkbox" class="select-all" title="Select all"></th>` : "";
   $pathsTableHead.insertAdjacentHTML("beforeend", `
     <tr>
+      ${selectAll}
       ${headerItems.map(item => {
     let svg = `<svg width="12" height="12" viewBox="0 0 16 16"><path fill-rule="evenodd" d="M11.5 15a.5.5 0 0 0 .5-.5V2.707l3.146 3.147a.5.5 0 0 0 .708-.708l-4-4a.5.5 0 0 0-.708 0l-4 4a.5.5 0 1 0 .708.708L11 2.707V14.5a.5.5 0 0 0 .5.5zm-7-14a.5.5 0 0 1 .5.5v11.793l3.146-3.147a.5.5 0 0 1 .708.708l-4 4a.5.5 0 0 1-.708 0l-4-4a.5.5 0 0 1 .708-.708L4 13.293V1.5a.5.5 0 0 1 .5-.5z"/></svg>`;
     let order = "desc";
@@ -461,6 +637,8 @@ function addPath(file, index) {
   let actionMove = "";
   let actionEdit = "";
   let actionView = "";
+  let actionExtract = "";
+  let actionBrowse = "";
   let isDir = file.path_type.endsWith("Dir");
   if (isDir) {
     url += "/";
@@ -478,30 +656,41 @@ function addPath(file, index) {
   }
   if (DATA.allow_delete) {
     if (DATA.allow_upload) {
-      actionMove = `<div onclick="movePath(${index})" class="action-btn" id="moveBtn${index}" title="Move & Rename">${ICONS.move}</div>`;
+      actionMove = `<div class="action-btn" id="moveBtn${index}" title="Move & Rename">${ICONS.move}</div>`;
       if (!isDir) {
         actionEdit = `<a class="action-btn" title="Edit file" target="_blank" href="${url}?edit">${ICONS.edit}</a>`;
       }
     }
     actionDelete = `
-    <div onclick="deletePath(${index})" class="action-btn" id="deleteBtn${index}" title="Delete">${ICONS.delete}</div>`;
+    <div class="action-btn" id="deleteBtn${index}" title="Delete">${ICONS.delete}</div>`;
   }
-  if (!actionEdit && !isDir) {
+  if (DATA.allow_extract && !isDir && isArchive(file.name)) {
+    actionExtract = `<div class="action-btn" id="extractBtn${index}" title="Extract here">${ICONS.extract}</div>`;
+  }
+  // Archives are browsed like folders, but not the ones nested in another archive
+  if (!isDir && !DATA.in_archive && isArchive(file.name)) {
+    actionBrowse = `<a class="action-btn" title="Browse archive" href="${url}/!/">${ICONS.browse}</a>`;
+  }
+  if (!actionEdit && !isDir && !DATA.in_archive) {
     actionView = `<a class="action-btn" title="View file" target="_blank" href="${url}?view">${ICONS.view}</a>`;
   }
   let actionCell = `
   <td class="cell-actions">
     ${actionDownload}
     ${actionView}
+    ${actionBrowse}
+    ${actionExtract}
     ${actionMove}
     ${actionDelete}
     ${actionEdit}
   </td>`;
 
   let sizeDisplay = isDir ? formatDirSize(file.size) : formatFileSize(file.size).join(" ");
+  let selectCell = DATA.allow_archive ? `<td class="cell-select"><input type="checkbox" class="path-select" data-index="${index}"></td>` : "";
 
   $pathsTableBody.insertAdjacentHTML("beforeend", `
 <tr id="addPath${index}">
+  ${selectCell}
   <td class="path cell-icon">
     ${getPathSvg(file.path_type)}
   </td>
@@ -512,6 +701,9 @@ function addPath(file, index) {
   <td class="cell-size">${sizeDisplay}</td>
   ${actionCell}
 </tr>`);
+  document.getElementById(`moveBtn${index}`)?.addEventListener("click", () => movePath(index));
+  document.getElementById(`deleteBtn${index}`)?.addEventListener("click", () => deletePath(index));
+  document.getElementById(`extractBtn${index}`)?.addEventListener("click", () => extractPath(index));
 }
 
 function setupDropzone() {
@@ -581,6 +773,58 @@ function setupDownloadWithToken() {
   });
 }
 
+function setupDownloadSelected() {
+  const $downloadSelected = document.querySelector(".download-selected");
+  const $selectedCount = $downloadSelected.querySelector(".selected-count");
+  $pathsTable.addEventListener("change", e => {
+    if (e.target.classList.contains("select-all")) {
+      document.querySelectorAll(".path-select").forEach($checkbox => {
+        $checkbox.checked = e.target.checked;
+      });
+    }
+    const count = document.querySelectorAll(".path-select:checked").length;
+    $selectedCount.textContent = count;
+    $downloadSelected.classList.toggle("hidden", count === 0);
+  });
+  $downloadSelected.addEventListener("click", downloadSelected);
+}
+
+/**
+ * Download the checked paths as a single zip, the response is streamed by the browser
+ */
+async function downloadSelected() {
+  const names = Array.from(document.querySelectorAll(".path-select:checked"))
+    .map($checkbox => DATA.paths[parseInt($checkbox.dataset.index)].name);
+  if (names.length === 0) return;
+  const url = new URL(baseUrl());
+  url.searchParams.set("zip", "");
+  if (DATA.user) {
+    try {
+      const tokengenUrl = new URL(url);
+      tokengenUrl.searchParams.set("tokengen", "");
+      const res = await fetch(tokengenUrl);
+      if (!res.ok) throw new Error("Failed to fetch token");
+      url.searchParams.set("token", await res.text());
+    } catch (err) {
+      alert(`Failed to download, ${err.message}`);
+      return;
+    }
+  }
+  const $form = document.createElement("form");
+  $form.method = "POST";
+  $form.action = url.toString();
+  for (const name of names) {
+    const $input = document.createElement("input");
+    $input.type = "hidden";
+    $input.name = "path";
+    $input.value = name;
+    $form.appendChild($input);
+  }
+  document.body.appendChild($form);
+  $form.submit();
+  document.body.removeChild($form);
+}
+
 function setupSearch() {
   const $searchbar = document.querySelector(".searchbar");
   $searchbar.classList.remove("hidden");
@@ -601,6 +845,14 @@ function setupSearch() {
 
 function setupUploadFile() {
   document.querySelector(".upload-file").classList.remove("hidden");
+  document.querySelector(".upload-conflict").classList.remove("hidden");
+  const $conflict = document.getElementById("upload-conflict");
+  $conflict.value = localStorage.getItem("uploadConflict") || "overwrite";
+  Uploader.conflict = $conflict.value;
+  $conflict.addEventListener("change", () => {
+    Uploader.conflict = $conflict.value;
+    localStorage.setItem("uploadConflict", $conflict.value);
+  });
   document.getElementById("file").addEventListener("change", async e => {
     const files = e.target.files;
     for (let file of files) {
@@ -728,6 +980,35 @@ async function doDeletePath(name, url, cb) {
   }
 }
 
+/**
+ * Extract an archive into a sibling folder
+ * @param {number} index
+ */
+async function extractPath(index) {
+  const file = DATA.paths[index];
+  if (!file) return;
+  try {
+    await checkAuth();
+    const res = await fetch(newUrl(file.name) + "?extract", {
+      method: "POST",
+      headers: {
+        "X-Upload-Conflict": Uploader.conflict,
+      },
+    });
+    await assertResOK(res);
+    location.href = res.headers.get("location") || location.href;
+  } catch (err) {
+    alert(`Cannot extract \`${file.name}\`, ${err.message}`);
+  }
+}
+
+/**
+ * @param {string} name
+ */
+function isArchive(name) {
+  return /\.(zip|tar|tar\.gz|tgz|tar\.zst|tzst)$/i.test(name);
+}
+
 /**
  * Move path
  * @param {number} index
@@ -0,0 +1,176 @@
+//! Download throughput of a running server, `cargo bench --bench download`.
+//!
+//! Serves a 256MB file and reports the rate of a single download, of a range in the middle of
+//! the file and of 16 downloads at once. Each is measured against dufs and against a baseline
+//! server that streams the file with `ReaderStream`, the way dufs sent files before downloads
+//! over plain HTTP went through `sendfile`.
+
+use assert_fs::fixture::TempDir;
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::io::SeekFrom;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+const SIZE: usize = 256 * 1024 * 1024;
+const ROUNDS: usize = 3;
+const BUF_SIZE: usize = 65536;
+
+fn main() {
+    let tmpdir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..SIZE).map(|v| (v % 251) as u8).collect();
+    let path = tmpdir.path().join("data.bin");
+    std::fs::write(&path, data).unwrap();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dufs"))
+        .arg(tmpdir.path())
+        .arg("-b")
+        .arg("127.0.0.1")
+        .arg("-p")
+        .arg(port.to_string())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let servers = [
+        ("stream", spawn_stream_server(path)),
+        ("dufs", format!("http://127.0.0.1:{port}/data.bin")),
+    ];
+    for (_, url) in &servers {
+        wait_for_server(url);
+    }
+
+    for round in 0..ROUNDS {
+        for (server, url) in &servers {
+            let elapsed = measure(|| download(url, None));
+            report(round, server, "whole file", SIZE, elapsed);
+
+            let range = (SIZE / 4, SIZE / 4 * 3);
+            let elapsed = measure(|| download(url, Some(range)));
+            report(round, server, "range", range.1 - range.0, elapsed);
+
+            let elapsed = measure(|| {
+                let handles: Vec<_> = (0..16)
+                    .map(|_| {
+                        let url = url.clone();
+                        std::thread::spawn(move || download(&url, None))
+                    })
+                    .collect();
+                for handle in handles {
+                    handle.join().unwrap();
+                }
+            });
+            report(round, server, "16 at once", SIZE * 16, elapsed);
+        }
+    }
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+/// Serve `path` the way dufs did with `ReaderStream`, returns its URL.
+fn spawn_stream_server(path: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/data.bin", listener.local_addr().unwrap());
+    listener.set_nonblocking(true).unwrap();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let path = path.clone();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(move |req| {
+                        let path = path.clone();
+                        async move { send_file(&path, req).await }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+    });
+    url
+}
+
+async fn send_file(
+    path: &Path,
+    req: Request<Incoming>,
+) -> std::io::Result<Response<BoxBody<Bytes, std::io::Error>>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let range = req
+        .headers()
+        .get("range")
+        .and_then(|v| v.to_str().ok()?.strip_prefix("bytes=")?.split_once('-'))
+        .and_then(|(start, end)| Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?)));
+    let mut res = Response::builder();
+    let len = match range {
+        Some((start, end)) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            res = res
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("content-range", format!("bytes {start}-{end}/{size}"));
+            end - start + 1
+        }
+        None => size,
+    };
+    let stream = ReaderStream::with_capacity(file.take(len), BUF_SIZE);
+    let body = StreamBody::new(stream.map_ok(Frame::data)).boxed();
+    Ok(res.header("content-length", len).body(body).unwrap())
+}
+
+fn download(url: &str, range: Option<(usize, usize)>) {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .unwrap();
+    let mut req = client.get(url);
+    if let Some((start, end)) = range {
+        req = req.header("Range", format!("bytes={start}-{}", end - 1));
+    }
+    let mut resp = req.send().unwrap();
+    assert!(resp.status().is_success());
+    let size = std::io::copy(&mut resp, &mut std::io::sink()).unwrap();
+    let expected = range.map(|(start, end)| end - start).unwrap_or(SIZE);
+    assert_eq!(size as usize, expected);
+}
+
+fn measure(f: impl FnOnce()) -> Duration {
+    let now = Instant::now();
+    f();
+    now.elapsed()
+}
+
+fn report(round: usize, server: &str, name: &str, bytes: usize, elapsed: Duration) {
+    println!(
+        "round {round}: {server:<6} {name:<10} {:>6.0} MB/s",
+        bytes as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn wait_for_server(url: &str) {
+    for _ in 0..100 {
+        if reqwest::blocking::Client::new().head(url).send().is_ok() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    panic!("server didn't start");
+}
@@ -0,0 +1,391 @@
+//! Read-only browsing of zip and tar(.gz/.zst) files. Their entries are listed like the files of
+//! a folder and a single entry is streamed out without extracting the archive.
+//!
+//! Entries stored as-is, uncompressed zip entries and files of a plain tar, are read straight
+//! from the archive file so ranges of them can be served.
+//!
+//! Listing an archive is bounded by the extract limits and the listing is kept until the archive
+//! changes, so browsing a folder of it or opening its entries doesn't read it all again.
+
+use crate::extract::{is_zip_symlink, open_tar, ArchiveFormat, ExtractLimits, Rejected};
+use crate::hash_cache::Stamp;
+
+use anyhow::{bail, Result};
+use async_zip::tokio::read::fs::ZipFileReader;
+use async_zip::Compression;
+use futures_util::TryStreamExt;
+use hyper::StatusCode;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+const ZIP_LOCAL_HEADER_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const ZIP_LOCAL_HEADER_SIZE: u64 = 30;
+
+/// Listings kept per site, the least recently used is dropped beyond
+const CACHED_LISTINGS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// `/`-joined path inside the archive, empty for the root
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Milliseconds since the epoch
+    pub mtime: u64,
+    source: Source,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    /// A directory only implied by the paths of other entries
+    Implied,
+    Zip {
+        index: usize,
+        /// Offset of the local header of an uncompressed entry
+        stored_header: Option<u64>,
+    },
+    Tar {
+        index: usize,
+        /// Offset of the content of a file in a plain tar
+        raw_offset: Option<u64>,
+    },
+}
+
+impl ArchiveEntry {
+    fn dir(name: &str, mtime: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            is_dir: true,
+            size: 0,
+            mtime,
+            source: Source::Implied,
+        }
+    }
+
+    /// Base name of the entry
+    pub fn base_name(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Whether the content can be read from any offset, so ranges can be served.
+    pub fn is_seekable(&self) -> bool {
+        matches!(
+            self.source,
+            Source::Zip {
+                stored_header: Some(_),
+                ..
+            } | Source::Tar {
+                raw_offset: Some(_),
+                ..
+            }
+        )
+    }
+}
+
+/// Listings of the archives browsed lately.
+pub struct ListingCache {
+    limits: ExtractLimits,
+    listings: Mutex<HashMap<PathBuf, CachedListing>>,
+}
+
+struct CachedListing {
+    stamp: Stamp,
+    listing: Arc<ArchiveListing>,
+    used: Instant,
+}
+
+impl ListingCache {
+    pub fn new(limits: ExtractLimits) -> Self {
+        Self {
+            limits,
+            listings: Default::default(),
+        }
+    }
+
+    /// The listing of `archive`, read again only if its size, modification time or inode
+    /// changed.
+    pub async fn get(&self, archive: &Path, format: ArchiveFormat) -> Result<Arc<ArchiveListing>> {
+        let meta = fs::metadata(archive).await?;
+        let stamp = Stamp::new(&meta);
+        if let Some(cached) = self.listings.lock().unwrap().get_mut(archive) {
+            if cached.stamp == stamp && cached.listing.format == format {
+                cached.used = Instant::now();
+                return Ok(cached.listing.clone());
+            }
+        }
+        let listing = Arc::new(ArchiveListing::read(archive, format, &meta, self.limits).await?);
+        // An archive written to while being read has no listing worth keeping
+        let unchanged = fs::metadata(archive)
+            .await
+            .is_ok_and(|v| Stamp::new(&v) == stamp);
+        if unchanged {
+            let mut listings = self.listings.lock().unwrap();
+            if listings.len() >= CACHED_LISTINGS && !listings.contains_key(archive) {
+                let oldest = listings
+                    .iter()
+                    .min_by_key(|(_, v)| v.used)
+                    .map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    listings.remove(&oldest);
+                }
+            }
+            listings.insert(
+                archive.to_path_buf(),
+                CachedListing {
+                    stamp,
+                    listing: listing.clone(),
+                    used: Instant::now(),
+                },
+            );
+        }
+        Ok(listing)
+    }
+}
+
+/// The entries of an archive, keyed by their normalized path.
+pub struct ArchiveListing {
+    path: PathBuf,
+    format: ArchiveFormat,
+    entries: BTreeMap<String, ArchiveEntry>,
+    /// The central directory, kept to open compressed entries
+    zip: Option<ZipFileReader>,
+}
+
+impl ArchiveListing {
+    async fn read(
+        archive: &Path,
+        format: ArchiveFormat,
+        meta: &Metadata,
+        limits: ExtractLimits,
+    ) -> Result<Self> {
+        let mtime = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_millis() as u64)
+            .unwrap_or_default();
+        let mut listing = Self {
+            path: archive.to_path_buf(),
+            format,
+            entries: BTreeMap::from([(String::new(), ArchiveEntry::dir("", mtime))]),
+            zip: None,
+        };
+        match format {
+            ArchiveFormat::Zip => listing.read_zip(mtime, limits).await?,
+            _ => listing.read_tar(mtime, limits).await?,
+        }
+        Ok(listing)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ArchiveEntry> {
+        self.entries.get(name)
+    }
+
+    /// Entries directly inside the directory `dir`
+    pub fn children<'a>(&'a self, dir: &'a str) -> impl Iterator<Item = &'a ArchiveEntry> {
+        self.entries.values().filter(move |v| {
+            let parent = v.name.rsplit_once('/').map(|(v, _)| v).unwrap_or_default();
+            !v.name.is_empty() && parent == dir
+        })
+    }
+
+    /// Open the content of the file `entry`, skipping the first `start` bytes.
+    ///
+    /// Only seekable entries can start anywhere but at the beginning.
+    pub async fn open(
+        &self,
+        entry: &ArchiveEntry,
+        start: u64,
+    ) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+        if start > 0 && !entry.is_seekable() {
+            bail!("Entry `{}` can only be read from the start", entry.name);
+        }
+        match entry.source {
+            Source::Zip {
+                stored_header: Some(header_offset),
+                ..
+            } => {
+                let mut file = fs::File::open(&self.path).await?;
+                file.seek(SeekFrom::Start(header_offset)).await?;
+                let mut header = [0; ZIP_LOCAL_HEADER_SIZE as usize];
+                file.read_exact(&mut header).await?;
+                if header[..4] != ZIP_LOCAL_HEADER_SIGNATURE {
+                    bail!("Invalid local header of `{}`", entry.name);
+                }
+                let name_len = u16::from_le_bytes([header[26], header[27]]) as u64;
+                let extra_len = u16::from_le_bytes([header[28], header[29]]) as u64;
+                let offset = header_offset + ZIP_LOCAL_HEADER_SIZE + name_len + extra_len;
+                file.seek(SeekFrom::Start(offset + start)).await?;
+                Ok(Box::new(file.take(entry.size - start)))
+            }
+            Source::Zip { index, .. } => {
+                let Some(reader) = &self.zip else {
+                    bail!("Entry `{}` is missing", entry.name);
+                };
+                Ok(Box::new(reader.reader_without_entry(index).await?.compat()))
+            }
+            Source::Tar {
+                raw_offset: Some(offset),
+                ..
+            } => {
+                let mut file = fs::File::open(&self.path).await?;
+                file.seek(SeekFrom::Start(offset + start)).await?;
+                Ok(Box::new(file.take(entry.size - start)))
+            }
+            Source::Tar { index, .. } => {
+                let mut tar = tokio_tar::Archive::new(open_tar(&self.path, self.format).await?);
+                let mut entries = tar.entries()?;
+                let mut position = 0;
+                while let Some(v) = entries.try_next().await? {
+                    if position == index {
+                        return Ok(Box::new(v));
+                    }
+                    position += 1;
+                }
+                bail!("Entry `{}` is missing", entry.name)
+            }
+            Source::Implied => bail!("`{}` is a directory", entry.name),
+        }
+    }
+
+    async fn read_zip(&mut self, mtime: u64, limits: ExtractLimits) -> Result<()> {
+        let reader = ZipFileReader::new(&self.path).await?;
+        if reader.file().entries().len() as u64 > limits.max_entries {
+            bail!(too_many_entries(limits));
+        }
+        for (index, entry) in reader.file().entries().iter().enumerate() {
+            // Links could point anywhere, they are left out like when extracting
+            if is_zip_symlink(entry) {
+                continue;
+            }
+            let name = String::from_utf8_lossy(entry.filename().as_bytes()).to_string();
+            let Some(name) = normalize_name(&name).filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            let is_dir = entry.dir()?;
+            let stored = !is_dir && entry.compression() == Compression::Stored;
+            let entry_mtime = entry
+                .last_modification_date()
+                .as_chrono()
+                .single()
+                .map(|v| v.timestamp_millis().max(0) as u64)
+                .unwrap_or(mtime);
+            self.insert(
+                ArchiveEntry {
+                    name,
+                    is_dir,
+                    size: if is_dir { 0 } else { entry.uncompressed_size() },
+                    mtime: entry_mtime,
+                    source: Source::Zip {
+                        index,
+                        stored_header: stored.then(|| entry.header_offset()),
+                    },
+                },
+                mtime,
+            );
+        }
+        self.zip = Some(reader);
+        Ok(())
+    }
+
+    async fn read_tar(&mut self, mtime: u64, limits: ExtractLimits) -> Result<()> {
+        let format = self.format;
+        let mut tar = tokio_tar::Archive::new(open_tar(&self.path, format).await?);
+        let mut entries = tar.entries()?;
+        let mut position = 0;
+        // Listing a compressed tar decompresses the content of all its entries
+        let mut scanned = 0u64;
+        while let Some(entry) = entries.try_next().await? {
+            let index = position;
+            position += 1;
+            if position as u64 > limits.max_entries {
+                bail!(too_many_entries(limits));
+            }
+            if format != ArchiveFormat::Tar {
+                scanned = scanned.saturating_add(entry.header().size()?);
+                if scanned > limits.max_size {
+                    bail!(Rejected::new(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "Archive expands too large, the limit is {} bytes",
+                            limits.max_size
+                        ),
+                    ));
+                }
+            }
+            let entry_type = entry.header().entry_type();
+            if !entry_type.is_dir() && !entry_type.is_file() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(&entry.path_bytes()?).to_string();
+            let Some(name) = normalize_name(&name).filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            let is_dir = entry_type.is_dir();
+            let raw_offset =
+                (format == ArchiveFormat::Tar && !is_dir).then(|| entry.raw_file_position());
+            self.insert(
+                ArchiveEntry {
+                    name,
+                    is_dir,
+                    size: if is_dir { 0 } else { entry.header().size()? },
+                    mtime: entry
+                        .header()
+                        .mtime()
+                        .map(|v| v.saturating_mul(1000))
+                        .unwrap_or(mtime),
+                    source: Source::Tar { index, raw_offset },
+                },
+                mtime,
+            );
+        }
+        Ok(())
+    }
+
+    /// Add an entry and the directories above it that the archive doesn't list itself.
+    fn insert(&mut self, entry: ArchiveEntry, mtime: u64) {
+        let mut end = 0;
+        while let Some(i) = entry.name[end..].find('/') {
+            let parent = &entry.name[..end + i];
+            self.entries
+                .entry(parent.to_string())
+                .or_insert_with(|| ArchiveEntry::dir(parent, mtime));
+            end += i + 1;
+        }
+        // Like when extracting, a later entry with the same path wins
+        self.entries.insert(entry.name.clone(), entry);
+    }
+}
+
+/// Turn a path inside an archive into its `/`-joined form, `None` if it escapes the root.
+pub fn normalize_name(name: &str) -> Option<String> {
+    let mut parts = vec![];
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn too_many_entries(limits: ExtractLimits) -> Rejected {
+    Rejected::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "Archive has too many entries, the limit is {}",
+            limits.max_entries
+        ),
+    )
+}
@@ -0,0 +1,190 @@
+//! The pool compressed archives are built on, shared by all sites.
+//!
+//! Archives are built on a runtime of their own so compression never stalls the workers serving
+//! requests. At most `--archive-jobs` archives are compressed at the same time: a job holds one
+//! of the slots while it produces output and gives it back whenever its client stops reading.
+//! Jobs waiting for a slot are capped, beyond that new archives are refused.
+
+use anyhow::Result;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::runtime::Runtime;
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+/// Archives that may wait for their first slot, per slot
+const QUEUED_JOBS_PER_SLOT: usize = 8;
+
+pub struct ArchivePool {
+    runtime: Option<Runtime>,
+    slots: Arc<Semaphore>,
+    /// Jobs that haven't got their first slot yet
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+}
+
+impl ArchivePool {
+    pub fn new(jobs: usize) -> Result<Self> {
+        let jobs = jobs.max(1);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(jobs)
+            .max_blocking_threads(jobs)
+            .thread_name("dufs-archive")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            runtime: Some(runtime),
+            slots: Arc::new(Semaphore::new(jobs)),
+            queued: Default::default(),
+            max_queued: jobs * QUEUED_JOBS_PER_SLOT,
+        })
+    }
+
+    /// Run `job` once a slot is free, it writes the archive to the `SlotWriter` around `writer`.
+    /// Returns false if too many jobs are waiting already.
+    pub fn spawn<W, F, Fut>(&self, writer: W, job: F) -> bool
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+        F: FnOnce(SlotWriter<W>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let Some(runtime) = &self.runtime else {
+            return false;
+        };
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        let slots = self.slots.clone();
+        let queued = self.queued.clone();
+        runtime.spawn(async move {
+            let permit = slots.clone().acquire_owned().await;
+            queued.fetch_sub(1, Ordering::SeqCst);
+            let Ok(permit) = permit else {
+                return;
+            };
+            job(SlotWriter {
+                inner: writer,
+                slots,
+                permit: Some(permit),
+                acquire: None,
+            })
+            .await;
+        });
+        true
+    }
+}
+
+impl Drop for ArchivePool {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+type Acquire = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// Holds a slot of the pool while writing, gives it back while `inner` is full.
+pub struct SlotWriter<W> {
+    inner: W,
+    slots: Arc<Semaphore>,
+    permit: Option<OwnedSemaphorePermit>,
+    acquire: Option<Acquire>,
+}
+
+impl<W: AsyncWrite + Unpin> SlotWriter<W> {
+    fn poll_slot(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.permit.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        let acquire = self
+            .acquire
+            .get_or_insert_with(|| Box::pin(self.slots.clone().acquire_owned()));
+        let permit = ready!(acquire.as_mut().poll(cx));
+        self.acquire = None;
+        self.permit = Some(permit.map_err(io::Error::other)?);
+        Poll::Ready(Ok(()))
+    }
+
+    fn release_if_pending<T>(&mut self, poll: Poll<T>) -> Poll<T> {
+        if poll.is_pending() {
+            self.permit = None;
+        }
+        poll
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for SlotWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_slot(cx))?;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        this.release_if_pending(poll)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        this.release_if_pending(poll)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_shutdown(cx);
+        this.release_if_pending(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_queue_limit() {
+        let pool = ArchivePool::new(1).unwrap();
+        let (tx, rx) = oneshot::channel::<()>();
+        // Holds the only slot until told to stop
+        assert!(pool.spawn(tokio::io::sink(), |writer| async move {
+            let _ = rx.await;
+            drop(writer);
+        }));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        for _ in 0..QUEUED_JOBS_PER_SLOT {
+            assert!(pool.spawn(tokio::io::sink(), |_writer| async {}));
+        }
+        assert!(!pool.spawn(tokio::io::sink(), |_writer| async {}));
+        tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(pool.spawn(tokio::io::sink(), |_writer| async {}));
+    }
+
+    #[tokio::test]
+    async fn test_slot_released_while_blocked() {
+        let pool = ArchivePool::new(1).unwrap();
+        // A client that never reads
+        let (writer, _reader) = tokio::io::duplex(16);
+        assert!(pool.spawn(writer, |mut writer| async move {
+            let _ = writer.write_all(&[0; 1024]).await;
+        }));
+        let (writer, mut reader) = tokio::io::duplex(16);
+        assert!(pool.spawn(writer, |mut writer| async move {
+            writer.write_all(b"done").await.unwrap();
+        }));
+        let mut output = vec![];
+        let ret = tokio::time::timeout(Duration::from_secs(5), reader.read_to_end(&mut output));
+        ret.await.unwrap().unwrap();
+        assert_eq!(output, b"done");
+    }
+}
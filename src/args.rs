@@ -1,9 +1,10 @@
 use anyhow::{bail, Context, Result};
+use async_compression::Level;
 use async_zip::Compression;
 use clap::builder::{PossibleValue, PossibleValuesParser};
 use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueEnum};
 use clap_complete::{generate, Generator, Shell};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use smart_default::SmartDefault;
 use std::env;
 use std::net::IpAddr;
@@ -11,7 +12,7 @@ use std::path::{Path, PathBuf};
 
 use crate::auth::AccessControl;
 use crate::http_logger::HttpLogger;
-use crate::utils::encode_uri;
+use crate::utils::{encode_uri, glob, parse_size};
 
 pub fn build_cli() -> Command {
     let app = Command::new(env!("CARGO_CRATE_NAME"))
@@ -148,6 +149,14 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Allow download folders as archive file"),
         )
+        .arg(
+            Arg::new("allow-extract")
+                .env("DUFS_ALLOW_EXTRACT")
+                .hide_env(true)
+                .long("allow-extract")
+                .action(ArgAction::SetTrue)
+                .help("Allow ?extract query to unpack zip/tar archives on the server"),
+        )
         .arg(
             Arg::new("allow-hash")
                 .env("DUFS_ALLOW_HASH")
@@ -162,7 +171,7 @@ pub fn build_cli() -> Command {
 				.hide_env(true)
                 .long("enable-cors")
                 .action(ArgAction::SetTrue)
-                .help("Enable CORS, sets `Access-Control-Allow-Origin: *`"),
+                .help("Enable CORS, allows requests from any origin"),
         )
         .arg(
             Arg::new("render-index")
@@ -197,6 +206,110 @@ pub fn build_cli() -> Command {
                 .value_parser(value_parser!(PathBuf))
                 .value_name("path")
         )
+        .arg(
+            Arg::new("upload-fsync")
+                .env("DUFS_UPLOAD_FSYNC")
+                .hide_env(true)
+                .long("upload-fsync")
+                .action(ArgAction::SetTrue)
+                .help("Flush uploaded files to disk before moving them into place"),
+        )
+        .arg(
+            Arg::new("upload-max-size")
+                .env("DUFS_UPLOAD_MAX_SIZE")
+                .hide_env(true)
+                .long("upload-max-size")
+                .value_name("size")
+                .help("Reject uploaded files larger than the size, e.g. 100M"),
+        )
+        .arg(
+            Arg::new("upload-allow")
+                .env("DUFS_UPLOAD_ALLOW")
+                .hide_env(true)
+                .long("upload-allow")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("types")
+                .help("Only allow uploading these extensions or MIME types, e.g. jpg,png,video/*"),
+        )
+        .arg(
+            Arg::new("upload-deny")
+                .env("DUFS_UPLOAD_DENY")
+                .hide_env(true)
+                .long("upload-deny")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .value_name("types")
+                .help("Forbid uploading these extensions or MIME types, e.g. exe,text/html"),
+        )
+        .arg(
+            Arg::new("upload-conflict")
+                .env("DUFS_UPLOAD_CONFLICT")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<ConflictPolicy>::new())
+                .long("upload-conflict")
+                .value_name("policy")
+                .help("What to do when an upload already exists [default: overwrite]"),
+        )
+        .arg(
+            Arg::new("extract-max-entries")
+                .env("DUFS_EXTRACT_MAX_ENTRIES")
+                .hide_env(true)
+                .long("extract-max-entries")
+                .value_name("num")
+                .value_parser(value_parser!(u64))
+                .help("Refuse to extract archives with more entries [default: 10000]"),
+        )
+        .arg(
+            Arg::new("extract-max-size")
+                .env("DUFS_EXTRACT_MAX_SIZE")
+                .hide_env(true)
+                .long("extract-max-size")
+                .value_name("size")
+                .help("Refuse to extract archives that expand larger [default: 1G]"),
+        )
+        .arg(
+            Arg::new("csp")
+                .env("DUFS_CSP")
+                .hide_env(true)
+                .long("csp")
+                .value_name("policy")
+                .help("Set the Content-Security-Policy of the web UI, `{nonce}` is replaced by a per-response nonce"),
+        )
+        .arg(
+            Arg::new("frame-options")
+                .env("DUFS_FRAME_OPTIONS")
+                .hide_env(true)
+                .long("frame-options")
+                .value_name("value")
+                .help("Set the X-Frame-Options header, empty to disable [default: SAMEORIGIN]"),
+        )
+        .arg(
+            Arg::new("referrer-policy")
+                .env("DUFS_REFERRER_POLICY")
+                .hide_env(true)
+                .long("referrer-policy")
+                .value_name("value")
+                .help("Set the Referrer-Policy header, empty to disable [default: same-origin]"),
+        )
+        .arg(
+            Arg::new("hsts")
+                .env("DUFS_HSTS")
+                .hide_env(true)
+                .long("hsts")
+                .value_name("seconds")
+                .value_parser(value_parser!(u64))
+                .help("Send Strict-Transport-Security with the given max-age"),
+        )
+        .arg(
+            Arg::new("active-content")
+                .env("DUFS_ACTIVE_CONTENT")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<ActiveContent>::new())
+                .long("active-content")
+                .value_name("mode")
+                .help("How to serve HTML, SVG and XML files [default: inline]"),
+        )
         .arg(
             Arg::new("log-format")
                 .env("DUFS_LOG_FORMAT")
@@ -214,6 +327,41 @@ pub fn build_cli() -> Command {
                 .value_parser(value_parser!(PathBuf))
                 .help("Specify the file to save logs to, other than stdout/stderr"),
         )
+        .arg(
+            Arg::new("audit-log")
+                .env("DUFS_AUDIT_LOG")
+                .hide_env(true)
+                .long("audit-log")
+                .value_name("file")
+                .value_parser(value_parser!(PathBuf))
+                .help("Record uploads, deletes, moves and auth failures to a JSON lines file"),
+        )
+        .arg(
+            Arg::new("audit-log-max-size")
+                .env("DUFS_AUDIT_LOG_MAX_SIZE")
+                .hide_env(true)
+                .long("audit-log-max-size")
+                .value_name("size")
+                .help("Rotate the audit log when it exceeds the size, e.g. 10M"),
+        )
+        .arg(
+            Arg::new("audit-log-max-files")
+                .env("DUFS_AUDIT_LOG_MAX_FILES")
+                .hide_env(true)
+                .long("audit-log-max-files")
+                .value_name("num")
+                .value_parser(value_parser!(usize))
+                .help("Number of rotated audit logs to keep [default: 5]"),
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .env("DUFS_CACHE_DIR")
+                .hide_env(true)
+                .long("cache-dir")
+                .value_name("dir")
+                .value_parser(value_parser!(PathBuf))
+                .help("Keep file hashes in this dir [default: $XDG_CACHE_HOME/dufs]"),
+        )
         .arg(
             Arg::new("compress")
                 .env("DUFS_COMPRESS")
@@ -223,6 +371,57 @@ pub fn build_cli() -> Command {
                 .value_name("level")
                 .help("Set zip compress level [default: low]")
         )
+        .arg(
+            Arg::new("archive-jobs")
+                .env("DUFS_ARCHIVE_JOBS")
+                .hide_env(true)
+                .long("archive-jobs")
+                .value_name("num")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Maximum number of archive downloads built at the same time [default: 4]"),
+        )
+        .arg(
+            Arg::new("http-compress")
+                .env("DUFS_HTTP_COMPRESS")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<Compress>::new())
+                .long("http-compress")
+                .value_name("level")
+                .help("Set compress level of text responses sent with Content-Encoding [default: low]")
+        )
+        .arg(
+            Arg::new("etag")
+                .env("DUFS_ETAG")
+                .hide_env(true)
+                .value_parser(clap::builder::EnumValueParser::<EtagMode>::new())
+                .long("etag")
+                .value_name("mode")
+                .help("Build file ETags from the modified time and size or from a hash of the content [default: mtime]"),
+        )
+        .arg(
+            Arg::new("rate-limit")
+                .env("DUFS_RATE_LIMIT")
+                .hide_env(true)
+                .long("rate-limit")
+                .value_name("size")
+                .help("Limit the bandwidth of all transfers together to bytes per second, e.g. 100M"),
+        )
+        .arg(
+            Arg::new("rate-limit-user")
+                .env("DUFS_RATE_LIMIT_USER")
+                .hide_env(true)
+                .long("rate-limit-user")
+                .value_name("size")
+                .help("Limit the bandwidth of each user to bytes per second, e.g. 10M"),
+        )
+        .arg(
+            Arg::new("rate-limit-conn")
+                .env("DUFS_RATE_LIMIT_CONN")
+                .hide_env(true)
+                .long("rate-limit-conn")
+                .value_name("size")
+                .help("Limit the bandwidth of each connection to bytes per second, e.g. 1M"),
+        )
         .arg(
             Arg::new("completions")
                 .long("completions")
@@ -250,6 +449,14 @@ pub fn build_cli() -> Command {
                 .value_name("path")
                 .value_parser(value_parser!(PathBuf))
                 .help("Path to the SSL/TLS certificate's private key"),
+        )
+        .arg(
+            Arg::new("tls-self-signed")
+                .env("DUFS_TLS_SELF_SIGNED")
+                .hide_env(true)
+                .long("tls-self-signed")
+                .action(ArgAction::SetTrue)
+                .help("Serve HTTPS with a generated self-signed certificate"),
         );
 
     app
@@ -290,19 +497,64 @@ pub struct Args {
     pub allow_symlink: bool,
     pub allow_archive: bool,
     pub allow_hash: bool,
+    pub allow_extract: bool,
     pub render_index: bool,
     pub render_spa: bool,
     pub render_try_index: bool,
     pub enable_cors: bool,
+    pub cors: Option<Cors>,
+    #[serde(default = "default_csp")]
+    #[default(default_csp())]
+    pub csp: String,
+    #[serde(default = "default_frame_options")]
+    #[default(default_frame_options())]
+    pub frame_options: String,
+    #[serde(default = "default_referrer_policy")]
+    #[default(default_referrer_policy())]
+    pub referrer_policy: String,
+    pub hsts: Option<u64>,
+    pub active_content: ActiveContent,
     pub assets: Option<PathBuf>,
+    pub upload_fsync: bool,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub upload_max_size: Option<u64>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub upload_allow: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub upload_deny: Vec<String>,
+    pub upload_conflict: ConflictPolicy,
+    pub upload_limits: Vec<UploadLimit>,
+    pub extract_max_entries: Option<u64>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub extract_max_size: Option<u64>,
     pub error_page: Option<PathBuf>,
     #[serde(deserialize_with = "deserialize_log_http")]
     #[serde(rename = "log-format")]
     pub http_logger: HttpLogger,
     pub log_file: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub audit_log_max_size: Option<u64>,
+    #[serde(default = "default_audit_log_max_files")]
+    #[default(default_audit_log_max_files())]
+    pub audit_log_max_files: usize,
+    pub cache_dir: Option<PathBuf>,
     pub compress: Compress,
+    #[serde(default = "default_archive_jobs")]
+    #[default(default_archive_jobs())]
+    pub archive_jobs: usize,
+    pub http_compress: Compress,
+    pub etag: EtagMode,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub rate_limit: Option<u64>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub rate_limit_user: Option<u64>,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub rate_limit_conn: Option<u64>,
     pub tls_cert: Option<PathBuf>,
     pub tls_key: Option<PathBuf>,
+    pub tls_self_signed: bool,
+    pub sites: Vec<Site>,
 }
 
 impl Args {
@@ -341,26 +593,20 @@ impl Args {
         }
         args.path_prefix = args.path_prefix.trim_matches('/').to_string();
 
-        args.uri_prefix = if args.path_prefix.is_empty() {
-            "/".to_owned()
-        } else {
-            format!("/{}/", &encode_uri(&args.path_prefix))
-        };
+        args.uri_prefix = Self::to_uri_prefix(&args.path_prefix);
 
         if let Some(hidden) = matches.get_many::<String>("hidden") {
             args.hidden = hidden.cloned().collect();
         } else {
-            let mut hidden = vec![];
-            std::mem::swap(&mut args.hidden, &mut hidden);
-            args.hidden = hidden
-                .into_iter()
-                .flat_map(|v| v.split(',').map(|v| v.to_string()).collect::<Vec<String>>())
-                .collect();
+            args.hidden = Self::split_hidden(std::mem::take(&mut args.hidden));
         }
 
         if !args.enable_cors {
             args.enable_cors = matches.get_flag("enable-cors");
         }
+        if args.enable_cors && args.cors.is_none() {
+            args.cors = Some(Cors::default());
+        }
 
         if let Some(rules) = matches.get_many::<String>("auth") {
             let rules: Vec<_> = rules.map(|v| v.as_str()).collect();
@@ -391,6 +637,9 @@ impl Args {
         if !args.allow_archive {
             args.allow_archive = allow_all || matches.get_flag("allow-archive");
         }
+        if !args.allow_extract {
+            args.allow_extract = allow_all || matches.get_flag("allow-extract");
+        }
         if !args.render_index {
             args.render_index = matches.get_flag("render-index");
         }
@@ -407,15 +656,54 @@ impl Args {
             args.assets = Some(assets_path.clone());
         }
 
-        if let Some(assets_path) = &args.assets {
-            args.assets = Some(Args::sanitize_assets_path(assets_path)?);
+        args.init_assets()?;
+
+        if !args.upload_fsync {
+            args.upload_fsync = matches.get_flag("upload-fsync");
         }
 
-        if let Some(assets_path) = &args.assets {
-            let p = assets_path.join("404.html");
-            if p.exists() {
-                args.error_page = Some(p);
-            }
+        if let Some(max_size) = matches.get_one::<String>("upload-max-size") {
+            args.upload_max_size = Some(parse_size(max_size)?);
+        }
+
+        if let Some(max_entries) = matches.get_one::<u64>("extract-max-entries") {
+            args.extract_max_entries = Some(*max_entries);
+        }
+
+        if let Some(max_size) = matches.get_one::<String>("extract-max-size") {
+            args.extract_max_size = Some(parse_size(max_size)?);
+        }
+
+        if let Some(types) = matches.get_many::<String>("upload-allow") {
+            args.upload_allow = types.cloned().collect();
+        }
+
+        if let Some(types) = matches.get_many::<String>("upload-deny") {
+            args.upload_deny = types.cloned().collect();
+        }
+
+        if let Some(policy) = matches.get_one::<ConflictPolicy>("upload-conflict") {
+            args.upload_conflict = *policy;
+        }
+
+        if let Some(csp) = matches.get_one::<String>("csp") {
+            args.csp = csp.clone();
+        }
+
+        if let Some(frame_options) = matches.get_one::<String>("frame-options") {
+            args.frame_options = frame_options.clone();
+        }
+
+        if let Some(referrer_policy) = matches.get_one::<String>("referrer-policy") {
+            args.referrer_policy = referrer_policy.clone();
+        }
+
+        if let Some(hsts) = matches.get_one::<u64>("hsts") {
+            args.hsts = Some(*hsts);
+        }
+
+        if let Some(active_content) = matches.get_one::<ActiveContent>("active-content") {
+            args.active_content = *active_content;
         }
 
         if let Some(log_format) = matches.get_one::<String>("log-format") {
@@ -426,10 +714,50 @@ impl Args {
             args.log_file = Some(log_file.clone());
         }
 
+        if let Some(audit_log) = matches.get_one::<PathBuf>("audit-log") {
+            args.audit_log = Some(audit_log.clone());
+        }
+
+        if let Some(max_size) = matches.get_one::<String>("audit-log-max-size") {
+            args.audit_log_max_size = Some(parse_size(max_size)?);
+        }
+
+        if let Some(max_files) = matches.get_one::<usize>("audit-log-max-files") {
+            args.audit_log_max_files = *max_files;
+        }
+
+        if let Some(cache_dir) = matches.get_one::<PathBuf>("cache-dir") {
+            args.cache_dir = Some(cache_dir.clone());
+        }
+
         if let Some(compress) = matches.get_one::<Compress>("compress") {
             args.compress = *compress;
         }
 
+        if let Some(archive_jobs) = matches.get_one::<u64>("archive-jobs") {
+            args.archive_jobs = *archive_jobs as usize;
+        }
+
+        if let Some(http_compress) = matches.get_one::<Compress>("http-compress") {
+            args.http_compress = *http_compress;
+        }
+
+        if let Some(etag) = matches.get_one::<EtagMode>("etag") {
+            args.etag = *etag;
+        }
+
+        if let Some(rate) = matches.get_one::<String>("rate-limit") {
+            args.rate_limit = Some(parse_size(rate)?);
+        }
+
+        if let Some(rate) = matches.get_one::<String>("rate-limit-user") {
+            args.rate_limit_user = Some(parse_size(rate)?);
+        }
+
+        if let Some(rate) = matches.get_one::<String>("rate-limit-conn") {
+            args.rate_limit_conn = Some(parse_size(rate)?);
+        }
+
         #[cfg(feature = "tls")]
         {
             if let Some(tls_cert) = matches.get_one::<PathBuf>("tls-cert") {
@@ -440,6 +768,10 @@ impl Args {
                 args.tls_key = Some(tls_key.clone())
             }
 
+            if !args.tls_self_signed {
+                args.tls_self_signed = matches.get_flag("tls-self-signed");
+            }
+
             match (&args.tls_cert, &args.tls_key) {
                 (Some(_), Some(_)) => {}
                 (Some(_), _) => bail!("No tls-key set"),
@@ -451,11 +783,47 @@ impl Args {
         {
             args.tls_cert = None;
             args.tls_key = None;
+            args.tls_self_signed = false;
+        }
+
+        let mut sites = std::mem::take(&mut args.sites);
+        for site in sites.iter_mut() {
+            site.init(&args)?;
         }
+        args.sites = sites;
 
         Ok(args)
     }
 
+    fn to_uri_prefix(path_prefix: &str) -> String {
+        if path_prefix.is_empty() {
+            "/".to_owned()
+        } else {
+            format!("/{}/", &encode_uri(path_prefix))
+        }
+    }
+
+    fn split_hidden(hidden: Vec<String>) -> Vec<String> {
+        hidden
+            .into_iter()
+            .flat_map(|v| v.split(',').map(|v| v.to_string()).collect::<Vec<String>>())
+            .collect()
+    }
+
+    fn init_assets(&mut self) -> Result<()> {
+        if let Some(assets_path) = &self.assets {
+            self.assets = Some(Args::sanitize_assets_path(assets_path)?);
+        }
+
+        if let Some(assets_path) = &self.assets {
+            let p = assets_path.join("404.html");
+            if p.exists() {
+                self.error_page = Some(p);
+            }
+        }
+        Ok(())
+    }
+
     fn sanitize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
         let path = path.as_ref();
         if !path.exists() {
@@ -479,6 +847,82 @@ impl Args {
     }
 }
 
+/// A virtual host served by the same process, selected by the `Host` header or the TLS SNI name.
+///
+/// Listening and logging options (`bind`, `port`, `log-format`, `log-file`, `audit-log*`) and
+/// `cache-dir` are global, everything else is configured per site.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Site {
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    pub args: Args,
+}
+
+impl Site {
+    fn init(&mut self, parent: &Args) -> Result<()> {
+        self.hosts = self
+            .hosts
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect();
+        if self.hosts.is_empty() {
+            bail!("Invalid site, no hosts set");
+        }
+        let name = self.hosts.join(",");
+        let args = &mut self.args;
+        if !args.sites.is_empty() {
+            bail!("Invalid site `{name}`, sites cannot be nested");
+        }
+        args.serve_path = Args::sanitize_path(&args.serve_path)?;
+        args.path_is_file = args.serve_path.metadata()?.is_file();
+        args.path_prefix = args.path_prefix.trim_matches('/').to_string();
+        args.uri_prefix = Args::to_uri_prefix(&args.path_prefix);
+        args.hidden = Args::split_hidden(std::mem::take(&mut args.hidden));
+        if args.allow_all {
+            args.allow_upload = true;
+            args.allow_delete = true;
+            args.allow_search = true;
+            args.allow_symlink = true;
+            args.allow_hash = true;
+            args.allow_archive = true;
+            args.allow_extract = true;
+        }
+        args.init_assets()?;
+        if args.enable_cors && args.cors.is_none() {
+            args.cors = Some(Cors::default());
+        }
+        args.addrs.clone_from(&parent.addrs);
+        args.port = parent.port;
+        args.http_logger = parent.http_logger.clone();
+        args.log_file.clone_from(&parent.log_file);
+        args.audit_log.clone_from(&parent.audit_log);
+        args.audit_log_max_size = parent.audit_log_max_size;
+        args.audit_log_max_files = parent.audit_log_max_files;
+        args.cache_dir.clone_from(&parent.cache_dir);
+        args.archive_jobs = parent.archive_jobs;
+        args.rate_limit = parent.rate_limit;
+        args.rate_limit_user = parent.rate_limit_user;
+        args.rate_limit_conn = parent.rate_limit_conn;
+        args.tls_self_signed = false;
+        #[cfg(feature = "tls")]
+        match (&args.tls_cert, &args.tls_key) {
+            (Some(_), None) => bail!("Invalid site `{name}`, no tls-key set"),
+            (None, Some(_)) => bail!("Invalid site `{name}`, no tls-cert set"),
+            _ => {}
+        }
+        #[cfg(not(feature = "tls"))]
+        {
+            args.tls_cert = None;
+            args.tls_key = None;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum BindAddr {
     IpAddr(IpAddr),
@@ -546,6 +990,241 @@ impl Compress {
             Compress::High => Compression::Xz,
         }
     }
+
+    /// Level of the gzip or zstd encoding of responses
+    pub fn to_level(self) -> Level {
+        match self {
+            Compress::None | Compress::Low => Level::Fastest,
+            Compress::Medium => Level::Default,
+            Compress::High => Level::Best,
+        }
+    }
+}
+
+/// Cross-origin resource sharing settings, enabled by `--enable-cors` or a `cors` config block.
+#[derive(Debug, Deserialize, SmartDefault, PartialEq)]
+#[serde(default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Cors {
+    /// Allowed origins, exact or glob patterns such as `https://*.example.com`, `*` allows all
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    #[default(vec!["*".to_string()])]
+    pub allow_origins: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    #[default(CORS_ALLOW_METHODS.iter().map(|v| v.to_string()).collect())]
+    pub allow_methods: Vec<String>,
+    /// Allowed request headers, the preflight's `Access-Control-Request-Headers` is echoed when empty
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub allow_headers: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    #[default(CORS_EXPOSE_HEADERS.iter().map(|v| v.to_string()).collect())]
+    pub expose_headers: Vec<String>,
+    /// Send credentials to the origins listed explicitly, never to those only matched by `*`
+    pub allow_credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+const CORS_ALLOW_METHODS: [&str; 13] = [
+    "GET",
+    "HEAD",
+    "POST",
+    "PUT",
+    "PATCH",
+    "DELETE",
+    "OPTIONS",
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "COPY",
+    "MOVE",
+    "CHECKAUTH",
+];
+
+const CORS_EXPOSE_HEADERS: [&str; 11] = [
+    "Authorization",
+    "Content-Disposition",
+    "Content-Length",
+    "Content-Range",
+    "Accept-Ranges",
+    "ETag",
+    "Last-Modified",
+    "WWW-Authenticate",
+    "X-Upload-Name",
+    "X-Upload-Received",
+    "Location",
+];
+
+impl Cors {
+    /// Whether `origin` is listed exactly or by a pattern, `*` is not counted.
+    pub fn list_origin(&self, origin: &str) -> bool {
+        self.allow_origins
+            .iter()
+            .filter(|v| *v != "*")
+            .any(|v| v.eq_ignore_ascii_case(origin) || glob(v, origin))
+    }
+
+    pub fn allow_any_origin(&self) -> bool {
+        self.allow_origins.iter().any(|v| v == "*")
+    }
+}
+
+/// Upload settings of the files under `path`, unset fields fall back to the global ones.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct UploadLimit {
+    pub path: String,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_size: Option<u64>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub allow: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub deny: Vec<String>,
+    pub conflict: Option<ConflictPolicy>,
+}
+
+impl UploadLimit {
+    /// Whether a file with this name and content type may be uploaded.
+    ///
+    /// Entries containing a `/` are MIME types like `image/*`, the others are extensions.
+    pub fn allow_type(&self, name: &str, mime: &str) -> bool {
+        let is_match = |pattern: &String| {
+            if pattern.contains('/') {
+                match pattern.strip_suffix("/*") {
+                    Some(prefix) => mime
+                        .split_once('/')
+                        .is_some_and(|(v, _)| v.eq_ignore_ascii_case(prefix)),
+                    None => mime.eq_ignore_ascii_case(pattern),
+                }
+            } else {
+                let ext = pattern.trim_start_matches('*').trim_start_matches('.');
+                name.rsplit_once('.')
+                    .is_some_and(|(_, v)| v.eq_ignore_ascii_case(ext))
+            }
+        };
+        if self.deny.iter().any(is_match) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(is_match)
+    }
+}
+
+impl Args {
+    /// Upload restrictions of a path, merging the most specific `upload-limits` entry into the global ones.
+    pub fn upload_limit(&self, path: &str) -> UploadLimit {
+        let mut limit = UploadLimit {
+            path: String::new(),
+            max_size: self.upload_max_size,
+            allow: self.upload_allow.clone(),
+            deny: self.upload_deny.clone(),
+            conflict: Some(self.upload_conflict),
+        };
+        let path = path.trim_matches('/');
+        let matched = self
+            .upload_limits
+            .iter()
+            .map(|v| (v.path.trim_matches('/'), v))
+            .filter(|(prefix, _)| {
+                prefix.is_empty() || path == *prefix || path.starts_with(&format!("{prefix}/"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len());
+        if let Some((prefix, matched)) = matched {
+            limit.path = prefix.to_string();
+            if matched.max_size.is_some() {
+                limit.max_size = matched.max_size;
+            }
+            if !matched.allow.is_empty() {
+                limit.allow.clone_from(&matched.allow);
+            }
+            if !matched.deny.is_empty() {
+                limit.deny.clone_from(&matched.deny);
+            }
+            if matched.conflict.is_some() {
+                limit.conflict = matched.conflict;
+            }
+        }
+        limit
+    }
+}
+
+/// What an upload does when its destination already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Replace the existing file, requires `--allow-delete`
+    #[default]
+    Overwrite,
+    /// Reject the upload with `409 Conflict`
+    Fail,
+    /// Store the upload under a free name like `report (2).pdf`
+    Rename,
+    /// Move the existing file to a free name and store the upload under the requested one
+    KeepBoth,
+}
+
+impl ValueEnum for ConflictPolicy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Overwrite, Self::Fail, Self::Rename, Self::KeepBoth]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            ConflictPolicy::Overwrite => PossibleValue::new("overwrite"),
+            ConflictPolicy::Fail => PossibleValue::new("fail"),
+            ConflictPolicy::Rename => PossibleValue::new("rename"),
+            ConflictPolicy::KeepBoth => PossibleValue::new("keep-both"),
+        })
+    }
+}
+
+/// How files whose content can run scripts (HTML, SVG, XML) are served.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ActiveContent {
+    /// Render them in the browser like any other file
+    #[default]
+    Inline,
+    /// Always download them
+    Attachment,
+    /// Render them with `Content-Security-Policy: sandbox`, which blocks scripts
+    Sandbox,
+}
+
+impl ValueEnum for ActiveContent {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Inline, Self::Attachment, Self::Sandbox]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            ActiveContent::Inline => PossibleValue::new("inline"),
+            ActiveContent::Attachment => PossibleValue::new("attachment"),
+            ActiveContent::Sandbox => PossibleValue::new("sandbox"),
+        })
+    }
+}
+
+/// What the ETag of a file is built from.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    /// The modified time and size, free to compute
+    #[default]
+    Mtime,
+    /// The SHA-256 of the content, computed once per change of the file
+    Content,
+}
+
+impl ValueEnum for EtagMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Mtime, Self::Content]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            EtagMode::Mtime => PossibleValue::new("mtime"),
+            EtagMode::Content => PossibleValue::new("content"),
+        })
+    }
 }
 
 fn deserialize_bind_addrs<'de, D>(deserializer: D) -> Result<Vec<BindAddr>, D::Error>
@@ -572,8 +1251,9 @@ where
         where
             S: serde::de::SeqAccess<'de>,
         {
-            let addrs: Vec<&'de str> =
+            let addrs: Vec<String> =
                 Deserialize::deserialize(serde::de::value::SeqAccessDeserializer::new(seq))?;
+            let addrs: Vec<&str> = addrs.iter().map(|v| v.as_str()).collect();
             BindAddr::parse_addrs(&addrs).map_err(serde::de::Error::custom)
         }
     }
@@ -616,10 +1296,28 @@ fn deserialize_access_control<'de, D>(deserializer: D) -> Result<AccessControl,
 where
     D: Deserializer<'de>,
 {
-    let rules: Vec<&str> = Vec::deserialize(deserializer)?;
+    let rules: Vec<String> = Vec::deserialize(deserializer)?;
+    let rules: Vec<&str> = rules.iter().map(|v| v.as_str()).collect();
     AccessControl::new(&rules).map_err(serde::de::Error::custom)
 }
 
+fn deserialize_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(v)) => Ok(Some(v)),
+        Some(Size::Text(v)) => parse_size(&v).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
 fn deserialize_log_http<'de, D>(deserializer: D) -> Result<HttpLogger, D::Error>
 where
     D: Deserializer<'de>,
@@ -632,6 +1330,28 @@ fn default_serve_path() -> PathBuf {
     PathBuf::from(".")
 }
 
+fn default_csp() -> String {
+    "default-src 'self'; script-src 'self' 'nonce-{nonce}'; style-src 'self' 'unsafe-inline'; \
+     img-src 'self' data:; object-src 'none'; base-uri 'self'; frame-ancestors 'self'"
+        .to_string()
+}
+
+fn default_frame_options() -> String {
+    "SAMEORIGIN".to_string()
+}
+
+fn default_referrer_policy() -> String {
+    "same-origin".to_string()
+}
+
+fn default_audit_log_max_files() -> usize {
+    5
+}
+
+fn default_archive_jobs() -> usize {
+    4
+}
+
 fn default_addrs() -> Vec<BindAddr> {
     BindAddr::parse_addrs(&["0.0.0.0", "::"]).unwrap()
 }
@@ -674,6 +1394,35 @@ mod tests {
         assert_eq!(args.hidden, ["tmp", "*.log", "*.lock"]);
     }
 
+    #[test]
+    fn test_upload_limit() {
+        let args = Args {
+            upload_max_size: Some(10),
+            upload_deny: vec!["exe".into()],
+            upload_limits: vec![
+                UploadLimit {
+                    path: "/dir1/".into(),
+                    max_size: Some(100),
+                    ..Default::default()
+                },
+                UploadLimit {
+                    path: "dir1/sub".into(),
+                    allow: vec!["image/*".into()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(args.upload_limit("dir2/a.txt").max_size, Some(10));
+        assert_eq!(args.upload_limit("dir10/a.txt").max_size, Some(10));
+        assert_eq!(args.upload_limit("dir1/a.txt").max_size, Some(100));
+        let limit = args.upload_limit("dir1/sub/a.png");
+        assert_eq!(limit.max_size, Some(10));
+        assert!(limit.allow_type("a.png", "image/png"));
+        assert!(!limit.allow_type("a.txt", "text/plain"));
+        assert!(!limit.allow_type("a.EXE", "image/png"));
+    }
+
     #[test]
     fn test_args_from_cli2() {
         let cli = build_cli();
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use chrono::{Local, SecondsFormat};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// JSON lines log of mutating operations, separate from the http access log.
+struct AuditLog {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_files: usize,
+    file: Mutex<(File, u64)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    pub time: String,
+    pub action: &'static str,
+    /// The user that authenticated, never taken from the request alone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// The user a failed login claimed to be
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claimed_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    pub status: u16,
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    pub fn new(action: &'static str, path: String) -> Self {
+        Self {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            action,
+            user: None,
+            claimed_user: None,
+            ip: None,
+            path,
+            dest: None,
+            bytes: None,
+            status: 0,
+            result: "failure",
+            error: None,
+        }
+    }
+}
+
+pub fn init(path: Option<PathBuf>, max_size: Option<u64>, max_files: usize) -> Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let (file, size) = open(&path)
+        .with_context(|| format!("Failed to open the audit log at '{}'", path.display()))?;
+    let _ = AUDIT_LOG.set(AuditLog {
+        path,
+        max_size,
+        max_files,
+        file: Mutex::new((file, size)),
+    });
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    AUDIT_LOG.get().is_some()
+}
+
+pub fn log(record: &AuditRecord) {
+    let Some(audit_log) = AUDIT_LOG.get() else {
+        return;
+    };
+    let Ok(mut line) = serde_json::to_string(record) else {
+        return;
+    };
+    line.push('\n');
+    if let Err(err) = audit_log.write(line.as_bytes()) {
+        error!("Failed to write the audit log, {err}");
+    }
+}
+
+impl AuditLog {
+    fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        let Ok(mut guard) = self.file.lock() else {
+            return Ok(());
+        };
+        let (file, size) = &mut *guard;
+        if let Some(max_size) = self.max_size {
+            if *size > 0 && *size + data.len() as u64 > max_size {
+                self.rotate()?;
+                (*file, *size) = open(&self.path)?;
+            }
+        }
+        file.write_all(data)?;
+        *size += data.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `audit.log` to `audit.log.1`, `audit.log.1` to `audit.log.2` and so on,
+    /// dropping the files beyond `max_files`.
+    fn rotate(&self) -> std::io::Result<()> {
+        let rotated = |i: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{i}"));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        let _ = std::fs::remove_file(rotated(self.max_files));
+        for i in (1..self.max_files).rev() {
+            let _ = std::fs::rename(rotated(i), rotated(i + 1));
+        }
+        std::fs::rename(&self.path, rotated(1))
+    }
+}
+
+fn open(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
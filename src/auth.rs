@@ -237,6 +237,7 @@ impl AccessPaths {
                 None => (item, AccessPerm::ReadOnly),
                 Some((path, "ro")) => (path, AccessPerm::ReadOnly),
                 Some((path, "rw")) => (path, AccessPerm::ReadWrite),
+                Some((path, "wo")) => (path, AccessPerm::WriteOnly),
                 _ => return None,
             };
             self.add(path, perm);
@@ -246,7 +247,11 @@ impl AccessPaths {
 
     pub fn guard(&self, path: &str, method: &Method) -> Option<Self> {
         let target = self.find(path)?;
-        if !is_readonly_method(method) && !target.perm().readwrite() {
+        let perm = target.perm();
+        let allowed = is_readonly_method(method)
+            || perm.readwrite()
+            || (perm.writeonly() && is_upload_method(method));
+        if !allowed {
             return None;
         }
         Some(target)
@@ -321,11 +326,11 @@ impl AccessPaths {
             perm
         };
         if parts.is_empty() {
-            if perm.indexonly() {
-                return Some(self.clone());
-            } else {
-                return Some(AccessPaths::new(perm));
-            }
+            // Children are kept so that walks of a readable dir can skip write-only ones
+            return Some(AccessPaths {
+                perm,
+                children: self.children.clone(),
+            });
         }
         let child = match self.children.get(parts[0]) {
             Some(v) => v,
@@ -358,8 +363,26 @@ impl AccessPaths {
             let base = base.join(name);
             if child.perm().indexonly() {
                 child.entry_paths_impl(output, &base);
-            } else {
+            } else if !child.perm().writeonly() {
+                output.push(base)
+            }
+        }
+    }
+
+    /// Write-only dirs below `base`, their content must not be listed, searched or zipped.
+    pub fn write_only_paths(&self, base: &Path) -> Vec<PathBuf> {
+        let mut output = vec![];
+        self.write_only_paths_impl(&mut output, base);
+        output
+    }
+
+    fn write_only_paths_impl(&self, output: &mut Vec<PathBuf>, base: &Path) {
+        for (name, child) in self.children.iter() {
+            let base = base.join(name);
+            if child.perm().writeonly() {
                 output.push(base)
+            } else {
+                child.write_only_paths_impl(output, &base);
             }
         }
     }
@@ -369,6 +392,8 @@ impl AccessPaths {
 pub enum AccessPerm {
     #[default]
     IndexOnly,
+    /// Upload new files without seeing what is there, such as a drop box
+    WriteOnly,
     ReadOnly,
     ReadWrite,
 }
@@ -378,6 +403,10 @@ impl AccessPerm {
         self == &AccessPerm::IndexOnly
     }
 
+    pub fn writeonly(&self) -> bool {
+        self == &AccessPerm::WriteOnly
+    }
+
     pub fn readwrite(&self) -> bool {
         self == &AccessPerm::ReadWrite
     }
@@ -557,6 +586,13 @@ fn is_readonly_method(method: &Method) -> bool {
         || method.as_str() == "LOGOUT"
 }
 
+fn is_upload_method(method: &Method) -> bool {
+    method == Method::PUT
+        || method == Method::POST
+        || method == Method::PATCH
+        || method.as_str() == "MKCOL"
+}
+
 fn strip_prefix<'a>(search: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
     let l = prefix.len();
     if search.len() < l {
@@ -750,4 +786,22 @@ mod tests {
             Some(AccessPaths::new(AccessPerm::ReadWrite))
         );
     }
+
+    #[test]
+    fn test_write_only_paths() {
+        let mut paths = AccessPaths::default();
+        paths.merge("/:ro,/dir1/drop:wo,/dir2:wo").unwrap();
+        assert_eq!(
+            paths
+                .find("dir1")
+                .map(|v| v.write_only_paths(Path::new("/tmp/dir1"))),
+            Some(vec![PathBuf::from("/tmp/dir1/drop")])
+        );
+        let drop = paths.find("dir2/sub").unwrap();
+        assert!(drop.guard("", &Method::PUT).is_some());
+        assert!(drop.guard("", &Method::DELETE).is_none());
+        assert!(drop
+            .guard("", &Method::from_bytes(b"MOVE").unwrap())
+            .is_none());
+    }
 }
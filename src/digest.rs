@@ -0,0 +1,187 @@
+//! Integrity checks of upload bodies with `Content-Digest`, `Repr-Digest` (RFC 9530)
+//! and the legacy `Content-MD5` header.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hyper::header::{HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+    Md5,
+}
+
+impl DigestAlgorithm {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sha-256" => Some(Self::Sha256),
+            "sha-512" => Some(Self::Sha512),
+            "md5" => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha-256",
+            Self::Sha512 => "sha-512",
+            Self::Md5 => "md5",
+        }
+    }
+
+    fn hasher(&self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+            Self::Md5 => Hasher::Md5(md5::Context::new()),
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Md5(md5::Context),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+            Self::Md5(ctx) => ctx.consume(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Sha512(hasher) => hasher.finalize().to_vec(),
+            Self::Md5(ctx) => ctx.finalize().0.to_vec(),
+        }
+    }
+}
+
+/// Digests computed over an upload body while it is written.
+pub struct UploadDigests {
+    entries: Vec<(DigestAlgorithm, Hasher, Vec<Vec<u8>>)>,
+}
+
+pub struct DigestOutcome {
+    pub matched: bool,
+    /// Value of the `Content-Digest` response header
+    pub content_digest: String,
+}
+
+impl UploadDigests {
+    /// Collect the expected digests from the request headers, or `None` if the client sent none.
+    ///
+    /// `Repr-Digest` describes the whole file so it's only checked when the body is the whole file.
+    pub fn from_headers(
+        headers: &HeaderMap<HeaderValue>,
+        whole_file: bool,
+    ) -> Result<Option<Self>> {
+        let mut digests = Self { entries: vec![] };
+        let mut names = vec!["content-digest"];
+        let mut want_names = vec!["want-content-digest"];
+        if whole_file {
+            names.push("repr-digest");
+            want_names.push("want-repr-digest");
+        }
+        for name in names {
+            for value in headers.get_all(name) {
+                let value = value
+                    .to_str()
+                    .map_err(|_| anyhow!("Invalid {name} header"))?;
+                for (algorithm, value) in parse_dictionary(value) {
+                    let Some(algorithm) = DigestAlgorithm::parse(&algorithm) else {
+                        continue;
+                    };
+                    let expected = value
+                        .strip_prefix(':')
+                        .and_then(|v| v.strip_suffix(':'))
+                        .and_then(|v| STANDARD.decode(v).ok())
+                        .ok_or_else(|| anyhow!("Invalid {name} header"))?;
+                    digests.add(algorithm, Some(expected));
+                }
+            }
+        }
+        if let Some(value) = headers.get("content-md5") {
+            let expected = value
+                .to_str()
+                .ok()
+                .and_then(|v| STANDARD.decode(v.trim()).ok())
+                .ok_or_else(|| anyhow!("Invalid content-md5 header"))?;
+            digests.add(DigestAlgorithm::Md5, Some(expected));
+        }
+        for name in want_names {
+            for value in headers.get_all(name) {
+                for (algorithm, preference) in parse_dictionary(value.to_str().unwrap_or_default())
+                {
+                    if let Some(algorithm) = DigestAlgorithm::parse(&algorithm) {
+                        if preference != "0" {
+                            digests.add(algorithm, None);
+                        }
+                    }
+                }
+            }
+        }
+        if digests.entries.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(digests))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, hasher, _) in self.entries.iter_mut() {
+            hasher.update(data);
+        }
+    }
+
+    pub fn finish(self) -> DigestOutcome {
+        let mut matched = true;
+        let mut content_digest = vec![];
+        for (algorithm, hasher, expected) in self.entries {
+            let output = hasher.finalize();
+            if expected.iter().any(|v| *v != output) {
+                matched = false;
+            }
+            content_digest.push(format!(
+                "{}=:{}:",
+                algorithm.name(),
+                STANDARD.encode(output)
+            ));
+        }
+        DigestOutcome {
+            matched,
+            content_digest: content_digest.join(", "),
+        }
+    }
+
+    fn add(&mut self, algorithm: DigestAlgorithm, expected: Option<Vec<u8>>) {
+        let index = match self.entries.iter().position(|(v, _, _)| *v == algorithm) {
+            Some(index) => index,
+            None => {
+                self.entries.push((algorithm, algorithm.hasher(), vec![]));
+                self.entries.len() - 1
+            }
+        };
+        if let Some(expected) = expected {
+            self.entries[index].2.push(expected);
+        }
+    }
+}
+
+/// Parse the members of a structured field dictionary, dropping their parameters.
+fn parse_dictionary(value: &str) -> Vec<(String, &str)> {
+    value
+        .split(',')
+        .filter_map(|member| {
+            let member = member.split(';').next()?.trim();
+            let (key, value) = member.split_once('=')?;
+            Some((key.trim().to_ascii_lowercase(), value.trim()))
+        })
+        .collect()
+}
@@ -0,0 +1,329 @@
+//! Server-side extraction of zip and tar(.gz/.zst) archives. Entries are streamed to disk one
+//! by one while the number of entries and the expanded size are counted against the limits.
+
+use anyhow::Result;
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use async_zip::tokio::read::fs::ZipFileReader;
+use async_zip::ZipEntry;
+use futures_util::TryStreamExt;
+use hyper::StatusCode;
+use std::fmt;
+use std::path::Path;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFLNK: u16 = 0o120000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Detect the format from an archive name, returning it with the name of the directory to
+    /// extract into.
+    pub fn from_name(name: &str) -> Option<(Self, &str)> {
+        let lower = name.to_ascii_lowercase();
+        let formats = [
+            (".zip", Self::Zip),
+            (".tar", Self::Tar),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZst),
+            (".tzst", Self::TarZst),
+        ];
+        formats.into_iter().find_map(|(ext, format)| {
+            let stem = &name[..lower.strip_suffix(ext)?.len()];
+            (!stem.is_empty()).then_some((format, stem))
+        })
+    }
+
+    /// Parse a format name such as `zip`, `tgz` or `tar.zst`.
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "tar" => Some(Self::Tar),
+            "tgz" | "tar.gz" => Some(Self::TarGz),
+            "tzst" | "tar.zst" => Some(Self::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+            Self::TarZst => "application/zstd",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractLimits {
+    pub max_entries: u64,
+    pub max_size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Extracted {
+    pub entries: u64,
+    pub size: u64,
+}
+
+/// An archive that is refused, reported to the client with `status`.
+#[derive(Debug)]
+pub struct Rejected {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl Rejected {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn invalid(err: impl fmt::Display) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, format!("Invalid archive, {err}"))
+    }
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Extract `archive` into the existing empty directory `dest`.
+///
+/// `check` is called with the `/`-joined path of every file and directory before it's
+/// created and returns the maximum size of a file there, if any.
+pub async fn extract<F>(
+    archive: &Path,
+    format: ArchiveFormat,
+    dest: &Path,
+    limits: ExtractLimits,
+    check: F,
+) -> Result<Extracted>
+where
+    F: Fn(&str) -> Result<Option<u64>, Rejected>,
+{
+    let mut extractor = Extractor {
+        dest,
+        limits,
+        check,
+        extracted: Extracted::default(),
+    };
+    match format {
+        ArchiveFormat::Zip => extractor.extract_zip(archive).await?,
+        _ => {
+            extractor
+                .extract_tar(open_tar(archive, format).await?)
+                .await?
+        }
+    }
+    Ok(extractor.extracted)
+}
+
+/// Open a tar archive, decompressing it according to `format`.
+pub async fn open_tar(
+    archive: &Path,
+    format: ArchiveFormat,
+) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let file = BufReader::new(fs::File::open(archive).await?);
+    let reader: Box<dyn AsyncRead + Unpin + Send> = match format {
+        ArchiveFormat::TarGz => {
+            let mut decoder = GzipDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::new(decoder)
+        }
+        ArchiveFormat::TarZst => Box::new(ZstdDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(reader)
+}
+
+/// Whether the unix permissions of a zip entry mark it as a symbolic link.
+pub fn is_zip_symlink(entry: &ZipEntry) -> bool {
+    entry
+        .unix_permissions()
+        .is_some_and(|v| v & S_IFMT == S_IFLNK)
+}
+
+struct Extractor<'a, F> {
+    dest: &'a Path,
+    limits: ExtractLimits,
+    check: F,
+    extracted: Extracted,
+}
+
+impl<F> Extractor<'_, F>
+where
+    F: Fn(&str) -> Result<Option<u64>, Rejected>,
+{
+    async fn extract_zip(&mut self, archive: &Path) -> Result<()> {
+        let reader = ZipFileReader::new(archive)
+            .await
+            .map_err(Rejected::invalid)?;
+        let entries = reader.file().entries();
+        if entries.len() as u64 > self.limits.max_entries {
+            return Err(self.too_many_entries().into());
+        }
+        // Declared sizes can lie, the written bytes are counted as well
+        let declared_size = entries
+            .iter()
+            .fold(0u64, |acc, v| acc.saturating_add(v.uncompressed_size()));
+        if declared_size > self.limits.max_size {
+            return Err(self.too_large().into());
+        }
+        for (index, entry) in entries.iter().enumerate() {
+            let name = String::from_utf8_lossy(entry.filename().as_bytes()).to_string();
+            let is_dir = name.ends_with('/');
+            let is_symlink = is_zip_symlink(entry);
+            let Some(relative_path) = self.begin_entry(&name)? else {
+                continue;
+            };
+            if is_symlink {
+                continue;
+            }
+            let max_size = (self.check)(&relative_path)?;
+            let path = self.dest.join(&relative_path);
+            if is_dir {
+                fs::create_dir_all(&path).await?;
+                continue;
+            }
+            let entry_reader = reader
+                .reader_without_entry(index)
+                .await
+                .map_err(Rejected::invalid)?;
+            self.write_file(&path, entry_reader.compat(), max_size)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn extract_tar(&mut self, reader: Box<dyn AsyncRead + Unpin + Send>) -> Result<()> {
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries()?;
+        while let Some(entry) = entries.try_next().await.map_err(Rejected::invalid)? {
+            let name = entry.path_bytes().map_err(Rejected::invalid)?;
+            let name = String::from_utf8_lossy(&name).to_string();
+            let Some(relative_path) = self.begin_entry(&name)? else {
+                continue;
+            };
+            let entry_type = entry.header().entry_type();
+            // Links and special files are skipped, they could point outside of the destination
+            if !entry_type.is_dir() && !entry_type.is_file() {
+                continue;
+            }
+            let max_size = (self.check)(&relative_path)?;
+            let path = self.dest.join(&relative_path);
+            if entry_type.is_dir() {
+                fs::create_dir_all(&path).await?;
+            } else {
+                self.write_file(&path, entry, max_size).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Count an entry and turn its name into a safe relative path, `None` for the root itself.
+    fn begin_entry(&mut self, name: &str) -> Result<Option<String>, Rejected> {
+        self.extracted.entries += 1;
+        if self.extracted.entries > self.limits.max_entries {
+            return Err(self.too_many_entries());
+        }
+        let mut parts = vec![];
+        for part in name.split(['/', '\\']) {
+            match part {
+                "" | "." => {}
+                // Drive letters are only special on Windows
+                _ if part == ".." || (cfg!(windows) && part.contains(':')) => {
+                    return Err(Rejected::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid path `{name}` in archive"),
+                    ));
+                }
+                _ => parts.push(part),
+            }
+        }
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(parts.join("/")))
+    }
+
+    async fn write_file<R: AsyncRead + Unpin>(
+        &mut self,
+        path: &Path,
+        mut reader: R,
+        max_size: Option<u64>,
+    ) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let mut file = fs::File::create(path).await?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut written = 0u64;
+        loop {
+            let n = reader.read(&mut buf).await.map_err(Rejected::invalid)?;
+            if n == 0 {
+                break;
+            }
+            written += n as u64;
+            self.extracted.size += n as u64;
+            if self.extracted.size > self.limits.max_size {
+                return Err(self.too_large().into());
+            }
+            if let Some(max_size) = max_size.filter(|v| written > *v) {
+                return Err(Rejected::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("File too large, the upload limit is {max_size} bytes"),
+                )
+                .into());
+            }
+            file.write_all(&buf[..n]).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+
+    fn too_many_entries(&self) -> Rejected {
+        Rejected::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Archive has too many entries, the limit is {}",
+                self.limits.max_entries
+            ),
+        )
+    }
+
+    fn too_large(&self) -> Rejected {
+        Rejected::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Archive expands too large, the limit is {} bytes",
+                self.limits.max_size
+            ),
+        )
+    }
+}
@@ -0,0 +1,239 @@
+//! SHA-256 digests and CRC-32 checksums of served files, kept across restarts so a file is only
+//! read again once it changed. They back `?hash`, the content-based ETags of `--etag content` and
+//! the CRCs of uncompressed zip downloads.
+//!
+//! A digest is recorded with the size, modification time and inode of the file it was computed
+//! from, any difference makes it stale. The records live in `--cache-dir`, outside the serve
+//! path, in a directory only the user running dufs can access so no one else can plant them.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use uuid::Uuid;
+
+/// What a cached result was computed from, any change of the file makes it stale
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    size: u64,
+    /// Nanoseconds since the epoch
+    mtime: u128,
+    dev: u64,
+    ino: u64,
+}
+
+impl Stamp {
+    pub fn new(meta: &Metadata) -> Self {
+        #[cfg(unix)]
+        let (dev, ino) = {
+            use std::os::unix::fs::MetadataExt;
+            (meta.dev(), meta.ino())
+        };
+        #[cfg(not(unix))]
+        let (dev, ino) = (0, 0);
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+            .map(|v| v.as_nanos())
+            .unwrap_or_default();
+        Self {
+            size: meta.len(),
+            mtime,
+            dev,
+            ino,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    stamp: Stamp,
+    sha256: String,
+    /// Missing from records written before CRCs were kept
+    #[serde(default)]
+    crc32: Option<u32>,
+}
+
+pub struct HashCache {
+    /// `None` if there is no cache dir to keep the records in
+    dir: Option<PathBuf>,
+    /// Locks of the files being hashed, so a file is only read once at a time
+    locks: Mutex<HashMap<PathBuf, Weak<AsyncMutex<()>>>>,
+}
+
+impl HashCache {
+    /// Keep the records of `serve_path` in `cache_dir`, or the default cache dir if `None`.
+    pub fn new(serve_path: &Path, cache_dir: Option<&Path>) -> Result<Self> {
+        let cache_dir = match cache_dir {
+            Some(cache_dir) => {
+                create_private_dir(cache_dir)?;
+                Some(cache_dir.to_path_buf())
+            }
+            None => default_cache_dir().and_then(|v| match create_private_dir(&v) {
+                Ok(()) => Some(v),
+                Err(err) => {
+                    warn!("Hashes are not cached, {err}");
+                    None
+                }
+            }),
+        };
+        let hash = hex::encode(Sha256::digest(serve_path.to_string_lossy().as_bytes()));
+        Ok(Self {
+            dir: cache_dir.map(|v| v.join("hashes").join(&hash[0..16])),
+            locks: Default::default(),
+        })
+    }
+
+    /// Hex SHA-256 of the file at `path`, `meta` is its current metadata.
+    pub async fn sha256(&self, path: &Path, meta: &Metadata) -> Result<String> {
+        Ok(self.record(path, meta).await?.sha256)
+    }
+
+    /// CRC-32 of the file at `path` as zip archives store it, `meta` is its current metadata.
+    pub async fn crc32(&self, path: &Path, meta: &Metadata) -> Result<u32> {
+        let record = self.record(path, meta).await?;
+        Ok(record.crc32.unwrap_or_default())
+    }
+
+    async fn record(&self, path: &Path, meta: &Metadata) -> Result<Record> {
+        let stamp = Stamp::new(meta);
+        // Requests for a file being hashed wait for its record instead of reading it again
+        let _guard = self.lock(path).await;
+        let record_path = self.record_path(path);
+        if let Some(record_path) = &record_path {
+            if let Some(record) = fs::read(record_path)
+                .await
+                .ok()
+                .and_then(|v| serde_json::from_slice::<Record>(&v).ok())
+            {
+                if record.stamp == stamp && record.crc32.is_some() {
+                    return Ok(record);
+                }
+            }
+        }
+        let (sha256, crc32) = digest_file(path).await?;
+        let record = Record {
+            stamp,
+            sha256,
+            crc32: Some(crc32),
+        };
+        // A file written to while being read has no digest worth keeping
+        let unchanged = fs::metadata(path)
+            .await
+            .is_ok_and(|v| Stamp::new(&v) == record.stamp);
+        if let (true, Some(dir), Some(record_path)) = (unchanged, &self.dir, &record_path) {
+            if let Err(err) = save(dir, record_path, &record).await {
+                warn!("Failed to cache the hash of {}, {err}", path.display());
+            }
+        }
+        Ok(record)
+    }
+
+    fn record_path(&self, path: &Path) -> Option<PathBuf> {
+        let key = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{}.json", &key[0..32])))
+    }
+
+    async fn lock(&self, path: &Path) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            match locks.get(path).and_then(|v| v.upgrade()) {
+                Some(lock) => lock,
+                None => {
+                    locks.retain(|_, v| v.strong_count() > 0);
+                    let lock = Arc::new(AsyncMutex::new(()));
+                    locks.insert(path.to_path_buf(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
+
+async fn save(dir: &Path, record_path: &Path, record: &Record) -> Result<()> {
+    fs::create_dir_all(dir).await?;
+    let tmp_path = dir.join(format!("{}.tmp", Uuid::new_v4().simple()));
+    fs::write(&tmp_path, serde_json::to_vec(record)?).await?;
+    if let Err(err) = fs::rename(&tmp_path, record_path).await {
+        let _ = fs::remove_file(&tmp_path).await;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/dufs`, else `~/.cache/dufs`, or `%LOCALAPPDATA%\dufs` on Windows
+fn default_cache_dir() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|v| v.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|v| PathBuf::from(v).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(dir.join("dufs"))
+}
+
+/// Create `dir` accessible by its owner only, refusing one that belongs to another user.
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder
+        .create(dir)
+        .map_err(|err| anyhow!("Failed to create cache dir `{}`, {err}", dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let meta = std::fs::symlink_metadata(dir)?;
+        // SAFETY: geteuid has no preconditions and can't fail
+        let uid = unsafe { libc::geteuid() };
+        if !meta.is_dir() || meta.uid() != uid {
+            anyhow::bail!(
+                "Cache dir `{}` must be a directory owned by the current user",
+                dir.display()
+            );
+        }
+        if meta.mode() & 0o077 != 0 {
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    Ok(())
+}
+
+/// Hex SHA-256 and CRC-32 of the file at `path`, in one read.
+async fn digest_file(path: &Path) -> Result<(String, u32)> {
+    let mut hasher = Sha256::new();
+    let mut crc = crc32fast::Hasher::new();
+    hash_file(path, |data| {
+        hasher.update(data);
+        crc.update(data);
+    })
+    .await?;
+    Ok((hex::encode(hasher.finalize()), crc.finalize()))
+}
+
+/// Feed the content of the file at `path` to `update`, chunk by chunk.
+pub async fn hash_file(path: &Path, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut file = fs::File::open(path).await?;
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let bytes_read = file.read(&mut buffer).await?;
+        if bytes_read == 0 {
+            break;
+        }
+        update(&buffer[..bytes_read]);
+    }
+    Ok(())
+}
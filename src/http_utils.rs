@@ -1,13 +1,19 @@
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use async_compression::Level;
 use bytes::{Bytes, BytesMut};
-use futures_util::Stream;
-use http_body_util::{combinators::BoxBody, BodyExt, Full};
-use hyper::body::{Body, Incoming};
+use futures_util::{Stream, TryStreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Body, Frame, Incoming};
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 use tokio::io::AsyncRead;
-use tokio_util::io::poll_read_buf;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Content codings the server sends, most preferred first.
+pub const CONTENT_ENCODINGS: [&str; 3] = ["br", "zstd", "gzip"];
 
 #[derive(Debug)]
 pub struct IncomingStream {
@@ -37,69 +43,228 @@ impl Stream for IncomingStream {
     }
 }
 
-pin_project_lite::pin_project! {
-    pub struct LengthLimitedStream<R> {
-        #[pin]
-        reader: Option<R>,
-        remaining: usize,
-        buf: BytesMut,
-        capacity: usize,
+pub fn body_full(content: impl Into<hyper::body::Bytes>) -> BoxBody<Bytes, anyhow::Error> {
+    Full::new(content.into())
+        .map_err(anyhow::Error::new)
+        .boxed()
+}
+
+/// The supported content codings that an `Accept-Encoding` value allows, preferred first.
+pub fn accepted_encodings(accept_encoding: &str) -> Vec<&'static str> {
+    let mut weights: Vec<(&str, f32)> = vec![];
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|v| v.trim().strip_prefix("q="))
+            .and_then(|v| v.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        weights.push((coding, q));
     }
+    let weight = |coding: &str| {
+        weights
+            .iter()
+            .find(|(v, _)| v.eq_ignore_ascii_case(coding))
+            .or_else(|| weights.iter().find(|(v, _)| *v == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or_default()
+    };
+    let mut encodings: Vec<_> = CONTENT_ENCODINGS
+        .into_iter()
+        .map(|v| (v, weight(v)))
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    encodings.into_iter().map(|(v, _)| v).collect()
 }
 
-impl<R> LengthLimitedStream<R> {
-    pub fn new(reader: R, limit: usize) -> Self {
-        Self {
-            reader: Some(reader),
-            remaining: limit,
-            buf: BytesMut::new(),
-            capacity: 4096,
-        }
+/// Whether a response of this type shrinks when compressed, text and the like.
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/x-ndjson"
+                | "application/toml"
+                | "application/yaml"
+        )
+}
+
+/// Compress a response body on the fly with `gzip` or `zstd`.
+pub fn encode_body(
+    body: BoxBody<Bytes, anyhow::Error>,
+    encoding: &str,
+    level: Level,
+) -> BoxBody<Bytes, anyhow::Error> {
+    let reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    match encoding {
+        "br" => encoded_body(BrotliEncoder::with_quality(reader, brotli_level(level))),
+        "zstd" => encoded_body(ZstdEncoder::with_quality(reader, level)),
+        _ => encoded_body(GzipEncoder::with_quality(reader, level)),
     }
 }
 
-impl<R: AsyncRead> Stream for LengthLimitedStream<R> {
-    type Item = std::io::Result<Bytes>;
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut this = self.as_mut().project();
+/// Brotli's default quality is its best and far too slow to compress responses on the fly
+fn brotli_level(level: Level) -> Level {
+    match level {
+        Level::Fastest => Level::Precise(3),
+        Level::Best => Level::Best,
+        _ => Level::Precise(5),
+    }
+}
 
-        if *this.remaining == 0 {
-            self.project().reader.set(None);
-            return Poll::Ready(None);
-        }
+fn encoded_body<R>(reader: R) -> BoxBody<Bytes, anyhow::Error>
+where
+    R: AsyncRead + Send + Sync + 'static,
+{
+    StreamBody::new(
+        ReaderStream::new(reader)
+            .map_ok(Frame::data)
+            .map_err(anyhow::Error::from),
+    )
+    .boxed()
+}
 
-        let reader = match this.reader.as_pin_mut() {
-            Some(r) => r,
-            None => return Poll::Ready(None),
-        };
+/// Size of the chunks `file_body` reads at once
+pub const FILE_CHUNK_SIZE: usize = 512 * 1024;
 
-        if this.buf.capacity() == 0 {
-            this.buf.reserve(*this.capacity);
+/// Stream `len` bytes of `file` from `offset`.
+///
+/// Every chunk is a positioned read in its own blocking task, the next one is read while the
+/// connection sends the current one. No blocking thread waits on a slow client and at most two
+/// chunks are held per download.
+pub fn file_body(file: Arc<std::fs::File>, offset: u64, len: u64) -> BoxBody<Bytes, anyhow::Error> {
+    let end = offset + len;
+    let read_chunk = move |position: u64| {
+        let file = file.clone();
+        tokio::task::spawn_blocking(move || {
+            let size = ((end - position) as usize).min(FILE_CHUNK_SIZE);
+            let mut buf = BytesMut::zeroed(size);
+            let n = read_full_at(&file, &mut buf, position)?;
+            buf.truncate(n);
+            Ok::<_, std::io::Error>(buf.freeze())
+        })
+    };
+    let stream = async_stream::stream! {
+        let mut position = offset;
+        let mut next = (position < end).then(|| read_chunk(position));
+        while let Some(task) = next.take() {
+            let chunk = match task.await.map_err(std::io::Error::other).and_then(|v| v) {
+                Ok(chunk) if chunk.is_empty() => break,
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    yield Err(anyhow::Error::new(err));
+                    break;
+                }
+            };
+            position += chunk.len() as u64;
+            if position < end {
+                next = Some(read_chunk(position));
+            }
+            yield Ok(Frame::data(chunk));
         }
+    };
+    StreamBody::new(stream).boxed()
+}
 
-        match poll_read_buf(reader, cx, &mut this.buf) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Err(err)) => {
-                self.project().reader.set(None);
-                Poll::Ready(Some(Err(err)))
-            }
-            Poll::Ready(Ok(0)) => {
-                self.project().reader.set(None);
-                Poll::Ready(None)
-            }
-            Poll::Ready(Ok(_)) => {
-                let mut chunk = this.buf.split();
-                let chunk_size = (*this.remaining).min(chunk.len());
-                chunk.truncate(chunk_size);
-                *this.remaining -= chunk_size;
-                Poll::Ready(Some(Ok(chunk.freeze())))
-            }
+/// Fill `buf` from `offset` unless the file ends first, without moving the file cursor.
+fn read_full_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileExt;
+    let mut read = 0;
+    while read < buf.len() {
+        #[cfg(unix)]
+        let n = file.read_at(&mut buf[read..], offset + read as u64);
+        #[cfg(windows)]
+        let n = file.seek_read(&mut buf[read..], offset + read as u64);
+        match n {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
         }
     }
+    Ok(read)
 }
 
-pub fn body_full(content: impl Into<hyper::body::Bytes>) -> BoxBody<Bytes, anyhow::Error> {
-    Full::new(content.into())
-        .map_err(anyhow::Error::new)
-        .boxed()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_accepted_encodings() {
+        assert_eq!(
+            accepted_encodings("gzip, deflate, br, zstd"),
+            vec!["br", "zstd", "gzip"]
+        );
+        assert_eq!(
+            accepted_encodings("gzip;q=1.0, br;q=0.5"),
+            vec!["gzip", "br"]
+        );
+        assert_eq!(accepted_encodings("*;q=0.1, zstd;q=0"), vec!["br", "gzip"]);
+        assert_eq!(accepted_encodings("identity"), Vec::<&str>::new());
+        assert_eq!(accepted_encodings(""), Vec::<&str>::new());
+    }
+
+    fn temp_file(name: &str, size: usize) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dufs-{}-{name}", std::process::id()));
+        let data: Vec<u8> = (0..size).map(|v| (v % 251) as u8).collect();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_file_body() {
+        let size = FILE_CHUNK_SIZE * 2 + 100;
+        let path = temp_file("file-body", size);
+        let data = std::fs::read(&path).unwrap();
+        for (offset, len) in [(0, size), (7, FILE_CHUNK_SIZE + 3), (size - 10, 10)] {
+            let file = Arc::new(std::fs::File::open(&path).unwrap());
+            let body = file_body(file, offset as u64, len as u64);
+            let output = body.collect().await.unwrap().to_bytes();
+            assert_eq!(&output[..], &data[offset..offset + len]);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_body_releases_blocking_threads() {
+        let size = FILE_CHUNK_SIZE * 8;
+        let path = temp_file("file-body-idle", size);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .max_blocking_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            // Downloads whose clients stopped reading after the first chunk
+            let mut bodies = vec![];
+            for _ in 0..4 {
+                let file = Arc::new(std::fs::File::open(&path).unwrap());
+                let mut body = file_body(file, 0, size as u64);
+                let frame = tokio::time::timeout(Duration::from_secs(5), body.frame()).await;
+                assert!(frame.unwrap().unwrap().is_ok());
+                bodies.push(body);
+            }
+            let task = tokio::task::spawn_blocking(|| 1);
+            let ret = tokio::time::timeout(Duration::from_secs(5), task).await;
+            assert_eq!(ret.unwrap().unwrap(), 1);
+        });
+        std::fs::remove_file(path).unwrap();
+    }
 }
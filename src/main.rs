@@ -1,26 +1,45 @@
+mod archive_browse;
+mod archive_pool;
 mod args;
+mod audit_log;
 mod auth;
+mod digest;
+mod extract;
+mod hash_cache;
 mod http_logger;
 mod http_utils;
 mod logger;
+mod multipart;
 mod noscript;
+mod partial_upload;
 mod server;
+mod stored_zip;
+mod throttle;
+mod tus;
+mod upload_session;
 mod utils;
+mod zero_copy;
 
 #[macro_use]
 extern crate log;
 
-use crate::args::{build_cli, print_completions, Args};
+use crate::archive_pool::ArchivePool;
+use crate::args::{build_cli, print_completions, Args, Site};
 use crate::server::Server;
+use crate::throttle::RateLimits;
 #[cfg(feature = "tls")]
-use crate::utils::{load_certs, load_private_key};
+use crate::utils::{
+    cert_fingerprint, generate_self_signed_cert, load_certs, load_private_key,
+    parse_pem_cert_and_key,
+};
+use crate::zero_copy::ZeroCopy;
 
 use anyhow::{anyhow, Context, Result};
 use args::BindAddr;
 use clap_complete::Shell;
 use futures_util::future::join_all;
 
-use hyper::{body::Incoming, service::service_fn, Request};
+use hyper::{body::Incoming, header::HOST, service::service_fn, Request, Version};
 use hyper_util::{
     rt::{TokioExecutor, TokioIo},
     server::conn::auto::Builder,
@@ -34,7 +53,15 @@ use std::time::Duration;
 use tokio::time::timeout;
 use tokio::{net::TcpListener, task::JoinHandle};
 #[cfg(feature = "tls")]
-use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -47,11 +74,17 @@ async fn main() -> Result<()> {
     }
     let mut args = Args::parse(matches)?;
     logger::init(args.log_file.clone()).map_err(|e| anyhow!("Failed to init logger, {e}"))?;
+    audit_log::init(
+        args.audit_log.clone(),
+        args.audit_log_max_size,
+        args.audit_log_max_files,
+    )?;
     let (new_addrs, print_addrs) = check_addrs(&args)?;
     args.addrs = new_addrs;
+    let tls = load_tls(&args, &print_addrs)?;
     let running = Arc::new(AtomicBool::new(true));
-    let listening = print_listening(&args, &print_addrs)?;
-    let handles = serve(args, running.clone())?;
+    let listening = print_listening(&args, &print_addrs, tls.as_ref())?;
+    let handles = serve(args, tls, running.clone())?;
     println!("{listening}");
 
     tokio::select! {
@@ -70,11 +103,10 @@ async fn main() -> Result<()> {
     }
 }
 
-fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
+fn serve(args: Args, tls: Option<Tls>, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
     let addrs = args.addrs.clone();
     let port = args.port;
-    let tls_config = (args.tls_cert.clone(), args.tls_key.clone());
-    let server_handle = Arc::new(Server::init(args, running)?);
+    let server_handle = Arc::new(Sites::init(args, running)?);
     let mut handles = vec![];
     for bind_addr in addrs.iter() {
         let server_handle = server_handle.clone();
@@ -83,17 +115,10 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                 let listener = create_listener(SocketAddr::new(*ip, port))
                     .with_context(|| format!("Failed to bind `{ip}:{port}`"))?;
 
-                match &tls_config {
+                match &tls {
                     #[cfg(feature = "tls")]
-                    (Some(cert_file), Some(key_file)) => {
-                        let certs = load_certs(cert_file)?;
-                        let key = load_private_key(key_file)?;
-                        let mut config = ServerConfig::builder()
-                            .with_no_client_auth()
-                            .with_single_cert(certs, key)?;
-                        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-                        let config = Arc::new(config);
-                        let tls_accepter = TlsAcceptor::from(config);
+                    Some(tls) => {
+                        let tls_accepter = TlsAcceptor::from(tls.config.clone());
                         let handshake_timeout = Duration::from_secs(10);
 
                         let handle = tokio::spawn(async move {
@@ -114,31 +139,39 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                                     server_handle.clone(),
                                     stream,
                                     Some(addr),
+                                    None,
                                 ));
                             }
                         });
 
                         handles.push(handle);
                     }
-                    (None, None) => {
+                    _ => {
                         let handle = tokio::spawn(async move {
                             loop {
                                 let Ok((stream, addr)) = listener.accept().await else {
                                     continue;
                                 };
+                                // Files are sent straight from the page cache, only over plain TCP
+                                #[cfg(any(target_os = "linux", target_os = "android"))]
+                                let (stream, zero_copy) = {
+                                    let stream = zero_copy::ZeroCopyStream::new(stream);
+                                    let zero_copy = stream.zero_copy();
+                                    (stream, Some(zero_copy))
+                                };
+                                #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                                let zero_copy = None;
                                 let stream = TokioIo::new(stream);
                                 tokio::spawn(handle_stream(
                                     server_handle.clone(),
                                     stream,
                                     Some(addr),
+                                    zero_copy,
                                 ));
                             }
                         });
                         handles.push(handle);
                     }
-                    _ => {
-                        unreachable!()
-                    }
                 };
             }
             #[cfg(unix)]
@@ -162,7 +195,7 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
                             continue;
                         };
                         let stream = TokioIo::new(stream);
-                        tokio::spawn(handle_stream(server_handle.clone(), stream, None));
+                        tokio::spawn(handle_stream(server_handle.clone(), stream, None, None));
                     }
                 });
 
@@ -173,12 +206,111 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
     Ok(handles)
 }
 
-async fn handle_stream<T>(handle: Arc<Server>, stream: TokioIo<T>, addr: Option<SocketAddr>)
-where
+/// The default server plus the virtual hosts configured by `sites`.
+struct Sites {
+    default: Arc<Server>,
+    sites: Vec<(Vec<String>, Arc<Server>)>,
+    rate_limits: Arc<RateLimits>,
+}
+
+impl Sites {
+    fn init(mut args: Args, running: Arc<AtomicBool>) -> Result<Self> {
+        // Archive jobs are capped for the whole process, not per site
+        let archive_pool = Arc::new(ArchivePool::new(args.archive_jobs)?);
+        // So are rate limits, a connection or a user may reach several sites
+        let rate_limits = Arc::new(RateLimits::new(
+            args.rate_limit,
+            args.rate_limit_user,
+            args.rate_limit_conn,
+        ));
+        let mut sites = vec![];
+        for Site { hosts, args } in std::mem::take(&mut args.sites) {
+            let name = hosts.join(",");
+            let server = Server::init(
+                args,
+                running.clone(),
+                archive_pool.clone(),
+                rate_limits.clone(),
+            )
+            .with_context(|| format!("Failed to init site `{name}`"))?;
+            sites.push((hosts, Arc::new(server)));
+        }
+        let default = Arc::new(Server::init(
+            args,
+            running,
+            archive_pool,
+            rate_limits.clone(),
+        )?);
+        Ok(Self {
+            default,
+            sites,
+            rate_limits,
+        })
+    }
+
+    fn select(&self, req: &Request<Incoming>) -> Arc<Server> {
+        if self.sites.is_empty() {
+            return self.default.clone();
+        }
+        let host = req
+            .uri()
+            .host()
+            .or_else(|| req.headers().get(HOST).and_then(|v| v.to_str().ok()))
+            .map(strip_host_port)
+            .unwrap_or_default()
+            .to_lowercase();
+        match find_site(&self.sites, &host) {
+            Some(server) => server.clone(),
+            None => self.default.clone(),
+        }
+    }
+}
+
+/// Finds the site serving `host`, exact names take precedence over wildcard patterns.
+fn find_site<'a, T>(sites: &'a [(Vec<String>, T)], host: &str) -> Option<&'a T> {
+    sites
+        .iter()
+        .find(|(hosts, _)| hosts.iter().any(|v| v == host))
+        .or_else(|| {
+            sites
+                .iter()
+                .find(|(hosts, _)| hosts.iter().any(|v| utils::glob(v, host)))
+        })
+        .map(|(_, v)| v)
+}
+
+fn strip_host_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or_default();
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+async fn handle_stream<T>(
+    handle: Arc<Sites>,
+    stream: TokioIo<T>,
+    addr: Option<SocketAddr>,
+    zero_copy: Option<ZeroCopy>,
+) where
     T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
-    let hyper_service =
-        service_fn(move |request: Request<Incoming>| handle.clone().call(request, addr));
+    let connection_limit = handle.rate_limits.connection();
+    let hyper_service = service_fn(move |mut request: Request<Incoming>| {
+        if let Some(limit) = &connection_limit {
+            request.extensions_mut().insert(limit.clone());
+        }
+        // HTTP/2 copies the frames into its own, a placeholder would be sent as it is
+        if let Some(zero_copy) = zero_copy
+            .as_ref()
+            .filter(|_| request.version() < Version::HTTP_2)
+        {
+            request.extensions_mut().insert(zero_copy.clone());
+        }
+        handle.select(&request).call(request, addr)
+    });
 
     match Builder::new(TokioExecutor::new())
         .serve_connection_with_upgrades(stream, hyper_service)
@@ -266,7 +398,132 @@ fn interface_addrs() -> Result<(Vec<BindAddr>, Vec<BindAddr>)> {
     Ok((ipv4_addrs, ipv6_addrs))
 }
 
-fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
+/// TLS settings shared by all listeners.
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+struct Tls {
+    #[cfg(feature = "tls")]
+    config: Arc<ServerConfig>,
+    fingerprint: Option<String>,
+}
+
+#[cfg(feature = "tls")]
+fn load_tls(args: &Args, print_addrs: &[BindAddr]) -> Result<Option<Tls>> {
+    let (default_cert, fingerprint) = if args.tls_self_signed {
+        let (certs, key) = match (&args.tls_cert, &args.tls_key) {
+            (Some(cert_file), Some(key_file)) if cert_file.exists() && key_file.exists() => {
+                (load_certs(cert_file)?, load_private_key(key_file)?)
+            }
+            (cert_file, key_file) => {
+                let mut subject_alt_names = vec!["localhost".to_string()];
+                for bind_addr in print_addrs {
+                    if let BindAddr::IpAddr(ip) = bind_addr {
+                        let name = ip.to_string();
+                        if !subject_alt_names.contains(&name) {
+                            subject_alt_names.push(name);
+                        }
+                    }
+                }
+                let (cert_pem, key_pem) = generate_self_signed_cert(subject_alt_names)?;
+                if let (Some(cert_file), Some(key_file)) = (cert_file, key_file) {
+                    std::fs::write(cert_file, &cert_pem).with_context(|| {
+                        format!("Failed to save cert file at `{}`", cert_file.display())
+                    })?;
+                    save_private_key(key_file, &key_pem).with_context(|| {
+                        format!("Failed to save key file at `{}`", key_file.display())
+                    })?;
+                }
+                parse_pem_cert_and_key(&cert_pem, &key_pem)?
+            }
+        };
+        let fingerprint = certs.first().map(cert_fingerprint);
+        (Some((certs, key)), fingerprint)
+    } else {
+        match (&args.tls_cert, &args.tls_key) {
+            (Some(cert_file), Some(key_file)) => (
+                Some((load_certs(cert_file)?, load_private_key(key_file)?)),
+                None,
+            ),
+            _ => (None, None),
+        }
+    };
+    let mut site_certs = vec![];
+    for site in &args.sites {
+        if let (Some(cert_file), Some(key_file)) = (&site.args.tls_cert, &site.args.tls_key) {
+            site_certs.push((
+                site.hosts.clone(),
+                load_certs(cert_file)?,
+                load_private_key(key_file)?,
+            ));
+        }
+    }
+    let builder = ServerConfig::builder().with_no_client_auth();
+    let mut config = match (default_cert, site_certs.is_empty()) {
+        (None, true) => return Ok(None),
+        (Some((certs, key)), true) => builder.with_single_cert(certs, key)?,
+        (default_cert, false) => {
+            let provider = builder.crypto_provider().clone();
+            let certified_key = |certs: Vec<CertificateDer<'static>>,
+                                 key: PrivateKeyDer<'static>| {
+                CertifiedKey::from_der(certs, key, &provider)
+                    .map(Arc::new)
+                    .context("Invalid tls cert or key")
+            };
+            let default = match default_cert {
+                Some((certs, key)) => Some(certified_key(certs, key)?),
+                None => None,
+            };
+            let mut sites = vec![];
+            for (hosts, certs, key) in site_certs {
+                sites.push((hosts, certified_key(certs, key)?));
+            }
+            builder.with_cert_resolver(Arc::new(SniResolver { default, sites }))
+        }
+    };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Some(Tls {
+        config: Arc::new(config),
+        fingerprint,
+    }))
+}
+
+/// Picks the certificate of a site by the SNI name sent by the client.
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    sites: Vec<(Vec<String>, Arc<CertifiedKey>)>,
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| find_site(&self.sites, &name.to_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+fn load_tls(_args: &Args, _print_addrs: &[BindAddr]) -> Result<Option<Tls>> {
+    Ok(None)
+}
+
+#[cfg(feature = "tls")]
+fn save_private_key(path: &std::path::Path, key_pem: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(key_pem.as_bytes())
+}
+
+fn print_listening(args: &Args, print_addrs: &[BindAddr], tls: Option<&Tls>) -> Result<String> {
     let mut output = String::new();
     let urls = print_addrs
         .iter()
@@ -276,11 +533,7 @@ fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
                     IpAddr::V4(_) => format!("{}:{}", addr, args.port),
                     IpAddr::V6(_) => format!("[{}]:{}", addr, args.port),
                 };
-                let protocol = if args.tls_cert.is_some() {
-                    "https"
-                } else {
-                    "http"
-                };
+                let protocol = if tls.is_some() { "https" } else { "http" };
                 format!("{}://{}{}", protocol, addr, args.uri_prefix)
             }
             #[cfg(unix)]
@@ -299,6 +552,15 @@ fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
         output.push_str(&format!("Listening on:\n{info}\n"))
     }
 
+    if let Some(fingerprint) = tls.and_then(|v| v.fingerprint.as_ref()) {
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&format!(
+            "Certificate SHA-256 fingerprint:\n  {fingerprint}\n"
+        ));
+    }
+
     Ok(output)
 }
 
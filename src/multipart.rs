@@ -0,0 +1,186 @@
+//! Streaming parser of `multipart/form-data` request bodies, parts are read chunk by chunk
+//! so that uploaded files never have to be held in memory.
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::stream::BoxStream;
+use futures_util::TryStreamExt;
+use memchr::memmem;
+
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+pub struct Multipart {
+    stream: BoxStream<'static, Result<Bytes>>,
+    buf: BytesMut,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    in_part: bool,
+    done: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+}
+
+impl Multipart {
+    pub fn new(stream: BoxStream<'static, Result<Bytes>>, boundary: &str) -> Self {
+        // The first boundary isn't preceded by a line break, pretend it is
+        let buf = BytesMut::from(&b"\r\n"[..]);
+        Self {
+            stream,
+            buf,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // Skip the preamble like the rest of a part
+            in_part: true,
+            done: false,
+        }
+    }
+
+    /// Advance to the next part, skipping whatever is left of the current one.
+    pub async fn next_part(&mut self) -> Result<Option<Part>> {
+        if self.done {
+            return Ok(None);
+        }
+        while self.in_part {
+            self.next_chunk().await?;
+        }
+        while self.buf.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buf.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+        let headers_end = loop {
+            if let Some(i) = memmem::find(&self.buf, b"\r\n\r\n") {
+                break i;
+            }
+            if self.buf.len() > MAX_HEADERS_SIZE {
+                bail!("Multipart headers too large");
+            }
+            self.fill().await?;
+        };
+        let headers = self.buf.split_to(headers_end + 4);
+        let headers =
+            std::str::from_utf8(&headers).map_err(|_| anyhow!("Invalid multipart headers"))?;
+        let mut lines = headers.split("\r\n");
+        // Transport padding after the boundary
+        if !lines.next().unwrap_or_default().trim().is_empty() {
+            bail!("Invalid multipart boundary");
+        }
+        let mut part = Part::default();
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                for (key, value) in parse_disposition_params(value) {
+                    match key.as_str() {
+                        "name" => part.name = Some(value),
+                        "filename" => part.filename = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        self.in_part = true;
+        Ok(Some(part))
+    }
+
+    /// Read the next chunk of the current part, `None` at its end.
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        if !self.in_part {
+            return Ok(None);
+        }
+        loop {
+            match memmem::find(&self.buf, &self.delimiter) {
+                Some(0) => {
+                    self.buf.advance(self.delimiter.len());
+                    self.in_part = false;
+                    return Ok(None);
+                }
+                Some(i) => return Ok(Some(self.buf.split_to(i).freeze())),
+                None => {
+                    // The tail may be the start of a delimiter split across chunks
+                    let safe_len = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                    if safe_len > 0 {
+                        return Ok(Some(self.buf.split_to(safe_len).freeze()));
+                    }
+                    self.fill().await?;
+                }
+            }
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        match self.stream.try_next().await? {
+            Some(chunk) => {
+                self.buf.extend_from_slice(&chunk);
+                Ok(())
+            }
+            None => bail!("Unexpected end of multipart body"),
+        }
+    }
+}
+
+/// Get the boundary of a `multipart/form-data` content type.
+pub fn parse_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        (!value.is_empty() && value.len() <= 70).then(|| value.to_string())
+    })
+}
+
+/// Parse `form-data; name="files"; filename="a.txt"`
+fn parse_disposition_params(value: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = value;
+    while let Some(i) = rest.find(';') {
+        rest = &rest[i + 1..];
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim_start();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let mut output = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            if let Some((_, c)) = chars.next() {
+                                output.push(c);
+                            }
+                        }
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => output.push(c),
+                    }
+                }
+                (output, &quoted[end..])
+            }
+            None => {
+                let end = value.find(';').unwrap_or(value.len());
+                (value[..end].trim().to_string(), &value[end..])
+            }
+        };
+        params.push((key, value));
+        rest = next;
+    }
+    params
+}
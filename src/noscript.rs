@@ -48,11 +48,23 @@ pub fn generate_noscript_html(data: &IndexData) -> Result<String> {
 
     html.push_str("  </tbody>\n");
     html.push_str("</table>\n");
+    if data.allow_upload && data.dir_exists {
+        html.push_str(&render_upload_form());
+    }
     html.push_str("</body>\n");
 
     Ok(html)
 }
 
+fn render_upload_form() -> String {
+    r#"<form method="post" enctype="multipart/form-data">
+  <input type="file" name="file" multiple>
+  <input type="submit" value="Upload">
+</form>
+"#
+    .to_string()
+}
+
 fn render_parent() -> String {
     let value = "../";
     format!("<tr><td><a href=\"{value}?noscript\">{value}</a></td><td></td><td></td></tr>")
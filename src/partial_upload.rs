@@ -0,0 +1,259 @@
+//! Storage shared by resumable uploads, tus uploads and upload sessions.
+//!
+//! The partial file is staged next to its target as a hidden `.dufs-upload-<id>` file, so that
+//! completing an upload is a rename on the same filesystem, and its record is kept beside it in
+//! `.dufs-upload-<id>.json`. Both are hidden from listings and removed once the upload expires.
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::fs;
+use tokio::sync::{Mutex as AsyncMutex, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+/// Prefix of the hidden files staged by uploads
+pub const UPLOAD_TEMP_PREFIX: &str = ".dufs-upload-";
+
+/// A record of a pending upload.
+pub trait PartialUpload: Serialize + DeserializeOwned {
+    /// Path of the uploaded file relative to the serve path
+    fn target(&self) -> &str;
+    /// Unix timestamp after which the upload is dropped
+    fn expires(&self) -> i64;
+
+    fn is_expired(&self) -> bool {
+        self.expires() <= chrono::Utc::now().timestamp()
+    }
+}
+
+pub struct PartialStore<T> {
+    serve_path: PathBuf,
+    /// Locks of the uploads being written to
+    locks: Mutex<HashMap<String, Weak<RwLock<()>>>>,
+    /// Held while a record is read, changed and saved again
+    updating: AsyncMutex<()>,
+    _upload: PhantomData<T>,
+}
+
+impl<T: PartialUpload> PartialStore<T> {
+    pub fn new(serve_path: &Path) -> Self {
+        Self {
+            serve_path: serve_path.to_path_buf(),
+            locks: Default::default(),
+            updating: Default::default(),
+            _upload: PhantomData,
+        }
+    }
+
+    /// Stage an empty partial file for `upload`, returns its id.
+    pub async fn create(&self, upload: &T) -> Result<String> {
+        let dir = self.dir(upload.target());
+        self.sweep(&dir).await;
+        fs::create_dir_all(&dir).await?;
+        let id = Uuid::new_v4().simple().to_string();
+        fs::File::create(self.part_path(upload.target(), &id)).await?;
+        self.save(&id, upload).await?;
+        Ok(id)
+    }
+
+    /// The upload `id` of the file at `target`.
+    pub async fn get(&self, target: &str, id: &str) -> Option<T> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let data = fs::read(self.info_path(target, id)).await.ok()?;
+        serde_json::from_slice::<T>(&data)
+            .ok()
+            .filter(|v| v.target() == target)
+    }
+
+    /// Replaced in one go, uploads written to in parallel read it without taking the lock
+    pub async fn save(&self, id: &str, upload: &T) -> Result<()> {
+        let info_path = self.info_path(upload.target(), id);
+        let tmp_path = info_path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(upload)?).await?;
+        fs::rename(&tmp_path, &info_path).await?;
+        Ok(())
+    }
+
+    /// Change the record of the upload `id` with `f`, returns it as saved.
+    pub async fn update(
+        &self,
+        target: &str,
+        id: &str,
+        f: impl FnOnce(&mut T),
+    ) -> Result<Option<T>> {
+        let _guard = self.updating.lock().await;
+        let Some(mut upload) = self.get(target, id).await else {
+            return Ok(None);
+        };
+        f(&mut upload);
+        self.save(id, &upload).await?;
+        Ok(Some(upload))
+    }
+
+    pub async fn remove(&self, target: &str, id: &str) {
+        let _ = fs::remove_file(self.part_path(target, id)).await;
+        let _ = fs::remove_file(self.info_path(target, id)).await;
+    }
+
+    /// Move a complete upload to `dest`, a path in the same directory as its target.
+    pub async fn finish(&self, target: &str, id: &str, dest: &Path) -> Result<()> {
+        fs::rename(self.part_path(target, id), dest).await?;
+        let _ = fs::remove_file(self.info_path(target, id)).await;
+        Ok(())
+    }
+
+    /// Wait until no one else writes to the upload `id`.
+    pub async fn lock(&self, id: &str) -> OwnedRwLockWriteGuard<()> {
+        self.rw_lock(id).write_owned().await
+    }
+
+    /// Wait until the upload `id` isn't locked, others may write to it in parallel.
+    pub async fn lock_shared(&self, id: &str) -> OwnedRwLockReadGuard<()> {
+        self.rw_lock(id).read_owned().await
+    }
+
+    fn rw_lock(&self, id: &str) -> Arc<RwLock<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        match locks.get(id).and_then(|v| v.upgrade()) {
+            Some(lock) => lock,
+            None => {
+                locks.retain(|_, v| v.strong_count() > 0);
+                let lock = Arc::new(RwLock::new(()));
+                locks.insert(id.to_string(), Arc::downgrade(&lock));
+                lock
+            }
+        }
+    }
+
+    pub fn part_path(&self, target: &str, id: &str) -> PathBuf {
+        self.dir(target).join(format!("{UPLOAD_TEMP_PREFIX}{id}"))
+    }
+
+    fn info_path(&self, target: &str, id: &str) -> PathBuf {
+        self.dir(target)
+            .join(format!("{UPLOAD_TEMP_PREFIX}{id}.json"))
+    }
+
+    fn dir(&self, target: &str) -> PathBuf {
+        let path = self.serve_path.join(target);
+        match path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => self.serve_path.clone(),
+        }
+    }
+
+    /// Drop the expired uploads staged in `dir`
+    async fn sweep(&self, dir: &Path) {
+        let Ok(mut entries) = fs::read_dir(dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let Some(id) = record_id(&name) else {
+                continue;
+            };
+            if is_expired_record(&entry.path()) {
+                let _ = fs::remove_file(dir.join(format!("{UPLOAD_TEMP_PREFIX}{id}"))).await;
+                let _ = fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+}
+
+/// Drop the expired uploads staged anywhere under `serve_path`, those whose clients never came
+/// back to complete them.
+pub fn sweep_expired(serve_path: &Path) {
+    for entry in WalkDir::new(serve_path).into_iter().flatten() {
+        let Some(id) = record_id(entry.file_name()) else {
+            continue;
+        };
+        if entry.file_type().is_file() && is_expired_record(entry.path()) {
+            let _ = std::fs::remove_file(
+                entry
+                    .path()
+                    .with_file_name(format!("{UPLOAD_TEMP_PREFIX}{id}")),
+            );
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Whether the staged file at `path`, a partial file or its record, belongs to an upload that
+/// can still be resumed.
+pub fn is_pending(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|v| v.to_str()) else {
+        return false;
+    };
+    let Some(id) = name
+        .strip_prefix(UPLOAD_TEMP_PREFIX)
+        .map(|v| v.strip_suffix(".json").unwrap_or(v))
+    else {
+        return false;
+    };
+    let info_path = path.with_file_name(format!("{UPLOAD_TEMP_PREFIX}{id}.json"));
+    info_path.exists() && !is_expired_record(&info_path)
+}
+
+/// Id of the upload whose record is named `name`
+fn record_id(name: &OsStr) -> Option<&str> {
+    name.to_str()
+        .and_then(|v| v.strip_prefix(UPLOAD_TEMP_PREFIX))
+        .and_then(|v| v.strip_suffix(".json"))
+}
+
+fn is_expired_record(info_path: &Path) -> bool {
+    #[derive(Deserialize)]
+    struct Expires {
+        expires: i64,
+    }
+    std::fs::read(info_path)
+        .ok()
+        .and_then(|v| serde_json::from_slice::<Expires>(&v).ok())
+        .is_none_or(|v| v.expires <= chrono::Utc::now().timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_expired() {
+        let serve_path = assert_fs::TempDir::new().unwrap();
+        let dir = serve_path.join("dir1");
+        std::fs::create_dir(&dir).unwrap();
+        let now = chrono::Utc::now().timestamp();
+        for (id, expires) in [("abandoned", now - 1), ("pending", now + 60)] {
+            let part_path = dir.join(format!("{UPLOAD_TEMP_PREFIX}{id}"));
+            std::fs::write(&part_path, "Hello").unwrap();
+            std::fs::write(
+                part_path.with_file_name(format!("{UPLOAD_TEMP_PREFIX}{id}.json")),
+                format!(r#"{{"target":"dir1/file.txt","expires":{expires}}}"#),
+            )
+            .unwrap();
+        }
+        // Not a resumable upload, it has no record
+        std::fs::write(dir.join(format!("{UPLOAD_TEMP_PREFIX}streaming")), "").unwrap();
+
+        sweep_expired(&serve_path);
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                ".dufs-upload-pending",
+                ".dufs-upload-pending.json",
+                ".dufs-upload-streaming"
+            ]
+        );
+    }
+}
@@ -1,49 +1,75 @@
 #![allow(clippy::too_many_arguments)]
 
-use crate::auth::{www_authenticate, AccessPaths, AccessPerm};
-use crate::http_utils::{body_full, IncomingStream, LengthLimitedStream};
+use crate::archive_browse::{normalize_name, ArchiveEntry, ArchiveListing, ListingCache};
+use crate::archive_pool::{ArchivePool, SlotWriter};
+use crate::audit_log::{self, AuditRecord};
+use crate::auth::{get_auth_user, www_authenticate, AccessPaths, AccessPerm};
+use crate::digest::UploadDigests;
+use crate::extract::{extract, ArchiveFormat, ExtractLimits, Rejected};
+use crate::hash_cache::{hash_file, HashCache};
+use crate::http_utils::{
+    accepted_encodings, body_full, encode_body, file_body, is_compressible, IncomingStream,
+};
+use crate::multipart::{parse_boundary, Multipart};
 use crate::noscript::{detect_noscript, generate_noscript_html};
+use crate::partial_upload::{self, PartialUpload, UPLOAD_TEMP_PREFIX};
+use crate::stored_zip::StoredZip;
+use crate::throttle::{ConnectionLimit, RateLimits, Throttle};
+use crate::tus::{
+    metadata_filename, TusChecksum, TusStore, TusUpload, TUS_CHECKSUM_ALGORITHMS, TUS_CONTENT_TYPE,
+    TUS_EXTENSIONS, TUS_VERSION,
+};
+use crate::upload_session::{SessionStore, UploadSession};
 use crate::utils::{
-    decode_uri, encode_uri, get_file_mtime_and_mode, get_file_name, glob, parse_range,
-    try_get_file_name,
+    coalesce_ranges, decode_uri, encode_uri, get_file_mtime_and_mode, get_file_name, glob,
+    parse_range, set_file_mtime, try_get_file_name,
+};
+use crate::zero_copy::{FileSpan, ZeroCopy};
+use crate::{
+    args::{ActiveContent, Compress, ConflictPolicy, Cors, EtagMode, UploadLimit},
+    Args,
 };
-use crate::Args;
 
 use anyhow::{anyhow, Result};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
 use async_zip::{tokio::write::ZipFileWriter, Compression, ZipDateTime, ZipEntryBuilder};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use bytes::Bytes;
-use chrono::{LocalResult, TimeZone, Utc};
-use futures_util::{pin_mut, TryStreamExt};
+use chrono::{DateTime, LocalResult, TimeZone, Utc};
+use futures_util::{future, pin_mut, TryStreamExt};
 use headers::{
-    AcceptRanges, AccessControlAllowCredentials, AccessControlAllowOrigin, CacheControl,
-    ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfMatch, IfModifiedSince,
-    IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified, Range,
+    AcceptRanges, CacheControl, ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfMatch,
+    IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified, Range,
 };
-use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
-use hyper::body::Frame;
+use http_body_util::{combinators::BoxBody, BodyExt, Limited, StreamBody};
+use hyper::body::{Body as _, Frame};
 use hyper::{
     body::Incoming,
     header::{
-        HeaderValue, AUTHORIZATION, CONNECTION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
-        CONTENT_TYPE, RANGE,
+        HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CONNECTION, CONTENT_DISPOSITION,
+        CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+        ETAG, HOST, LOCATION, ORIGIN, RANGE, REFERRER_POLICY, RETRY_AFTER,
+        STRICT_TRANSPORT_SECURITY, VARY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
     },
     Method, StatusCode, Uri,
 };
 use serde::Serialize;
-use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::Metadata;
+use std::future::Future;
 use std::io::SeekFrom;
 use std::net::SocketAddr;
 use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
 use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, DuplexStream};
 use tokio::{fs, io};
 
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
@@ -51,6 +77,7 @@ use tokio_util::io::{ReaderStream, StreamReader};
 use uuid::Uuid;
 use walkdir::{DirEntry, WalkDir};
 use xml::escape::escape_str_pcdata;
+use xml::reader::{EventReader, XmlEvent};
 
 pub type Request = hyper::Request<Incoming>;
 pub type Response = hyper::Response<BoxBody<Bytes, anyhow::Error>>;
@@ -61,21 +88,47 @@ const INDEX_JS: &str = include_str!("../assets/index.js");
 const FAVICON_ICO: &[u8] = include_bytes!("../assets/favicon.ico");
 const INDEX_NAME: &str = "index.html";
 const BUF_SIZE: usize = 65536;
+const MAX_RANGES: usize = 100;
+const HTTP_COMPRESS_MIN_SIZE: u64 = 1024;
 const EDITABLE_TEXT_MAX_SIZE: u64 = 4194304; // 4M
 const RESUMABLE_UPLOAD_MIN_SIZE: u64 = 20971520; // 20M
+const PROPPATCH_MAX_BODY_SIZE: usize = 65536;
+const ARCHIVE_SELECTION_MAX_BODY_SIZE: usize = 1048576; // 1M
 const HEALTH_CHECK_PATH: &str = "__dufs__/health";
+const UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
 pub const MAX_SUBPATHS_COUNT: u64 = 1000;
+const AUDIT_NONE: &str = "";
+
+/// What the handlers learned about a request for its audit record.
+#[derive(Debug, Clone, Default)]
+struct Audit {
+    user: Option<String>,
+    bytes: Option<u64>,
+    auth_failed: bool,
+}
 
 pub struct Server {
     args: Args,
     assets_prefix: String,
     html: Cow<'static, str>,
     single_file_req_paths: Vec<String>,
+    security_headers: Vec<(HeaderName, HeaderValue)>,
+    tus: TusStore,
+    sessions: SessionStore,
+    hashes: Arc<HashCache>,
+    listings: ListingCache,
     running: Arc<AtomicBool>,
+    archive_pool: Arc<ArchivePool>,
+    rate_limits: Arc<RateLimits>,
 }
 
 impl Server {
-    pub fn init(args: Args, running: Arc<AtomicBool>) -> Result<Self> {
+    pub fn init(
+        args: Args,
+        running: Arc<AtomicBool>,
+        archive_pool: Arc<ArchivePool>,
+        rate_limits: Arc<RateLimits>,
+    ) -> Result<Self> {
         let assets_prefix = format!("__dufs_v{}__/", env!("CARGO_PKG_VERSION"));
         let single_file_req_paths = if args.path_is_file {
             vec![
@@ -94,9 +147,38 @@ impl Server {
             Some(path) => Cow::Owned(std::fs::read_to_string(path.join("index.html"))?),
             None => Cow::Borrowed(INDEX_HTML),
         };
+        HeaderValue::from_str(&args.csp).map_err(|_| anyhow!("Invalid csp `{}`", args.csp))?;
+        let mut security_headers =
+            vec![(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"))];
+        for (name, value) in [
+            (X_FRAME_OPTIONS, &args.frame_options),
+            (REFERRER_POLICY, &args.referrer_policy),
+        ] {
+            if !value.is_empty() {
+                let value = HeaderValue::from_str(value)
+                    .map_err(|_| anyhow!("Invalid {name} `{value}`"))?;
+                security_headers.push((name, value));
+            }
+        }
+        if let Some(max_age) = args.hsts {
+            security_headers.push((
+                STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={max_age}"))?,
+            ));
+        }
+        if args.allow_upload && !args.path_is_file {
+            sweep_upload_temp_files(args.serve_path.clone(), running.clone());
+        }
         Ok(Self {
+            tus: TusStore::new(&args.serve_path),
+            sessions: SessionStore::new(&args.serve_path),
+            hashes: Arc::new(HashCache::new(&args.serve_path, args.cache_dir.as_deref())?),
+            listings: ListingCache::new(extract_limits(&args)),
             args,
             running,
+            archive_pool,
+            rate_limits,
+            security_headers,
             single_file_req_paths,
             assets_prefix,
             html,
@@ -110,18 +192,38 @@ impl Server {
     ) -> Result<Response, hyper::Error> {
         let uri = req.uri().clone();
         let assets_prefix = &self.assets_prefix;
-        let enable_cors = self.args.enable_cors;
+        let origin = req.headers().get(ORIGIN).cloned();
+        let accept_encoding = req.headers().get(ACCEPT_ENCODING).cloned();
+        let zero_copy = req.extensions().get::<ZeroCopy>().cloned();
+        let audit_record = self.audit_record(&req, addr);
         let mut http_log_data = self.args.http_logger.data(&req);
         if let Some(addr) = addr {
             http_log_data.insert("remote_addr".to_string(), addr.ip().to_string());
         }
 
-        let mut res = match self.clone().handle(req).await {
-            Ok(res) => {
+        let mut res = match self.clone().handle(req, addr).await {
+            Ok(mut res) => {
                 http_log_data.insert("status".to_string(), res.status().as_u16().to_string());
                 if !uri.path().starts_with(assets_prefix) {
                     self.args.http_logger.log(&http_log_data, None);
                 }
+                if let Some(mut record) = audit_record {
+                    let audit = res.extensions_mut().remove::<Audit>().unwrap_or_default();
+                    if audit.auth_failed {
+                        record.action = "auth_failure";
+                    } else {
+                        record.claimed_user = None;
+                    }
+                    if record.action != AUDIT_NONE {
+                        record.user = audit.user;
+                        record.bytes = audit.bytes;
+                        record.status = res.status().as_u16();
+                        if res.status().is_success() {
+                            record.result = "success";
+                        }
+                        audit_log::log(&record);
+                    }
+                }
                 res
             }
             Err(err) => {
@@ -132,23 +234,102 @@ impl Server {
                 self.args
                     .http_logger
                     .log(&http_log_data, Some(err.to_string()));
+                if let Some(mut record) = audit_record.filter(|v| v.action != AUDIT_NONE) {
+                    record.claimed_user = None;
+                    record.status = status.as_u16();
+                    record.error = Some(err.to_string());
+                    audit_log::log(&record);
+                }
                 res
             }
         };
 
-        if enable_cors {
-            add_cors(&mut res);
+        for (name, value) in &self.security_headers {
+            if !res.headers().contains_key(name) {
+                res.headers_mut().insert(name, value.clone());
+            }
+        }
+        if let Some(cors) = &self.args.cors {
+            add_cors(cors, origin.as_ref(), &mut res);
+        }
+        let file_span = res.extensions_mut().remove::<FileSpan>();
+        let encoded = self.encode_response(accept_encoding.as_ref(), &mut res);
+        if let Some(throttle) = res.extensions_mut().remove::<Throttle>() {
+            let body = std::mem::replace(res.body_mut(), body_full(Bytes::new()));
+            *res.body_mut() = throttle.body(body);
+        } else if let Some((zero_copy, span)) = zero_copy.zip(file_span).filter(|_| !encoded) {
+            // Sent by the connection itself, nothing else may touch the body
+            *res.body_mut() = zero_copy.body(span);
         }
         Ok(res)
     }
 
-    pub async fn handle(self: Arc<Self>, req: Request) -> Result<Response> {
+    /// Compress text responses on the fly with a coding the client accepts, returns whether the
+    /// body was.
+    fn encode_response(&self, accept_encoding: Option<&HeaderValue>, res: &mut Response) -> bool {
+        if self.args.http_compress == Compress::None
+            || res.status() != StatusCode::OK
+            || res.headers().contains_key(CONTENT_ENCODING)
+        {
+            return false;
+        }
+        let compressible = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(is_compressible);
+        if !compressible {
+            return false;
+        }
+        add_vary(res, "accept-encoding");
+        let len = res
+            .headers()
+            .typed_get::<ContentLength>()
+            .map(|v| v.0)
+            .or_else(|| res.body().size_hint().exact());
+        if len.is_some_and(|v| v < HTTP_COMPRESS_MIN_SIZE) {
+            return false;
+        }
+        let Some(encoding) = accept_encoding
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| accepted_encodings(v).into_iter().next())
+        else {
+            return false;
+        };
+        let headers = res.headers_mut();
+        headers.remove(CONTENT_LENGTH);
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        // The encoded bytes differ, but a cached copy is still valid for `If-None-Match`
+        if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok()) {
+            if !etag.starts_with("W/") {
+                if let Ok(value) = HeaderValue::from_str(&format!("W/{etag}")) {
+                    headers.insert(ETAG, value);
+                }
+            }
+        }
+        let body = std::mem::replace(res.body_mut(), body_full(Bytes::new()));
+        *res.body_mut() = encode_body(body, encoding, self.args.http_compress.to_level());
+        true
+    }
+
+    pub async fn handle(
+        self: Arc<Self>,
+        req: Request,
+        addr: Option<SocketAddr>,
+    ) -> Result<Response> {
         let mut res = Response::default();
 
         let req_path = req.uri().path();
         let headers = req.headers();
         let method = req.method().clone();
 
+        if let Some(cors) = &self.args.cors {
+            if method == Method::OPTIONS && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD) {
+                set_cors_preflight(cors, headers, &mut res);
+                return Ok(res);
+            }
+        }
+
         let relative_path = match self.resolve_path(req_path) {
             Some(v) => v,
             None => {
@@ -186,9 +367,22 @@ impl Server {
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .collect();
 
+        // Downloading a selection of a folder as an archive only reads. Uploads can carry an
+        // archive query too, they are told apart first and keep being guarded as writes.
+        let is_archive_selection = method == Method::POST
+            && is_archive_selection_query(&query_params, headers)
+            && match self.join_path(&relative_path) {
+                Some(path) => fs::metadata(path).await.is_ok_and(|v| v.is_dir()),
+                None => false,
+            };
+        let guard_method = if is_archive_selection {
+            Method::GET
+        } else {
+            method.clone()
+        };
         let guard = self.args.auth.guard(
             &relative_path,
-            &method,
+            &guard_method,
             authorization,
             query_params.get("token"),
             is_microsoft_webdav,
@@ -196,6 +390,12 @@ impl Server {
 
         let (user, access_paths) = match guard {
             (None, None) => {
+                if authorization.is_some() || query_params.contains_key("token") {
+                    res.extensions_mut().insert(Audit {
+                        auth_failed: true,
+                        ..Default::default()
+                    });
+                }
                 self.auth_reject(&mut res)?;
                 return Ok(res);
             }
@@ -205,6 +405,18 @@ impl Server {
             }
             (x, Some(y)) => (x, y),
         };
+        res.extensions_mut().insert(Audit {
+            user: user.clone(),
+            ..Default::default()
+        });
+        let throttle = self.rate_limits.throttle(
+            user.as_deref(),
+            addr.map(|v| v.ip()),
+            req.extensions().get::<ConnectionLimit>(),
+        );
+        if !throttle.is_unlimited() {
+            res.extensions_mut().insert(throttle.clone());
+        }
 
         if detect_noscript(&user_agent) {
             query_params.insert("noscript".to_string(), String::new());
@@ -216,7 +428,10 @@ impl Server {
                     *res.body_mut() = body_full(user);
                 }
                 None => {
-                    if has_query_flag(&query_params, "login") || !access_paths.perm().readwrite() {
+                    let perm = access_paths.perm();
+                    if has_query_flag(&query_params, "login")
+                        || !(perm.readwrite() || perm.writeonly())
+                    {
                         self.auth_reject(&mut res)?
                     } else {
                         *res.body_mut() = body_full("");
@@ -265,6 +480,24 @@ impl Server {
             None => (true, false, false, 0),
         };
 
+        if let Some((archive_path, name)) =
+            archive_entry_target(&relative_path, is_miss, is_file, &query_params)
+        {
+            self.handle_archive_entry(
+                &archive_path,
+                &name,
+                &method,
+                headers,
+                &query_params,
+                head_only,
+                user,
+                access_paths,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        }
+
         let allow_upload = self.args.allow_upload;
         let allow_delete = self.args.allow_delete;
         let allow_search = self.args.allow_search;
@@ -272,6 +505,7 @@ impl Server {
         let render_index = self.args.render_index;
         let render_spa = self.args.render_spa;
         let render_try_index = self.args.render_try_index;
+        let write_only = access_paths.perm().writeonly();
 
         if self.guard_root_contained(path).await {
             self.handle_not_found(&query_params, headers, head_only, &mut res)
@@ -279,18 +513,100 @@ impl Server {
             return Ok(res);
         }
 
+        if let Some(id) = query_params.get("tus").filter(|_| method != Method::GET) {
+            self.handle_tus_upload(
+                path,
+                &relative_path,
+                id,
+                &access_paths,
+                req,
+                &throttle,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        } else if let Some(id) = query_params
+            .get("session")
+            .filter(|_| method != Method::GET)
+        {
+            if !id.is_empty() {
+                self.handle_session(
+                    path,
+                    &relative_path,
+                    id,
+                    &access_paths,
+                    req,
+                    &throttle,
+                    &mut res,
+                )
+                .await?;
+            } else if method == Method::POST {
+                self.handle_session_create(
+                    path,
+                    &relative_path,
+                    is_dir,
+                    write_only,
+                    &access_paths,
+                    headers,
+                    &query_params,
+                    &mut res,
+                )
+                .await?;
+            } else {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            }
+            return Ok(res);
+        } else if method == Method::POST && headers.contains_key(TUS_RESUMABLE) {
+            self.handle_tus_create(
+                path,
+                &relative_path,
+                is_dir,
+                is_miss,
+                write_only,
+                &access_paths,
+                headers,
+                &mut res,
+            )
+            .await?;
+            return Ok(res);
+        }
+
         match method {
+            Method::GET | Method::HEAD if write_only => {
+                // Only the upload page, never the content
+                if is_dir || (is_miss && allow_upload && req_path.ends_with('/')) {
+                    self.handle_ls_dir(
+                        path,
+                        is_dir,
+                        &query_params,
+                        head_only,
+                        user,
+                        access_paths,
+                        &mut res,
+                    )
+                    .await?;
+                } else {
+                    status_forbid(&mut res);
+                }
+            }
             Method::GET | Method::HEAD => {
                 if is_dir {
                     if render_try_index {
-                        if allow_archive && has_query_flag(&query_params, "zip") {
+                        if allow_archive && archive_query(&query_params).is_some() {
                             if !allow_archive {
                                 self.handle_not_found(&query_params, headers, head_only, &mut res)
                                     .await?;
                                 return Ok(res);
                             }
-                            self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                                .await?;
+                            self.handle_archive_dir(
+                                path,
+                                &query_params,
+                                headers,
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
                         } else if allow_search && query_params.contains_key("q") {
                             self.handle_search_dir(
                                 path,
@@ -324,13 +640,20 @@ impl Server {
                             &mut res,
                         )
                         .await?;
-                    } else if has_query_flag(&query_params, "zip") {
+                    } else if archive_query(&query_params).is_some() {
                         if !allow_archive {
                             status_not_found(&mut res);
                             return Ok(res);
                         }
-                        self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                            .await?;
+                        self.handle_archive_dir(
+                            path,
+                            &query_params,
+                            headers,
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
                     } else if allow_search && query_params.contains_key("q") {
                         self.handle_search_dir(
                             path,
@@ -393,18 +716,88 @@ impl Server {
             }
             Method::OPTIONS => {
                 set_webdav_headers(&mut res);
+                if allow_upload {
+                    set_tus_headers(&mut res);
+                }
+            }
+            Method::POST if is_file && has_query_flag(&query_params, "extract") => {
+                if !allow_upload || !self.args.allow_extract || write_only {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_extract(
+                        path,
+                        &relative_path,
+                        access_paths,
+                        headers,
+                        &query_params,
+                        &mut res,
+                    )
+                    .await?;
+                }
+            }
+            Method::POST if is_archive_selection => {
+                if !allow_archive {
+                    status_not_found(&mut res);
+                } else if write_only {
+                    status_forbid(&mut res);
+                } else {
+                    self.handle_archive_selected(path, &query_params, access_paths, req, &mut res)
+                        .await?;
+                }
+            }
+            Method::POST if is_dir => {
+                let boundary = headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_boundary);
+                match boundary {
+                    Some(boundary) if allow_upload => {
+                        self.handle_form_upload(
+                            path,
+                            &relative_path,
+                            &boundary,
+                            access_paths,
+                            req,
+                            &throttle,
+                            &mut res,
+                        )
+                        .await?;
+                    }
+                    Some(_) => status_forbid(&mut res),
+                    None => *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED,
+                }
             }
             Method::PUT => {
-                if is_dir || !allow_upload || (!allow_delete && size > 0) {
+                let mut limit = self.args.upload_limit(&relative_path);
+                match upload_conflict(headers, &query_params) {
+                    Ok(Some(conflict)) => limit.conflict = Some(conflict),
+                    Ok(None) => {}
+                    Err(err) => {
+                        status_bad_request(&mut res, &err.to_string());
+                        return Ok(res);
+                    }
+                }
+                if write_only {
+                    // Existing files are neither replaced nor revealed by a conflict error
+                    limit.conflict = Some(ConflictPolicy::Rename);
+                }
+                let conflict = limit.conflict.unwrap_or_default();
+                if is_dir
+                    || !allow_upload
+                    || (!allow_delete && size > 0 && conflict == ConflictPolicy::Overwrite)
+                {
                     status_forbid(&mut res);
+                } else if !is_miss && conflict == ConflictPolicy::Fail {
+                    *res.status_mut() = StatusCode::CONFLICT;
                 } else {
-                    self.handle_upload(path, None, size, req, &mut res).await?;
+                    self.handle_upload(path, None, size, &limit, req, &throttle, &mut res)
+                        .await?;
                 }
             }
             Method::PATCH => {
                 if is_miss {
                     status_not_found(&mut res);
-                } else if !allow_upload {
+                } else if !allow_upload || write_only {
                     status_forbid(&mut res);
                 } else {
                     let offset = match parse_upload_offset(headers, size) {
@@ -419,8 +812,17 @@ impl Server {
                             if offset < size && !allow_delete {
                                 status_forbid(&mut res);
                             }
-                            self.handle_upload(path, Some(offset), size, req, &mut res)
-                                .await?;
+                            let limit = self.args.upload_limit(&relative_path);
+                            self.handle_upload(
+                                path,
+                                Some(offset),
+                                size,
+                                &limit,
+                                req,
+                                &throttle,
+                                &mut res,
+                            )
+                            .await?;
                         }
                         None => {
                             *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
@@ -449,15 +851,19 @@ impl Server {
                             };
                         self.handle_propfind_dir(path, headers, access_paths, &mut res)
                             .await?;
-                    } else if is_file {
+                    } else if is_file && !write_only {
                         self.handle_propfind_file(path, &mut res).await?;
+                    } else if is_file {
+                        status_forbid(&mut res);
                     } else {
                         status_not_found(&mut res);
                     }
                 }
                 "PROPPATCH" => {
                     if is_file {
-                        self.handle_proppatch(req_path, &mut res).await?;
+                        let req_path = req_path.to_string();
+                        self.handle_proppatch(path, &req_path, allow_upload, req, &mut res)
+                            .await?;
                     } else {
                         status_not_found(&mut res);
                     }
@@ -518,77 +924,1047 @@ impl Server {
         path: &Path,
         upload_offset: Option<u64>,
         size: u64,
+        limit: &UploadLimit,
         req: Request,
+        throttle: &Throttle,
         res: &mut Response,
     ) -> Result<()> {
+        let name = get_file_name(path);
+        let content_length = req
+            .headers()
+            .typed_get::<ContentLength>()
+            .map(|v| v.0)
+            .unwrap_or_default();
+        let max_size = match limit.max_size {
+            Some(max_size) => {
+                let max_size = max_size.saturating_sub(upload_offset.unwrap_or_default());
+                if content_length > max_size {
+                    status_payload_too_large(res, limit);
+                    return Ok(());
+                }
+                Some(max_size)
+            }
+            None => None,
+        };
+        if !allow_upload_type(limit, name, None) {
+            status_unsupported_type(res, name);
+            return Ok(());
+        }
+        let mut digests = match UploadDigests::from_headers(req.headers(), upload_offset.is_none())
+        {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let mtime = match upload_mtime(req.headers()) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
         ensure_path_parent(path).await?;
-        let (mut file, status) = match upload_offset {
-            None => (fs::File::create(path).await?, StatusCode::CREATED),
+        // A whole file upload is written aside and renamed over the destination once complete,
+        // so that readers never see a half-written file and a failed upload keeps the old one.
+        // A partial upload with a digest is staged the same way until the digest is verified.
+        let (mut file, temp_path, status) = match upload_offset {
+            None => {
+                let temp_path = upload_temp_path(path);
+                (
+                    fs::File::create(&temp_path).await?,
+                    Some(temp_path),
+                    StatusCode::CREATED,
+                )
+            }
+            Some(_) if digests.is_some() => {
+                let temp_path = upload_temp_path(path);
+                (
+                    fs::File::create(&temp_path).await?,
+                    Some(temp_path),
+                    StatusCode::NO_CONTENT,
+                )
+            }
             Some(offset) if offset == size => (
                 fs::OpenOptions::new().append(true).open(path).await?,
+                None,
                 StatusCode::NO_CONTENT,
             ),
             Some(offset) => {
                 let mut file = fs::OpenOptions::new().write(true).open(path).await?;
                 file.seek(SeekFrom::Start(offset)).await?;
-                (file, StatusCode::NO_CONTENT)
+                (file, None, StatusCode::NO_CONTENT)
             }
         };
-        let stream = IncomingStream::new(req.into_body());
+        let mut head = vec![];
+        let mut too_large = false;
+        let mut ret = {
+            let mut received = 0;
+            let stream = throttle.stream(IncomingStream::new(req.into_body()));
+            let stream = stream.and_then(|chunk| {
+                received += chunk.len() as u64;
+                if max_size.is_some_and(|v| received > v) {
+                    too_large = true;
+                    return future::ready(Err(anyhow!("Upload too large")));
+                }
+                fill_head(&mut head, &chunk);
+                if let Some(digests) = digests.as_mut() {
+                    digests.update(&chunk);
+                }
+                future::ready(Ok(chunk))
+            });
 
-        let body_with_io_error = stream.map_err(io::Error::other);
-        let body_reader = StreamReader::new(body_with_io_error);
+            let body_with_io_error = stream.map_err(io::Error::other);
+            let body_reader = StreamReader::new(body_with_io_error);
 
-        pin_mut!(body_reader);
+            pin_mut!(body_reader);
 
-        let ret = io::copy(&mut body_reader, &mut file).await;
-        let size = fs::metadata(path)
-            .await
-            .map(|v| v.len())
-            .unwrap_or_default();
-        if ret.is_err() {
-            if upload_offset.is_none() && size < RESUMABLE_UPLOAD_MIN_SIZE {
-                let _ = tokio::fs::remove_file(&path).await;
+            io::copy(&mut body_reader, &mut file).await
+        };
+        // A staged partial upload is synced as it's copied into the file
+        let fsync = self.args.upload_fsync && (upload_offset.is_none() || temp_path.is_none());
+        if ret.is_ok() {
+            if let Err(err) = file.flush().await {
+                ret = Err(err);
+            } else if fsync {
+                if let Err(err) = file.sync_all().await {
+                    ret = Err(err);
+                }
+            }
+        }
+        drop(file);
+        if let (Ok(bytes), Some(audit)) = (&ret, res.extensions_mut().get_mut::<Audit>()) {
+            audit.bytes = Some(*bytes);
+        }
+        let allowed = upload_offset.is_some() || allow_upload_type(limit, name, Some(&head));
+        if too_large || (ret.is_ok() && !allowed) {
+            match &temp_path {
+                Some(temp_path) => {
+                    let _ = fs::remove_file(temp_path).await;
+                }
+                None => {
+                    let file = fs::OpenOptions::new().write(true).open(path).await?;
+                    file.set_len(size).await?;
+                }
+            }
+            if too_large {
+                status_payload_too_large(res, limit);
+            } else {
+                status_unsupported_type(res, name);
             }
+            return Ok(());
+        }
+
+        let Some(temp_path) = temp_path else {
             ret?;
+            apply_upload_mtime(path, mtime, res).await;
+            *res.status_mut() = status;
+            return Ok(());
+        };
+        let old_meta = fs::metadata(path).await.ok();
+        if let Err(err) = ret {
+            let partial_size = fs::metadata(&temp_path)
+                .await
+                .map(|v| v.len())
+                .unwrap_or_default();
+            // Keep a large partial upload of a new file so that it can be resumed with PATCH
+            if old_meta.is_none() && partial_size >= RESUMABLE_UPLOAD_MIN_SIZE && digests.is_none()
+            {
+                fs::rename(&temp_path, path).await?;
+            } else {
+                let _ = fs::remove_file(&temp_path).await;
+            }
+            return Err(err.into());
+        }
+        if let Some(digests) = digests {
+            let outcome = digests.finish();
+            if !outcome.matched {
+                let _ = fs::remove_file(&temp_path).await;
+                status_bad_request(res, "Digest mismatch");
+                return Ok(());
+            }
+            res.headers_mut()
+                .insert("content-digest", outcome.content_digest.parse()?);
+        }
+        let ret = match upload_offset {
+            None => {
+                if let Some(meta) = old_meta {
+                    let _ = fs::set_permissions(&temp_path, meta.permissions()).await;
+                }
+                match resolve_upload_conflict(path, limit.conflict).await {
+                    Ok(dest) => {
+                        set_upload_name(&dest, res);
+                        fs::rename(&temp_path, &dest).await.map(|_| dest)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Some(offset) => copy_into(&temp_path, path, offset, self.args.upload_fsync)
+                .await
+                .map(|_| path.to_path_buf()),
+        };
+        if ret.is_err() || upload_offset.is_some() {
+            let _ = fs::remove_file(&temp_path).await;
         }
+        apply_upload_mtime(&ret?, mtime, res).await;
 
         *res.status_mut() = status;
 
         Ok(())
     }
 
-    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
-        match is_dir {
-            true => fs::remove_dir_all(path).await?,
-            false => fs::remove_file(path).await?,
-        }
-
-        status_no_content(res);
-        Ok(())
-    }
-
-    async fn handle_ls_dir(
+    /// Store the files of a `multipart/form-data` body in the directory `path`.
+    ///
+    /// Filenames may contain a relative path, as sent when uploading a folder.
+    async fn handle_form_upload(
         &self,
         path: &Path,
-        exist: bool,
-        query_params: &HashMap<String, String>,
-        head_only: bool,
-        user: Option<String>,
+        dir_relative_path: &str,
+        boundary: &str,
         access_paths: AccessPaths,
+        req: Request,
+        throttle: &Throttle,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths = vec![];
-        if !head_only && exist {
-            paths = match self.list_dir(path, path, access_paths.clone()).await {
-                Ok(paths) => paths,
-                Err(_) => {
+        if !is_same_origin(req.headers(), self.args.cors.as_ref()) {
+            // Forms can be submitted cross-site with the browser's credentials
+            status_forbid(res);
+            return Ok(());
+        }
+        let is_html = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/html"));
+        let location = req.uri().to_string();
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let conflict = match upload_conflict(req.headers(), &query_params) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let mut multipart = Multipart::new(
+            throttle.stream(IncomingStream::new(req.into_body())),
+            boundary,
+        );
+        let mut total_bytes = 0;
+        loop {
+            let part = match multipart.next_part().await {
+                Ok(Some(v)) => v,
+                Ok(None) => break,
+                Err(err) => {
+                    status_bad_request(res, &err.to_string());
+                    return Ok(());
+                }
+            };
+            let Some(filename) = part.filename.filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            let Some(relative_path) = sanitize_upload_filename(&filename) else {
+                status_bad_request(res, &format!("Invalid filename `{filename}`"));
+                return Ok(());
+            };
+            let Some(target_paths) = access_paths.guard(&relative_path, &Method::PUT) else {
+                status_forbid(res);
+                return Ok(());
+            };
+            let mut file_path = path.to_path_buf();
+            file_path.extend(relative_path.split('/'));
+            let name = get_file_name(&file_path);
+            let mut limit = self
+                .args
+                .upload_limit(&format!("{dir_relative_path}/{relative_path}"));
+            if conflict.is_some() {
+                limit.conflict = conflict;
+            }
+            if target_paths.perm().writeonly() {
+                limit.conflict = Some(ConflictPolicy::Rename);
+            }
+            if !allow_upload_type(&limit, name, None) {
+                status_unsupported_type(res, name);
+                return Ok(());
+            }
+            if let Ok(meta) = fs::metadata(&file_path).await {
+                match limit.conflict.unwrap_or_default() {
+                    _ if meta.is_dir() => {
+                        status_forbid(res);
+                        return Ok(());
+                    }
+                    ConflictPolicy::Fail => {
+                        *res.status_mut() = StatusCode::CONFLICT;
+                        return Ok(());
+                    }
+                    ConflictPolicy::Overwrite if !self.args.allow_delete => {
+                        status_forbid(res);
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+            if !self.args.allow_symlink {
+                let mut existing = file_path.parent();
+                while let Some(dir) = existing {
+                    if fs::try_exists(dir).await.unwrap_or_default() {
+                        break;
+                    }
+                    existing = dir.parent();
+                }
+                if !self.is_root_contained(existing.unwrap_or(path)).await {
                     status_forbid(res);
                     return Ok(());
                 }
             }
-        };
-        self.send_index(
+
+            ensure_path_parent(&file_path).await?;
+            let temp_path = upload_temp_path(&file_path);
+            let mut head = vec![];
+            let ret = async {
+                let mut file = fs::File::create(&temp_path).await?;
+                let mut received = 0;
+                while let Some(chunk) = multipart.next_chunk().await? {
+                    received += chunk.len() as u64;
+                    if limit.max_size.is_some_and(|v| received > v) {
+                        return Ok(Some(StatusCode::PAYLOAD_TOO_LARGE));
+                    }
+                    fill_head(&mut head, &chunk);
+                    file.write_all(&chunk).await?;
+                }
+                if !allow_upload_type(&limit, name, Some(&head)) {
+                    return Ok(Some(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+                }
+                file.flush().await?;
+                if self.args.upload_fsync {
+                    file.sync_all().await?;
+                }
+                drop(file);
+                let dest = resolve_upload_conflict(&file_path, limit.conflict).await?;
+                fs::rename(&temp_path, &dest).await?;
+                add_upload_name(&dest, res);
+                total_bytes += received;
+                anyhow::Ok(None)
+            }
+            .await;
+            match ret {
+                Ok(None) => {}
+                Ok(Some(status)) => {
+                    let _ = fs::remove_file(&temp_path).await;
+                    if status == StatusCode::PAYLOAD_TOO_LARGE {
+                        status_payload_too_large(res, &limit);
+                    } else {
+                        status_unsupported_type(res, name);
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    let _ = fs::remove_file(&temp_path).await;
+                    return Err(err);
+                }
+            }
+        }
+        if let Some(audit) = res.extensions_mut().get_mut::<Audit>() {
+            audit.bytes = Some(total_bytes);
+        }
+
+        if is_html {
+            // Back to the listing the form was submitted from
+            *res.status_mut() = StatusCode::SEE_OTHER;
+            res.headers_mut().insert(LOCATION, location.parse()?);
+        } else {
+            *res.status_mut() = StatusCode::CREATED;
+        }
+        Ok(())
+    }
+
+    async fn handle_extract(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        access_paths: AccessPaths,
+        headers: &HeaderMap<HeaderValue>,
+        query_params: &HashMap<String, String>,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !is_same_origin(headers, self.args.cors.as_ref()) {
+            status_forbid(res);
+            return Ok(());
+        }
+        let name = get_file_name(path);
+        let Some((format, stem)) = ArchiveFormat::from_name(name) else {
+            *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            *res.body_mut() =
+                body_full(format!("Cannot extract `{name}`, not a zip or tar archive"));
+            return Ok(());
+        };
+        let parent_relative_path = relative_path
+            .rsplit_once('/')
+            .map(|(v, _)| v)
+            .unwrap_or_default();
+        let dest_relative_path = join_relative(parent_relative_path, stem);
+        if access_paths
+            .guard(&dest_relative_path, &Method::PUT)
+            .is_none()
+        {
+            status_forbid(res);
+            return Ok(());
+        }
+        let mut limit = self.args.upload_limit(&dest_relative_path);
+        match upload_conflict(headers, query_params) {
+            Ok(Some(conflict)) => limit.conflict = Some(conflict),
+            Ok(None) => {}
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        }
+        let conflict = limit.conflict.unwrap_or_default();
+        let dest = path.with_file_name(stem);
+        let dest_meta = fs::symlink_metadata(&dest).await.ok();
+        if dest_meta.is_some() {
+            match conflict {
+                ConflictPolicy::Fail => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                ConflictPolicy::Overwrite if !self.args.allow_delete => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        let limits = extract_limits(&self.args);
+        let check = |entry_path: &str| {
+            let entry_relative_path = format!("{dest_relative_path}/{entry_path}");
+            if access_paths
+                .guard(&entry_relative_path, &Method::PUT)
+                .is_none()
+            {
+                return Err(Rejected::new(
+                    StatusCode::FORBIDDEN,
+                    format!("Not allowed to write `{entry_path}`"),
+                ));
+            }
+            let limit = self.args.upload_limit(&entry_relative_path);
+            let entry_name = entry_path.rsplit('/').next().unwrap_or_default();
+            if !allow_upload_type(&limit, entry_name, None) {
+                return Err(Rejected::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("File type of `{entry_name}` is not allowed"),
+                ));
+            }
+            Ok(limit.max_size)
+        };
+        // Unpack next to the destination and move it into place once complete
+        let temp_dir = upload_temp_path(&dest);
+        fs::create_dir(&temp_dir).await?;
+        let ret = async {
+            let extracted = extract(path, format, &temp_dir, limits, check).await?;
+            let dest = match dest_meta {
+                Some(meta) if conflict == ConflictPolicy::Overwrite => {
+                    if meta.is_dir() {
+                        fs::remove_dir_all(&dest).await?;
+                    } else {
+                        fs::remove_file(&dest).await?;
+                    }
+                    dest
+                }
+                _ => resolve_upload_conflict(&dest, Some(conflict)).await?,
+            };
+            fs::rename(&temp_dir, &dest).await?;
+            anyhow::Ok((dest, extracted))
+        }
+        .await;
+        let (dest, extracted) = match ret {
+            Ok(v) => v,
+            Err(err) => {
+                let _ = fs::remove_dir_all(&temp_dir).await;
+                return match err.downcast::<Rejected>() {
+                    Ok(rejected) => {
+                        *res.status_mut() = rejected.status;
+                        *res.body_mut() = body_full(rejected.message);
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                };
+            }
+        };
+        if let Some(audit) = res.extensions_mut().get_mut::<Audit>() {
+            audit.bytes = Some(extracted.size);
+        }
+        let location = format!(
+            "{}{}/",
+            self.args.uri_prefix,
+            encode_uri(&join_relative(parent_relative_path, get_file_name(&dest)))
+        );
+        *res.status_mut() = StatusCode::CREATED;
+        res.headers_mut().insert(LOCATION, location.parse()?);
+        set_upload_name(&dest, res);
+        Ok(())
+    }
+
+    async fn handle_tus_create(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        is_dir: bool,
+        is_miss: bool,
+        write_only: bool,
+        access_paths: &AccessPaths,
+        headers: &HeaderMap<HeaderValue>,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !check_tus_resumable(headers, res) {
+            return Ok(());
+        }
+        if !self.args.allow_upload {
+            status_forbid(res);
+            return Ok(());
+        }
+        if !is_dir && !is_miss {
+            *res.status_mut() = StatusCode::CONFLICT;
+            return Ok(());
+        }
+        let Some(length) = headers
+            .get("upload-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+        else {
+            status_bad_request(res, "Invalid Upload-Length");
+            return Ok(());
+        };
+        let metadata = headers
+            .get("upload-metadata")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let Some(filename) = metadata
+            .as_deref()
+            .and_then(metadata_filename)
+            .filter(|v| !v.contains(['/', '\\']) && v != "." && v != "..")
+        else {
+            status_bad_request(res, "Invalid filename in Upload-Metadata");
+            return Ok(());
+        };
+        if access_paths.guard(&filename, &Method::PUT).is_none() {
+            status_forbid(res);
+            return Ok(());
+        }
+        let mut limit = self
+            .args
+            .upload_limit(&join_relative(relative_path, &filename));
+        match upload_conflict(headers, &HashMap::new()) {
+            Ok(Some(conflict)) => limit.conflict = Some(conflict),
+            Ok(None) => {}
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        }
+        if write_only {
+            limit.conflict = Some(ConflictPolicy::Rename);
+        }
+        if limit.max_size.is_some_and(|v| length > v) {
+            status_payload_too_large(res, &limit);
+            return Ok(());
+        }
+        if !allow_upload_type(&limit, &filename, None) {
+            status_unsupported_type(res, &filename);
+            return Ok(());
+        }
+        let mut target_path = path.join(&filename);
+        let conflict = limit.conflict.unwrap_or_default();
+        if let Ok(meta) = fs::metadata(&target_path).await {
+            match conflict {
+                _ if meta.is_dir() => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                ConflictPolicy::Fail => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                ConflictPolicy::Overwrite if !self.args.allow_delete => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+                ConflictPolicy::Rename => target_path = free_path(&target_path).await,
+                _ => {}
+            }
+        }
+        let target = join_relative(relative_path, get_file_name(&target_path));
+
+        let upload = TusUpload::new(target.clone(), length, metadata, conflict);
+        let id = self.tus.create(&upload).await?;
+        if length == 0 {
+            let dest = resolve_upload_conflict(&target_path, Some(conflict)).await?;
+            self.tus.finish(&target, &id, &dest).await?;
+        }
+        let location = format!("{}{}?tus={id}", self.args.uri_prefix, encode_uri(&target));
+        *res.status_mut() = StatusCode::CREATED;
+        res.headers_mut().insert(LOCATION, location.parse()?);
+        res.headers_mut()
+            .insert("upload-expires", upload.expires_header().parse()?);
+        Ok(())
+    }
+
+    async fn handle_tus_upload(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        id: &str,
+        access_paths: &AccessPaths,
+        req: Request,
+        throttle: &Throttle,
+        res: &mut Response,
+    ) -> Result<()> {
+        let headers = req.headers();
+        if !check_tus_resumable(headers, res) {
+            return Ok(());
+        }
+        if !self.args.allow_upload || access_paths.guard("", &Method::PUT).is_none() {
+            status_forbid(res);
+            return Ok(());
+        }
+        // Requests to the same upload take turns, a PATCH appends at the offset it was sent for
+        let _guard = self.tus.lock(id).await;
+        let Some(upload) = self.tus.get(relative_path, id).await else {
+            status_not_found(res);
+            return Ok(());
+        };
+        if upload.is_expired() {
+            self.tus.remove(relative_path, id).await;
+            *res.status_mut() = StatusCode::GONE;
+            return Ok(());
+        }
+        let part_path = self.tus.part_path(relative_path, id);
+        let offset = fs::metadata(&part_path).await?.len();
+        match req.method().as_str() {
+            "HEAD" => {
+                res.headers_mut()
+                    .insert("upload-offset", offset.to_string().parse()?);
+                res.headers_mut()
+                    .insert("upload-length", upload.length.to_string().parse()?);
+                if let Some(metadata) = upload.metadata.as_ref().and_then(|v| v.parse().ok()) {
+                    res.headers_mut().insert("upload-metadata", metadata);
+                }
+                res.headers_mut()
+                    .insert("upload-expires", upload.expires_header().parse()?);
+                res.headers_mut()
+                    .typed_insert(CacheControl::new().with_no_store());
+            }
+            "DELETE" => {
+                self.tus.remove(relative_path, id).await;
+                status_no_content(res);
+            }
+            "PATCH" => {
+                if headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) != Some(TUS_CONTENT_TYPE)
+                {
+                    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                    return Ok(());
+                }
+                let Some(upload_offset) = headers
+                    .get("upload-offset")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok())
+                else {
+                    status_bad_request(res, "Invalid Upload-Offset");
+                    return Ok(());
+                };
+                if upload_offset != offset {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                let mut checksum = match headers.get("upload-checksum") {
+                    Some(value) => match TusChecksum::parse(value.to_str().unwrap_or_default()) {
+                        Ok(v) => Some(v),
+                        Err(err) => {
+                            status_bad_request(res, &err.to_string());
+                            return Ok(());
+                        }
+                    },
+                    None => None,
+                };
+
+                let mut file = fs::OpenOptions::new().append(true).open(&part_path).await?;
+                let mut stream = throttle.stream(IncomingStream::new(req.into_body()));
+                let mut new_offset = offset;
+                let mut ret = Ok(());
+                loop {
+                    let chunk = match stream.try_next().await {
+                        Ok(Some(v)) => v,
+                        Ok(None) => break,
+                        Err(err) => {
+                            ret = Err(err);
+                            break;
+                        }
+                    };
+                    if new_offset + chunk.len() as u64 > upload.length {
+                        file.set_len(offset).await?;
+                        status_bad_request(res, "Upload exceeds Upload-Length");
+                        return Ok(());
+                    }
+                    file.write_all(&chunk).await?;
+                    if let Some(checksum) = checksum.as_mut() {
+                        checksum.update(&chunk);
+                    }
+                    new_offset += chunk.len() as u64;
+                }
+                file.flush().await?;
+                if self.args.upload_fsync {
+                    file.sync_all().await?;
+                }
+                if let Some(checksum) = checksum {
+                    // A chunk is only kept if it's complete and matches its checksum
+                    if ret.is_err() || !checksum.verify() {
+                        file.set_len(offset).await?;
+                        ret?;
+                        *res.status_mut() = StatusCode::from_u16(460)?;
+                        return Ok(());
+                    }
+                }
+                drop(file);
+                if let Some(audit) = res.extensions_mut().get_mut::<Audit>() {
+                    audit.bytes = Some(new_offset - offset);
+                }
+                ret?;
+                if new_offset == upload.length {
+                    let mut head = vec![];
+                    fs::File::open(&part_path)
+                        .await?
+                        .take(SNIFF_SIZE as u64)
+                        .read_to_end(&mut head)
+                        .await?;
+                    let name = get_file_name(path);
+                    if !allow_upload_type(&self.args.upload_limit(relative_path), name, Some(&head))
+                    {
+                        self.tus.remove(relative_path, id).await;
+                        status_unsupported_type(res, name);
+                        return Ok(());
+                    }
+                    let Some(dest) = self.finish_upload_path(path, upload.conflict, res).await?
+                    else {
+                        return Ok(());
+                    };
+                    self.tus.finish(relative_path, id, &dest).await?;
+                    set_upload_name(&dest, res);
+                }
+                status_no_content(res);
+                res.headers_mut()
+                    .insert("upload-offset", new_offset.to_string().parse()?);
+                res.headers_mut()
+                    .insert("upload-expires", upload.expires_header().parse()?);
+            }
+            _ => {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where a resumable upload completing at `path` goes. Its conflict policy is applied again,
+    /// another upload may have taken the name since it started. `None` once `res` tells why it
+    /// can't complete, the upload is kept so that it can be completed later.
+    async fn finish_upload_path(
+        &self,
+        path: &Path,
+        conflict: ConflictPolicy,
+        res: &mut Response,
+    ) -> Result<Option<PathBuf>> {
+        if let Ok(meta) = fs::symlink_metadata(path).await {
+            match conflict {
+                _ if meta.is_dir() => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(None);
+                }
+                ConflictPolicy::Fail => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(None);
+                }
+                ConflictPolicy::Overwrite if !self.args.allow_delete => {
+                    status_forbid(res);
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(resolve_upload_conflict(path, Some(conflict)).await?))
+    }
+
+    async fn handle_session_create(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        is_dir: bool,
+        write_only: bool,
+        access_paths: &AccessPaths,
+        headers: &HeaderMap<HeaderValue>,
+        query_params: &HashMap<String, String>,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !self.args.allow_upload || is_dir || access_paths.guard("", &Method::PUT).is_none() {
+            status_forbid(res);
+            return Ok(());
+        }
+        let Some(length) = headers
+            .get(X_UPLOAD_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+        else {
+            status_bad_request(res, "Invalid X-Upload-Length");
+            return Ok(());
+        };
+        let mut limit = self.args.upload_limit(relative_path);
+        match upload_conflict(headers, query_params) {
+            Ok(Some(conflict)) => limit.conflict = Some(conflict),
+            Ok(None) => {}
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        }
+        if write_only {
+            limit.conflict = Some(ConflictPolicy::Rename);
+        }
+        if limit.max_size.is_some_and(|v| length > v) {
+            status_payload_too_large(res, &limit);
+            return Ok(());
+        }
+        let name = get_file_name(path);
+        if !allow_upload_type(&limit, name, None) {
+            status_unsupported_type(res, name);
+            return Ok(());
+        }
+        if fs::symlink_metadata(path).await.is_ok() {
+            match limit.conflict.unwrap_or_default() {
+                ConflictPolicy::Fail => {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    return Ok(());
+                }
+                ConflictPolicy::Overwrite if !self.args.allow_delete => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        let session = UploadSession::new(relative_path.to_string(), length, limit.conflict);
+        let id = self.sessions.create(&session).await?;
+        let location = format!(
+            "{}{}?session={id}",
+            self.args.uri_prefix,
+            encode_uri(relative_path)
+        );
+        *res.status_mut() = StatusCode::CREATED;
+        res.headers_mut().insert(LOCATION, location.parse()?);
+        Ok(())
+    }
+
+    /// Status (HEAD), chunk upload (PUT), commit (POST) and abort (DELETE) of an upload session.
+    async fn handle_session(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        id: &str,
+        access_paths: &AccessPaths,
+        req: Request,
+        throttle: &Throttle,
+        res: &mut Response,
+    ) -> Result<()> {
+        if !self.args.allow_upload || access_paths.guard("", &Method::PUT).is_none() {
+            status_forbid(res);
+            return Ok(());
+        }
+        // Chunks are written in parallel, a commit or abort waits for them and keeps them out
+        let _guard = match req.method().as_str() {
+            "PUT" => (Some(self.sessions.lock_shared(id).await), None),
+            "POST" | "DELETE" => (None, Some(self.sessions.lock(id).await)),
+            _ => (None, None),
+        };
+        let Some(session) = self.sessions.get(relative_path, id).await else {
+            status_not_found(res);
+            return Ok(());
+        };
+        if session.is_expired() {
+            self.sessions.remove(relative_path, id).await;
+            *res.status_mut() = StatusCode::GONE;
+            return Ok(());
+        }
+        res.headers_mut()
+            .insert("cache-control", HeaderValue::from_static("no-store"));
+        res.headers_mut()
+            .insert(X_UPLOAD_LENGTH, session.length.to_string().parse()?);
+        res.headers_mut()
+            .insert(X_UPLOAD_RECEIVED, session.received_header().parse()?);
+        match req.method().as_str() {
+            "HEAD" => {}
+            "DELETE" => {
+                self.sessions.remove(relative_path, id).await;
+                status_no_content(res);
+            }
+            "PUT" => {
+                let start = match parse_upload_offset(req.headers(), session.length) {
+                    Ok(Some(v)) => v,
+                    Ok(None) => {
+                        status_bad_request(res, "Missing X-Update-Range");
+                        return Ok(());
+                    }
+                    // Also a range outside of the session's length
+                    Err(err) => {
+                        *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                        *res.body_mut() = body_full(err.to_string());
+                        return Ok(());
+                    }
+                };
+                let max_size = session.length.saturating_sub(start);
+                let content_length = req
+                    .headers()
+                    .typed_get::<ContentLength>()
+                    .map(|v| v.0)
+                    .unwrap_or_default();
+                if content_length > max_size {
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    return Ok(());
+                }
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .open(self.sessions.part_path(relative_path, id))
+                    .await?;
+                file.seek(SeekFrom::Start(start)).await?;
+                let mut too_large = false;
+                let ret = {
+                    let mut received = 0;
+                    let stream = throttle.stream(IncomingStream::new(req.into_body()));
+                    let stream = stream.and_then(|chunk| {
+                        received += chunk.len() as u64;
+                        if received > max_size {
+                            too_large = true;
+                            return future::ready(Err(anyhow!("Chunk exceeds X-Upload-Length")));
+                        }
+                        future::ready(Ok(chunk))
+                    });
+                    let body_reader = StreamReader::new(stream.map_err(io::Error::other));
+                    pin_mut!(body_reader);
+                    io::copy(&mut body_reader, &mut file).await
+                };
+                file.flush().await?;
+                drop(file);
+                if too_large {
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    return Ok(());
+                }
+                // Only a complete chunk counts as received
+                let written = ret?;
+                if let Some(audit) = res.extensions_mut().get_mut::<Audit>() {
+                    audit.bytes = Some(written);
+                }
+                if let Some(session) = self
+                    .sessions
+                    .add_range(relative_path, id, start, start + written)
+                    .await?
+                {
+                    res.headers_mut()
+                        .insert(X_UPLOAD_RECEIVED, session.received_header().parse()?);
+                }
+                status_no_content(res);
+            }
+            "POST" => {
+                if !session.is_complete() {
+                    *res.status_mut() = StatusCode::CONFLICT;
+                    *res.body_mut() = body_full("Upload session is incomplete");
+                    return Ok(());
+                }
+                let part_path = self.sessions.part_path(relative_path, id);
+                let file = fs::OpenOptions::new().write(true).open(&part_path).await?;
+                file.set_len(session.length).await?;
+                if self.args.upload_fsync {
+                    file.sync_all().await?;
+                }
+                drop(file);
+                let mut head = vec![];
+                fs::File::open(&part_path)
+                    .await?
+                    .take(SNIFF_SIZE as u64)
+                    .read_to_end(&mut head)
+                    .await?;
+                let name = get_file_name(path);
+                if !allow_upload_type(&self.args.upload_limit(relative_path), name, Some(&head)) {
+                    self.sessions.remove(relative_path, id).await;
+                    status_unsupported_type(res, name);
+                    return Ok(());
+                }
+                let (digests, mtime) = match UploadDigests::from_headers(req.headers(), true)
+                    .and_then(|v| Ok((v, upload_mtime(req.headers())?)))
+                {
+                    Ok(v) => v,
+                    Err(err) => {
+                        status_bad_request(res, &err.to_string());
+                        return Ok(());
+                    }
+                };
+                if let Some(mut digests) = digests {
+                    hash_file(&part_path, |data| digests.update(data)).await?;
+                    let outcome = digests.finish();
+                    if !outcome.matched {
+                        status_bad_request(res, "Digest mismatch");
+                        return Ok(());
+                    }
+                    res.headers_mut()
+                        .insert("content-digest", outcome.content_digest.parse()?);
+                }
+                let conflict = session.conflict.unwrap_or_default();
+                let Some(dest) = self.finish_upload_path(path, conflict, res).await? else {
+                    return Ok(());
+                };
+                self.sessions.finish(relative_path, id, &dest).await?;
+                apply_upload_mtime(&dest, mtime, res).await;
+                set_upload_name(&dest, res);
+                res.headers_mut().remove(X_UPLOAD_RECEIVED);
+                *res.status_mut() = StatusCode::CREATED;
+            }
+            _ => {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
+        match is_dir {
+            true => fs::remove_dir_all(path).await?,
+            false => fs::remove_file(path).await?,
+        }
+
+        status_no_content(res);
+        Ok(())
+    }
+
+    async fn handle_ls_dir(
+        &self,
+        path: &Path,
+        exist: bool,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut paths = vec![];
+        if !head_only && exist {
+            paths = match self.list_dir(path, path, access_paths.clone()).await {
+                Ok(paths) => paths,
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            }
+        };
+        self.send_index(
             path,
             paths,
             exist,
@@ -596,109 +1972,549 @@ impl Server {
             head_only,
             user,
             access_paths,
+            false,
             res,
         )
     }
 
-    async fn handle_search_dir(
+    async fn handle_search_dir(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut paths: Vec<PathItem> = vec![];
+        let search = query_params
+            .get("q")
+            .ok_or_else(|| anyhow!("invalid q"))?
+            .to_lowercase();
+        if search.is_empty() {
+            return self
+                .handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
+                .await;
+        }
+
+        if !head_only {
+            let path_buf = path.to_path_buf();
+            let hidden = Arc::new(self.args.hidden.to_vec());
+            let search = search.clone();
+
+            let search_paths = tokio::spawn(collect_dir_entries(
+                access_paths.clone(),
+                self.running.clone(),
+                path_buf,
+                hidden,
+                self.args.allow_symlink,
+                self.args.serve_path.clone(),
+                move |x| get_file_name(x.path()).to_lowercase().contains(&search),
+            ))
+            .await?;
+
+            for search_path in search_paths.into_iter() {
+                if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
+                    paths.push(item);
+                }
+            }
+        }
+        self.send_index(
+            path,
+            paths,
+            true,
+            query_params,
+            head_only,
+            user,
+            access_paths,
+            false,
+            res,
+        )
+    }
+
+    async fn handle_archive_dir(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(format) = archive_query(query_params).and_then(ArchiveFormat::from_ext) else {
+            status_bad_request(res, "Invalid archive format");
+            return Ok(());
+        };
+        let keep_symlinks = has_query_flag(query_params, "symlinks");
+        let filename = try_get_file_name(path)?;
+        set_content_disposition(res, false, &format!("{filename}.{}", format.extension()))?;
+        res.headers_mut().insert(
+            "content-type",
+            HeaderValue::from_static(format.content_type()),
+        );
+        if format == ArchiveFormat::Zip && self.args.compress == Compress::None {
+            return self
+                .handle_stored_zip_dir(path, headers, head_only, access_paths, res)
+                .await;
+        }
+        if head_only {
+            return Ok(());
+        }
+        let path = path.to_owned();
+        let hidden = Arc::new(self.args.hidden.clone());
+        let running = self.running.clone();
+        let compression = self.args.compress.to_compression();
+        let follow_symlinks = self.args.allow_symlink;
+        let serve_path = self.args.serve_path.clone();
+        self.send_archive(res, move |mut writer| async move {
+            let paths = collect_dir_entries(
+                access_paths,
+                running,
+                path.clone(),
+                hidden,
+                follow_symlinks,
+                serve_path,
+                |x| x.path().symlink_metadata().is_ok(),
+            )
+            .await;
+            if let Err(e) = write_archive(
+                &mut writer,
+                format,
+                &path,
+                paths,
+                compression,
+                keep_symlinks,
+            )
+            .await
+            {
+                error!("Failed to archive {}, {e}", path.display());
+            }
+        });
+        Ok(())
+    }
+
+    /// Stream the archive `job` writes on the archive pool, or answer 503 if too many archives
+    /// wait for their turn.
+    fn send_archive<F, Fut>(&self, res: &mut Response, job: F)
+    where
+        F: FnOnce(SlotWriter<DuplexStream>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (writer, reader) = tokio::io::duplex(BUF_SIZE);
+        if !self.archive_pool.spawn(writer, job) {
+            res.headers_mut().remove(CONTENT_DISPOSITION);
+            res.headers_mut().remove(CONTENT_TYPE);
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("10"));
+            *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            *res.body_mut() = body_full("Too many archive downloads, try again later");
+            return;
+        }
+        let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
+        let stream_body = StreamBody::new(
+            reader_stream
+                .map_ok(Frame::data)
+                .map_err(|err| anyhow!("{err}")),
+        );
+        *res.body_mut() = stream_body.boxed();
+    }
+
+    /// Uncompressed zips are laid out up front, for an exact length and range requests.
+    async fn handle_stored_zip_dir(
+        &self,
+        path: &Path,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let paths = collect_dir_entries(
+            access_paths,
+            self.running.clone(),
+            path.to_path_buf(),
+            Arc::new(self.args.hidden.clone()),
+            self.args.allow_symlink,
+            self.args.serve_path.clone(),
+            |x| x.path().symlink_metadata().is_ok() && x.file_type().is_file(),
+        )
+        .await;
+        let zip = StoredZip::new(path, paths, self.hashes.clone()).await?;
+        let size = zip.len();
+        let etag = zip.etag().parse::<ETag>()?;
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            if !if_none_match.precondition_passes(&etag) {
+                *res.status_mut() = StatusCode::NOT_MODIFIED;
+                return Ok(());
+            }
+        }
+        res.headers_mut().typed_insert(etag.clone());
+        res.headers_mut().typed_insert(AcceptRanges::bytes());
+
+        let use_range = headers
+            .typed_get::<IfRange>()
+            .map(|if_range| !if_range.is_modified(Some(&etag), None))
+            .unwrap_or(true);
+        let ranges = match headers.get(RANGE) {
+            Some(range) if use_range => {
+                Some(range.to_str().ok().and_then(|v| parse_range(v, size)))
+            }
+            _ => None,
+        };
+        let (start, end) = match ranges {
+            Some(Some(ranges)) if ranges.len() == 1 => {
+                let (start, end) = ranges[0];
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{size}").parse()?,
+                );
+                (start, end)
+            }
+            Some(None) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                return Ok(());
+            }
+            // Several ranges get the whole archive
+            _ => (0, size - 1),
+        };
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, format!("{}", end - start + 1).parse()?);
+        if head_only {
+            return Ok(());
+        }
+        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
+        let path = path.to_owned();
+        tokio::spawn(async move {
+            if let Err(e) = zip.write_range(&mut writer, start, end).await {
+                error!("Failed to zip {}, {e}", path.display());
+            }
+        });
+        let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
+        let stream_body = StreamBody::new(
+            reader_stream
+                .map_ok(Frame::data)
+                .map_err(|err| anyhow!("{err}")),
+        );
+        let boxed_body = stream_body.boxed();
+        *res.body_mut() = boxed_body;
+        Ok(())
+    }
+
+    async fn handle_archive_entry(
         &self,
-        path: &Path,
+        archive_path: &str,
+        name: &str,
+        method: &Method,
+        headers: &HeaderMap<HeaderValue>,
         query_params: &HashMap<String, String>,
         head_only: bool,
         user: Option<String>,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths: Vec<PathItem> = vec![];
-        let search = query_params
-            .get("q")
-            .ok_or_else(|| anyhow!("invalid q"))?
-            .to_lowercase();
-        if search.is_empty() {
-            return self
-                .handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
-                .await;
+        let is_propfind = method.as_str() == "PROPFIND";
+        if !matches!(*method, Method::GET | Method::HEAD) && !is_propfind {
+            *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return Ok(());
         }
+        if access_paths.perm().writeonly() {
+            status_forbid(res);
+            return Ok(());
+        }
+        let Some(archive) = self.join_path(archive_path) else {
+            status_forbid(res);
+            return Ok(());
+        };
+        let format = ArchiveFormat::from_name(get_file_name(&archive)).map(|(v, _)| v);
+        let is_file = fs::metadata(&archive).await.is_ok_and(|v| v.is_file());
+        let (Some(format), Some(name), true) = (format, normalize_name(name), is_file) else {
+            self.handle_not_found(query_params, headers, head_only, res)
+                .await?;
+            return Ok(());
+        };
+        if self.guard_root_contained(&archive).await {
+            self.handle_not_found(query_params, headers, head_only, res)
+                .await?;
+            return Ok(());
+        }
+        let listing = match self.listings.get(&archive, format).await {
+            Ok(v) => v,
+            Err(err) => {
+                match err.downcast::<Rejected>() {
+                    Ok(rejected) => {
+                        *res.status_mut() = rejected.status;
+                        *res.body_mut() = body_full(rejected.message);
+                    }
+                    Err(err) => status_bad_request(res, &format!("Invalid archive, {err}")),
+                }
+                return Ok(());
+            }
+        };
+        let Some(entry) = listing.get(&name) else {
+            self.handle_not_found(query_params, headers, head_only, res)
+                .await?;
+            return Ok(());
+        };
+        // Listings always link with the `!` form, whichever form was requested
+        let href = match name.is_empty() {
+            true => format!("{archive_path}/!"),
+            false => format!("{archive_path}/!/{name}"),
+        };
+        let children = || {
+            listing
+                .children(&name)
+                .filter(|v| !is_hidden(&self.args.hidden, v.base_name(), v.is_dir))
+        };
+        if is_propfind {
+            let Some(depth) = propfind_depth(headers, res) else {
+                return Ok(());
+            };
+            let mut paths = vec![archive_pathitem(entry, href.clone())];
+            if depth == 1 && entry.is_dir {
+                paths.extend(
+                    children().map(|v| archive_pathitem(v, format!("{href}/{}", v.base_name()))),
+                );
+            }
+            let output = paths
+                .iter()
+                .map(|v| v.to_dav_xml(self.args.uri_prefix.as_str()))
+                .collect::<String>();
+            res_multistatus(res, &output);
+        } else if entry.is_dir {
+            let paths = match head_only {
+                true => vec![],
+                false => children()
+                    .map(|v| archive_pathitem(v, v.base_name().to_string()))
+                    .collect(),
+            };
+            self.send_index(
+                &self.args.serve_path.join(&href),
+                paths,
+                true,
+                query_params,
+                head_only,
+                user,
+                access_paths,
+                true,
+                res,
+            )?;
+        } else {
+            self.handle_send_archive_entry(&listing, entry, headers, head_only, res)
+                .await?;
+        }
+        Ok(())
+    }
 
-        if !head_only {
-            let path_buf = path.to_path_buf();
-            let hidden = Arc::new(self.args.hidden.to_vec());
-            let search = search.clone();
-
-            let search_paths = tokio::spawn(collect_dir_entries(
-                access_paths.clone(),
-                self.running.clone(),
-                path_buf,
-                hidden,
-                self.args.allow_symlink,
-                self.args.serve_path.clone(),
-                move |x| get_file_name(x.path()).to_lowercase().contains(&search),
-            ))
-            .await?;
+    /// Stream a file out of an archive, ranges are served for entries stored as-is.
+    async fn handle_send_archive_entry(
+        &self,
+        listing: &ArchiveListing,
+        entry: &ArchiveEntry,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let archive = listing.path();
+        let meta = fs::metadata(archive).await?;
+        let size = entry.size;
+        // The entry changes whenever the archive does
+        let mtime = meta.modified()?;
+        let etag =
+            format!(r#""{}-{}-{size}""#, to_timestamp(&mtime), meta.len()).parse::<ETag>()?;
+        let last_modified = LastModified::from(mtime);
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            if !if_none_match.precondition_passes(&etag) {
+                *res.status_mut() = StatusCode::NOT_MODIFIED;
+                return Ok(());
+            }
+        }
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        res.headers_mut().typed_insert(last_modified);
+        res.headers_mut().typed_insert(etag.clone());
 
-            for search_path in search_paths.into_iter() {
-                if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
-                    paths.push(item);
+        let content_type = mime_guess::from_path(&entry.name)
+            .first_or_octet_stream()
+            .to_string();
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+        self.set_file_disposition(res, &content_type, entry.base_name())?;
+
+        let mut start = 0;
+        let mut len = size;
+        if entry.is_seekable() {
+            res.headers_mut().typed_insert(AcceptRanges::bytes());
+            let use_range = headers
+                .typed_get::<IfRange>()
+                .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
+                .unwrap_or(true);
+            let ranges = match headers.get(RANGE) {
+                Some(range) if use_range => {
+                    Some(range.to_str().ok().and_then(|v| parse_range(v, size)))
+                }
+                _ => None,
+            };
+            match ranges {
+                Some(Some(ranges)) if ranges.len() == 1 => {
+                    let end;
+                    (start, end) = ranges[0];
+                    len = end - start + 1;
+                    *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    res.headers_mut().insert(
+                        CONTENT_RANGE,
+                        format!("bytes {start}-{end}/{size}").parse()?,
+                    );
+                }
+                Some(None) => {
+                    *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                    res.headers_mut()
+                        .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                    return Ok(());
                 }
+                // Several ranges get the whole entry
+                _ => {}
             }
+        } else {
+            res.headers_mut().typed_insert(AcceptRanges::none());
         }
-        self.send_index(
-            path,
-            paths,
-            true,
-            query_params,
-            head_only,
-            user,
-            access_paths,
-            res,
-        )
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, format!("{len}").parse()?);
+        if head_only {
+            return Ok(());
+        }
+        let mut entry_reader = listing.open(entry, start).await?.take(len);
+        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
+        let archive = archive.to_owned();
+        let name = entry.name.clone();
+        tokio::spawn(async move {
+            if let Err(e) = io::copy(&mut entry_reader, &mut writer).await {
+                error!("Failed to read {name} from {}, {e}", archive.display());
+            }
+        });
+        let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
+        let stream_body = StreamBody::new(
+            reader_stream
+                .map_ok(Frame::data)
+                .map_err(|err| anyhow!("{err}")),
+        );
+        *res.body_mut() = stream_body.boxed();
+        Ok(())
     }
 
-    async fn handle_zip_dir(
+    async fn handle_archive_selected(
         &self,
         path: &Path,
-        head_only: bool,
+        query_params: &HashMap<String, String>,
         access_paths: AccessPaths,
+        req: Request,
         res: &mut Response,
     ) -> Result<()> {
-        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
-        let filename = try_get_file_name(path)?;
-        set_content_disposition(res, false, &format!("{filename}.zip"))?;
-        res.headers_mut()
-            .insert("content-type", HeaderValue::from_static("application/zip"));
-        if head_only {
+        let Some(format) = archive_query(query_params).and_then(ArchiveFormat::from_ext) else {
+            status_bad_request(res, "Invalid archive format");
+            return Ok(());
+        };
+        let keep_symlinks = has_query_flag(query_params, "symlinks");
+        let body = match Limited::new(req.into_body(), ARCHIVE_SELECTION_MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(v) => v.to_bytes(),
+            Err(_) => {
+                status_bad_request(res, "Invalid selection");
+                return Ok(());
+            }
+        };
+        let mut selected: Vec<(PathBuf, bool, AccessPaths)> = vec![];
+        for (key, name) in form_urlencoded::parse(&body) {
+            if key != "path" {
+                continue;
+            }
+            let Some(parts) = selected_parts(&name) else {
+                status_bad_request(res, &format!("Invalid path '{name}'"));
+                return Ok(());
+            };
+            // Every selection is checked on its own, the folder being readable isn't enough
+            let item_access_paths = match access_paths.guard(&parts.join("/"), &Method::GET) {
+                Some(v) if !v.perm().writeonly() => v,
+                _ => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            };
+            let item_path = parts.iter().fold(path.to_path_buf(), |acc, v| acc.join(v));
+            let is_dir = match fs::metadata(&item_path).await {
+                Ok(meta) => meta.is_dir(),
+                Err(_) => {
+                    status_not_found(res);
+                    return Ok(());
+                }
+            };
+            let last = parts.len() - 1;
+            if parts
+                .iter()
+                .enumerate()
+                .any(|(i, v)| is_hidden(&self.args.hidden, v, i < last || is_dir))
+                || self.guard_root_contained(&item_path).await
+            {
+                status_not_found(res);
+                return Ok(());
+            }
+            selected.push((item_path, is_dir, item_access_paths));
+        }
+        if selected.is_empty() {
+            status_bad_request(res, "No paths selected");
             return Ok(());
         }
+        let filename = try_get_file_name(path)?;
+        set_content_disposition(res, false, &format!("{filename}.{}", format.extension()))?;
+        res.headers_mut().insert(
+            "content-type",
+            HeaderValue::from_static(format.content_type()),
+        );
         let path = path.to_owned();
-        let hidden = self.args.hidden.clone();
+        let hidden = Arc::new(self.args.hidden.clone());
         let running = self.running.clone();
         let compression = self.args.compress.to_compression();
         let follow_symlinks = self.args.allow_symlink;
         let serve_path = self.args.serve_path.clone();
-        tokio::spawn(async move {
-            if let Err(e) = zip_dir(
+        self.send_archive(res, move |mut writer| async move {
+            let mut paths = vec![];
+            for (item_path, is_dir, item_access_paths) in selected {
+                if is_dir {
+                    let entries = collect_dir_entries(
+                        item_access_paths,
+                        running.clone(),
+                        item_path.clone(),
+                        hidden.clone(),
+                        follow_symlinks,
+                        serve_path.clone(),
+                        |x| x.path().symlink_metadata().is_ok(),
+                    )
+                    .await;
+                    paths.push(item_path);
+                    paths.extend(entries);
+                } else {
+                    paths.push(item_path);
+                }
+            }
+            // Overlapping selections, e.g. a folder and a file inside it
+            paths.sort();
+            paths.dedup();
+            if let Err(e) = write_archive(
                 &mut writer,
+                format,
                 &path,
-                access_paths,
-                &hidden,
+                paths,
                 compression,
-                follow_symlinks,
-                serve_path,
-                running,
+                keep_symlinks,
             )
             .await
             {
-                error!("Failed to zip {}, {e}", path.display());
+                error!("Failed to archive selection in {}, {e}", path.display());
             }
         });
-        let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
-        let stream_body = StreamBody::new(
-            reader_stream
-                .map_ok(Frame::data)
-                .map_err(|err| anyhow!("{err}")),
-        );
-        let boxed_body = stream_body.boxed();
-        *res.body_mut() = boxed_body;
         Ok(())
     }
 
@@ -840,10 +2656,6 @@ impl Server {
                 "cache-control",
                 HeaderValue::from_static("public, max-age=31536000, immutable"),
             );
-            res.headers_mut().insert(
-                "x-content-type-options",
-                HeaderValue::from_static("nosniff"),
-            );
             Ok(true)
         } else if req_path == HEALTH_CHECK_PATH {
             res.headers_mut()
@@ -863,11 +2675,37 @@ impl Server {
         head_only: bool,
         res: &mut Response,
     ) -> Result<()> {
-        let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
+        // Static sites may ship compressed copies of their files like `app.js.br`
+        let static_site =
+            self.args.render_index || self.args.render_try_index || self.args.render_spa;
+        let precompressed = match static_site {
+            true => self.find_precompressed(path, headers).await,
+            false => None,
+        };
+        let file_path = precompressed
+            .as_ref()
+            .map(|(v, _)| v.as_path())
+            .unwrap_or(path);
+        let (file, meta) = tokio::join!(fs::File::open(file_path), fs::metadata(file_path),);
         let (mut file, meta) = (file?, meta?);
+        if static_site {
+            add_vary(res, "accept-encoding");
+        }
+        if let Some((_, encoding)) = precompressed {
+            res.headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
         let size = meta.len();
         let mut use_range = true;
-        if let Some((etag, last_modified)) = extract_cache_headers(&meta) {
+        let cache_headers = match self.args.etag {
+            EtagMode::Mtime => extract_cache_headers(&meta),
+            EtagMode::Content => {
+                let sha256 = self.hashes.sha256(file_path, &meta).await?;
+                extract_cache_headers(&meta)
+                    .and_then(|(_, v)| Some((format!(r#""{sha256}""#).parse().ok()?, v)))
+            }
+        };
+        if let Some((etag, last_modified)) = cache_headers {
             if let Some(if_unmodified_since) = headers.typed_get::<IfUnmodifiedSince>() {
                 if !if_unmodified_since.precondition_passes(last_modified.into()) {
                     *res.status_mut() = StatusCode::PRECONDITION_FAILED;
@@ -915,18 +2753,19 @@ impl Server {
                     .to_str()
                     .ok()
                     .and_then(|range| parse_range(range, size))
+                    .map(coalesce_ranges)
             })
         } else {
             None
         };
+        // Too many ranges get the whole file instead
+        let ranges = ranges.filter(|v| v.as_ref().is_none_or(|v| v.len() <= MAX_RANGES));
 
-        res.headers_mut().insert(
-            CONTENT_TYPE,
-            HeaderValue::from_str(&get_content_type(path).await?)?,
-        );
+        let content_type = get_content_type(path).await?;
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
 
-        let filename = try_get_file_name(path)?;
-        set_content_disposition(res, true, filename)?;
+        self.set_file_disposition(res, &content_type, try_get_file_name(path)?)?;
 
         res.headers_mut().typed_insert(AcceptRanges::bytes());
 
@@ -934,7 +2773,6 @@ impl Server {
             if let Some(ranges) = ranges {
                 if ranges.len() == 1 {
                     let (start, end) = ranges[0];
-                    file.seek(SeekFrom::Start(start)).await?;
                     let range_size = end - start + 1;
                     *res.status_mut() = StatusCode::PARTIAL_CONTENT;
                     let content_range = format!("bytes {start}-{end}/{size}");
@@ -946,42 +2784,61 @@ impl Server {
                         return Ok(());
                     }
 
-                    let stream_body = StreamBody::new(
-                        LengthLimitedStream::new(file, range_size as usize)
-                            .map_ok(Frame::data)
-                            .map_err(|err| anyhow!("{err}")),
-                    );
-                    let boxed_body = stream_body.boxed();
-                    *res.body_mut() = boxed_body;
+                    set_file_body(file, start, range_size, res).await;
                 } else {
                     *res.status_mut() = StatusCode::PARTIAL_CONTENT;
                     let boundary = Uuid::new_v4();
-                    let mut body = Vec::new();
-                    let content_type = get_content_type(path).await?;
-                    for (start, end) in ranges {
-                        file.seek(SeekFrom::Start(start)).await?;
-                        let range_size = end - start + 1;
-                        let content_range = format!("bytes {start}-{end}/{size}");
-                        let part_header = format!(
-                            "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: {content_range}\r\n\r\n",
-                        );
-                        body.extend_from_slice(part_header.as_bytes());
-                        let mut buffer = vec![0; range_size as usize];
-                        file.read_exact(&mut buffer).await?;
-                        body.extend_from_slice(&buffer);
-                        body.extend_from_slice(b"\r\n");
-                    }
-                    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+                    let parts: Vec<_> = ranges
+                        .into_iter()
+                        .map(|(start, end)| {
+                            let part_header = format!(
+                                "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{end}/{size}\r\n\r\n",
+                            );
+                            (part_header, start, end - start + 1)
+                        })
+                        .collect();
+                    let closing = format!("--{boundary}--\r\n");
+                    let content_length = parts
+                        .iter()
+                        .map(|(part_header, _, range_size)| {
+                            part_header.len() as u64 + range_size + 2
+                        })
+                        .sum::<u64>()
+                        + closing.len() as u64;
                     res.headers_mut().insert(
                         CONTENT_TYPE,
                         format!("multipart/byteranges; boundary={boundary}").parse()?,
                     );
                     res.headers_mut()
-                        .insert(CONTENT_LENGTH, format!("{}", body.len()).parse()?);
+                        .insert(CONTENT_LENGTH, format!("{content_length}").parse()?);
                     if head_only {
                         return Ok(());
                     }
-                    *res.body_mut() = body_full(body);
+                    // Parts are streamed one after another, only a buffer of the file is held
+                    let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
+                    let path = path.to_owned();
+                    tokio::spawn(async move {
+                        let result: io::Result<()> = async {
+                            for (part_header, start, range_size) in parts {
+                                writer.write_all(part_header.as_bytes()).await?;
+                                file.seek(SeekFrom::Start(start)).await?;
+                                io::copy(&mut (&mut file).take(range_size), &mut writer).await?;
+                                writer.write_all(b"\r\n").await?;
+                            }
+                            writer.write_all(closing.as_bytes()).await
+                        }
+                        .await;
+                        if let Err(e) = result {
+                            error!("Failed to send ranges of {}, {e}", path.display());
+                        }
+                    });
+                    let reader_stream = ReaderStream::with_capacity(reader, BUF_SIZE);
+                    let stream_body = StreamBody::new(
+                        reader_stream
+                            .map_ok(Frame::data)
+                            .map_err(|err| anyhow!("{err}")),
+                    );
+                    *res.body_mut() = stream_body.boxed();
                 }
             } else {
                 *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
@@ -995,18 +2852,60 @@ impl Server {
                 return Ok(());
             }
 
-            let reader_stream = ReaderStream::with_capacity(file, BUF_SIZE);
-            let stream_body = StreamBody::new(
-                reader_stream
-                    .map_ok(Frame::data)
-                    .map_err(|err| anyhow!("{err}")),
-            );
-            let boxed_body = stream_body.boxed();
-            *res.body_mut() = boxed_body;
+            set_file_body(file, 0, size, res).await;
         }
         Ok(())
     }
 
+    /// The compressed sibling of `path` in the coding the client prefers, its ETag is its own.
+    async fn find_precompressed(
+        &self,
+        path: &Path,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<(PathBuf, &'static str)> {
+        let accept_encoding = headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+        let modified = fs::metadata(path).await.ok()?.modified().ok()?;
+        for encoding in accepted_encodings(accept_encoding) {
+            let ext = match encoding {
+                "br" => "br",
+                "zstd" => "zst",
+                _ => "gz",
+            };
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{ext}"));
+            let sibling = PathBuf::from(name);
+            // A copy older than the file is left over from a previous version
+            let fresh = fs::metadata(&sibling)
+                .await
+                .is_ok_and(|v| v.is_file() && v.modified().is_ok_and(|v| v >= modified));
+            if fresh && !self.guard_root_contained(&sibling).await {
+                return Some((sibling, encoding));
+            }
+        }
+        None
+    }
+
+    /// Serve a file inline unless it's active content that `--active-content` says otherwise for.
+    fn set_file_disposition(
+        &self,
+        res: &mut Response,
+        content_type: &str,
+        filename: &str,
+    ) -> Result<()> {
+        let mut inline = true;
+        if is_active_content(content_type) {
+            match self.args.active_content {
+                ActiveContent::Inline => {}
+                ActiveContent::Attachment => inline = false,
+                ActiveContent::Sandbox => {
+                    res.headers_mut()
+                        .insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+                }
+            }
+        }
+        set_content_disposition(res, inline, filename)
+    }
+
     async fn handle_edit_file(
         &self,
         path: &Path,
@@ -1037,14 +2936,7 @@ impl Server {
         };
         res.headers_mut()
             .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
-        let index_data = STANDARD.encode(serde_json::to_string(&data)?);
-        let output = self
-            .html
-            .replace(
-                "__ASSETS_PREFIX__",
-                &format!("{}{}", self.args.uri_prefix, self.assets_prefix),
-            )
-            .replace("__INDEX_DATA__", &index_data);
+        let output = self.render_html(&data, res)?;
         res.headers_mut()
             .typed_insert(ContentLength(output.len() as u64));
         res.headers_mut()
@@ -1062,7 +2954,8 @@ impl Server {
         head_only: bool,
         res: &mut Response,
     ) -> Result<()> {
-        let output = sha256_file(path).await?;
+        let meta = fs::metadata(path).await?;
+        let output = self.hashes.sha256(path, &meta).await?;
         res.headers_mut()
             .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
         res.headers_mut()
@@ -1099,16 +2992,8 @@ impl Server {
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let depth: u32 = match headers.get("depth") {
-            Some(v) => match v.to_str().ok().and_then(|v| v.parse().ok()) {
-                Some(0) => 0,
-                Some(1) => 1,
-                _ => {
-                    status_bad_request(res, "Invalid depth: only 0 and 1 are allowed.");
-                    return Ok(());
-                }
-            },
-            None => 1,
+        let Some(depth) = propfind_depth(headers, res) else {
+            return Ok(());
         };
         let mut paths = match self.to_pathitem(path, &self.args.serve_path).await? {
             Some(v) => vec![v],
@@ -1222,18 +3107,59 @@ impl Server {
 </D:activelock></D:lockdiscovery></D:prop>"#
         ));
         Ok(())
-    }
-
-    async fn handle_proppatch(&self, req_path: &str, res: &mut Response) -> Result<()> {
-        let output = format!(
-            r#"<D:response>
-<D:href>{req_path}</D:href>
-<D:propstat>
+    }
+
+    /// Only `getlastmodified` can be set, to keep the modification time of uploaded files.
+    async fn handle_proppatch(
+        &self,
+        path: &Path,
+        req_path: &str,
+        allow_upload: bool,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let body = match Limited::new(req.into_body(), PROPPATCH_MAX_BODY_SIZE)
+            .collect()
+            .await
+        {
+            Ok(v) => v.to_bytes(),
+            Err(_) => {
+                status_bad_request(res, "Invalid PROPPATCH body");
+                return Ok(());
+            }
+        };
+        let (mtime, others) = match parse_proppatch_mtime(&body) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let mut propstats = String::new();
+        if let Some(mtime) = mtime.filter(|_| allow_upload) {
+            set_file_mtime(path, mtime).await?;
+            propstats.push_str(
+                r#"<D:propstat>
+<D:prop><D:getlastmodified/></D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+"#,
+            );
+        }
+        if propstats.is_empty() || others > 0 {
+            propstats.push_str(
+                r#"<D:propstat>
 <D:prop>
 </D:prop>
 <D:status>HTTP/1.1 403 Forbidden</D:status>
 </D:propstat>
-</D:response>"#
+"#,
+            );
+        }
+        let output = format!(
+            r#"<D:response>
+<D:href>{req_path}</D:href>
+{propstats}</D:response>"#
         );
         res_multistatus(res, &output);
         Ok(())
@@ -1249,6 +3175,7 @@ impl Server {
         head_only: bool,
         user: Option<String>,
         access_paths: AccessPaths,
+        in_archive: bool,
         res: &mut Response,
     ) -> Result<()> {
         if let Some(sort) = query_params.get("sort") {
@@ -1297,14 +3224,24 @@ impl Server {
             normalize_path(path.strip_prefix(&self.args.serve_path)?)
         );
         let readwrite = access_paths.perm().readwrite();
+        let write_only = access_paths.perm().writeonly();
         let data = IndexData {
-            kind: DataKind::Index,
+            kind: if write_only {
+                DataKind::Upload
+            } else {
+                DataKind::Index
+            },
             href,
             uri_prefix: self.args.uri_prefix.clone(),
-            allow_upload: self.args.allow_upload && readwrite,
-            allow_delete: self.args.allow_delete && readwrite,
-            allow_search: self.args.allow_search,
-            allow_archive: self.args.allow_archive,
+            allow_upload: self.args.allow_upload && (readwrite || write_only) && !in_archive,
+            allow_delete: self.args.allow_delete && readwrite && !in_archive,
+            allow_search: self.args.allow_search && !write_only && !in_archive,
+            allow_archive: self.args.allow_archive && !write_only && !in_archive,
+            allow_extract: self.args.allow_extract
+                && self.args.allow_upload
+                && readwrite
+                && !in_archive,
+            in_archive,
             dir_exists: exist,
             auth: self.args.auth.has_users(),
             user,
@@ -1317,27 +3254,17 @@ impl Server {
         } else if has_query_flag(query_params, "noscript") {
             res.headers_mut()
                 .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
+            self.set_csp(&Uuid::new_v4().simple().to_string(), res)?;
             generate_noscript_html(&data)?
         } else {
             res.headers_mut()
                 .typed_insert(ContentType::from(mime_guess::mime::TEXT_HTML_UTF_8));
-
-            let index_data = STANDARD.encode(serde_json::to_string(&data)?);
-            self.html
-                .replace(
-                    "__ASSETS_PREFIX__",
-                    &format!("{}{}", self.args.uri_prefix, self.assets_prefix),
-                )
-                .replace("__INDEX_DATA__", &index_data)
+            self.render_html(&data, res)?
         };
         res.headers_mut()
             .typed_insert(ContentLength(output.len() as u64));
         res.headers_mut()
             .typed_insert(CacheControl::new().with_no_cache());
-        res.headers_mut().insert(
-            "x-content-type-options",
-            HeaderValue::from_static("nosniff"),
-        );
         if head_only {
             return Ok(());
         }
@@ -1345,6 +3272,77 @@ impl Server {
         Ok(())
     }
 
+    /// Fill the UI template with `data`, the page's scripts are allowed by a fresh CSP nonce.
+    fn render_html<T: Serialize>(&self, data: &T, res: &mut Response) -> Result<String> {
+        let nonce = Uuid::new_v4().simple().to_string();
+        self.set_csp(&nonce, res)?;
+        let index_data = STANDARD.encode(serde_json::to_string(data)?);
+        Ok(self
+            .html
+            .replace(
+                "__ASSETS_PREFIX__",
+                &format!("{}{}", self.args.uri_prefix, self.assets_prefix),
+            )
+            .replace("__INDEX_DATA__", &index_data)
+            .replace("__NONCE__", &nonce))
+    }
+
+    fn set_csp(&self, nonce: &str, res: &mut Response) -> Result<()> {
+        if !self.args.csp.is_empty() {
+            res.headers_mut().insert(
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(&self.args.csp.replace("{nonce}", nonce))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Start an audit record for mutating requests, the result is filled in by `call`.
+    ///
+    /// Every request gets a record when auditing is on, since any of them may fail to authenticate.
+    fn audit_record(&self, req: &Request, addr: Option<SocketAddr>) -> Option<AuditRecord> {
+        if !audit_log::enabled() {
+            return None;
+        }
+        let headers = req.headers();
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let path = self.resolve_path(req.uri().path());
+        // Downloading a selection as an archive changes nothing
+        let is_archive_selection = is_archive_selection_query(&query_params, headers)
+            && path
+                .as_ref()
+                .and_then(|v| self.join_path(v))
+                .is_some_and(|v| v.is_dir());
+        let action = match req.method().as_str() {
+            "POST" if query_params.contains_key("extract") => "extract",
+            "POST" if is_archive_selection => AUDIT_NONE,
+            "PUT" | "POST" => "upload",
+            "PATCH" => "append",
+            "DELETE" => "delete",
+            "MKCOL" => "mkcol",
+            "COPY" => "copy",
+            "MOVE" => "move",
+            _ if query_params.contains_key("tokengen") => "tokengen",
+            _ => AUDIT_NONE,
+        };
+        let path = path
+            .map(|v| format!("/{v}"))
+            .unwrap_or_else(|| req.uri().path().to_string());
+        let mut record = AuditRecord::new(action, path);
+        record.claimed_user = headers.get(AUTHORIZATION).and_then(get_auth_user);
+        record.ip = addr.map(|v| v.ip().to_string());
+        if matches!(action, "copy" | "move") {
+            record.dest = self
+                .extract_destination_header(headers)
+                .and_then(|v| self.resolve_path(&v))
+                .map(|v| format!("/{v}"));
+        }
+        Some(record)
+    }
+
     fn auth_reject(&self, res: &mut Response) -> Result<()> {
         set_webdav_headers(res);
 
@@ -1467,6 +3465,9 @@ impl Server {
         access_paths: AccessPaths,
     ) -> Result<Vec<PathItem>> {
         let mut paths: Vec<PathItem> = vec![];
+        if access_paths.perm().writeonly() {
+            return Ok(paths);
+        }
         if access_paths.perm().indexonly() {
             for name in access_paths.child_names() {
                 let entry_path = entry_path.join(name);
@@ -1551,6 +3552,8 @@ pub enum DataKind {
     Index,
     Edit,
     View,
+    /// A write-only dir, only the upload controls are shown
+    Upload,
 }
 
 #[derive(Debug, Serialize)]
@@ -1562,6 +3565,9 @@ pub struct IndexData {
     pub allow_delete: bool,
     pub allow_search: bool,
     pub allow_archive: bool,
+    pub allow_extract: bool,
+    /// The listing is of a folder inside an archive file, which is read-only
+    pub in_archive: bool,
     pub dir_exists: bool,
     pub auth: bool,
     pub user: Option<String>,
@@ -1710,6 +3716,265 @@ fn normalize_path<P: AsRef<Path>>(path: P) -> String {
     }
 }
 
+const DEFAULT_EXTRACT_MAX_ENTRIES: u64 = 10000;
+const DEFAULT_EXTRACT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Limits of extracting an archive, also those of browsing one
+fn extract_limits(args: &Args) -> ExtractLimits {
+    ExtractLimits {
+        max_entries: args
+            .extract_max_entries
+            .unwrap_or(DEFAULT_EXTRACT_MAX_ENTRIES),
+        max_size: args.extract_max_size.unwrap_or(DEFAULT_EXTRACT_MAX_SIZE),
+    }
+}
+
+/// Whether a request comes from a page of this server or an origin listed by CORS, `*` lets any
+/// site read responses but never write on behalf of the user.
+fn is_same_origin(headers: &HeaderMap<HeaderValue>, cors: Option<&Cors>) -> bool {
+    let Some(origin) = headers.get(ORIGIN).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    if cors.is_some_and(|v| v.list_origin(origin)) {
+        return true;
+    }
+    let origin_host = origin.split_once("://").map(|(_, v)| v).unwrap_or(origin);
+    headers
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case(origin_host))
+}
+
+fn join_relative(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Conflict policy picked by the request with `X-Upload-Conflict` or `?conflict=`.
+fn upload_conflict(
+    headers: &HeaderMap<HeaderValue>,
+    query_params: &HashMap<String, String>,
+) -> Result<Option<ConflictPolicy>> {
+    let value = match headers.get("x-upload-conflict") {
+        Some(value) => value.to_str().unwrap_or_default(),
+        None => match query_params.get("conflict") {
+            Some(value) => value.as_str(),
+            None => return Ok(None),
+        },
+    };
+    <ConflictPolicy as clap::ValueEnum>::from_str(value, true)
+        .map(Some)
+        .map_err(|_| anyhow!("Invalid upload conflict policy `{value}`"))
+}
+
+/// Modification time supplied by the client with `X-OC-Mtime` or `X-Mtime`, in unix seconds.
+fn upload_mtime(headers: &HeaderMap<HeaderValue>) -> Result<Option<SystemTime>> {
+    let Some(value) = headers.get("x-oc-mtime").or_else(|| headers.get("x-mtime")) else {
+        return Ok(None);
+    };
+    let value = value.to_str().unwrap_or_default();
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .and_then(|v| SystemTime::UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(v).ok()?))
+        .map(Some)
+        .ok_or_else(|| anyhow!("Invalid modification time `{value}`"))
+}
+
+/// Apply the client's modification time to an uploaded file, confirmed like ownCloud does.
+async fn apply_upload_mtime(path: &Path, mtime: Option<SystemTime>, res: &mut Response) {
+    let Some(mtime) = mtime else {
+        return;
+    };
+    match set_file_mtime(path, mtime).await {
+        Ok(()) => {
+            res.headers_mut()
+                .insert("x-oc-mtime", HeaderValue::from_static("accepted"));
+        }
+        Err(err) => warn!("Failed to set mtime of `{}`, {err}", path.display()),
+    }
+}
+
+/// The `getlastmodified` to set in a PROPPATCH body and the number of other properties.
+fn parse_proppatch_mtime(body: &[u8]) -> Result<(Option<SystemTime>, usize)> {
+    let mut mtime = None;
+    let mut others = 0;
+    if body.iter().all(|v| v.is_ascii_whitespace()) {
+        return Ok((mtime, others));
+    }
+    let mut stack: Vec<String> = vec![];
+    let mut text = String::new();
+    for event in EventReader::new(body) {
+        match event.map_err(|err| anyhow!("Invalid PROPPATCH body, {err}"))? {
+            XmlEvent::StartElement { name, .. } => {
+                // Elements directly in `<set><prop>` or `<remove><prop>` are properties
+                if stack.len() >= 2 && stack[stack.len() - 1] == "prop" {
+                    if stack[stack.len() - 2] == "set" && name.local_name == "getlastmodified" {
+                        text.clear();
+                    } else {
+                        others += 1;
+                    }
+                }
+                stack.push(name.local_name);
+            }
+            XmlEvent::Characters(value) => text.push_str(&value),
+            XmlEvent::EndElement { name } => {
+                stack.pop();
+                if name.local_name == "getlastmodified"
+                    && stack.len() >= 2
+                    && stack[stack.len() - 2] == "set"
+                {
+                    let value = text.trim();
+                    let datetime = DateTime::parse_from_rfc2822(value)
+                        .or_else(|_| DateTime::parse_from_rfc3339(value))
+                        .map_err(|_| anyhow!("Invalid getlastmodified `{value}`"))?;
+                    mtime = Some(SystemTime::from(datetime));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((mtime, others))
+}
+
+/// Where an upload to `path` ends up, moving the existing file aside for `keep-both`.
+async fn resolve_upload_conflict(
+    path: &Path,
+    conflict: Option<ConflictPolicy>,
+) -> io::Result<PathBuf> {
+    if fs::symlink_metadata(path).await.is_err() {
+        return Ok(path.to_path_buf());
+    }
+    match conflict.unwrap_or_default() {
+        ConflictPolicy::Rename => Ok(free_path(path).await),
+        ConflictPolicy::KeepBoth => {
+            fs::rename(path, free_path(path).await).await?;
+            Ok(path.to_path_buf())
+        }
+        ConflictPolicy::Overwrite | ConflictPolicy::Fail => Ok(path.to_path_buf()),
+    }
+}
+
+/// First free name like `report (2).pdf` next to `path`.
+async fn free_path(path: &Path) -> PathBuf {
+    let name = get_file_name(path);
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut i = 2;
+    loop {
+        let candidate = path.with_file_name(format!("{stem} ({i}){ext}"));
+        if fs::symlink_metadata(&candidate).await.is_err() {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+const X_UPLOAD_NAME: &str = "x-upload-name";
+const X_UPLOAD_LENGTH: &str = "x-upload-length";
+const X_UPLOAD_RECEIVED: &str = "x-upload-received";
+
+/// Tell the client which name an upload was stored under.
+fn set_upload_name(path: &Path, res: &mut Response) {
+    if let Ok(value) = HeaderValue::from_str(&encode_uri(get_file_name(path))) {
+        res.headers_mut().insert(X_UPLOAD_NAME, value);
+    }
+}
+
+fn add_upload_name(path: &Path, res: &mut Response) {
+    if let Ok(value) = HeaderValue::from_str(&encode_uri(get_file_name(path))) {
+        res.headers_mut().append(X_UPLOAD_NAME, value);
+    }
+}
+
+/// Turn the filename of a form upload into a relative path, rejecting any that escapes the directory.
+fn sanitize_upload_filename(filename: &str) -> Option<String> {
+    let parts: Vec<&str> = filename
+        .split(['/', '\\'])
+        .filter(|v| !v.is_empty() && *v != ".")
+        .collect();
+    if parts.is_empty() || parts.contains(&"..") {
+        return None;
+    }
+    Some(parts.join("/"))
+}
+
+/// Write the staged content of `src` into `dest` at `offset`.
+async fn copy_into(src: &Path, dest: &Path, offset: u64, fsync: bool) -> io::Result<()> {
+    let mut src = fs::File::open(src).await?;
+    let mut dest = fs::OpenOptions::new().write(true).open(dest).await?;
+    dest.seek(SeekFrom::Start(offset)).await?;
+    io::copy(&mut src, &mut dest).await?;
+    dest.flush().await?;
+    if fsync {
+        dest.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Hidden file next to `path` that an upload is streamed into before being renamed over it.
+fn upload_temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!("{UPLOAD_TEMP_PREFIX}{}", Uuid::new_v4().simple()))
+}
+
+/// Remove the temp files and directories left behind by uploads and extractions that were
+/// interrupted by a shutdown or crash, then keep dropping the resumable uploads that expire while
+/// the server runs.
+fn sweep_upload_temp_files(serve_path: PathBuf, running: Arc<AtomicBool>) {
+    // Files written since are from uploads of this process, which may still be going on
+    let started = SystemTime::now();
+    std::thread::spawn(move || {
+        let mut it = WalkDir::new(&serve_path).into_iter();
+        while let Some(entry) = it.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let is_temp = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|v| v.starts_with(UPLOAD_TEMP_PREFIX));
+            // Resumable uploads outlive restarts
+            if !is_temp || partial_upload::is_pending(entry.path()) {
+                continue;
+            }
+            let is_stale = entry
+                .metadata()
+                .ok()
+                .and_then(|v| v.modified().ok())
+                .is_some_and(|v| v < started);
+            if !is_stale {
+                continue;
+            }
+            if entry.file_type().is_dir() {
+                // Left by an interrupted extraction
+                let _ = std::fs::remove_dir_all(entry.path());
+                it.skip_current_dir();
+            } else if entry.file_type().is_file() {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        while running.load(atomic::Ordering::SeqCst) {
+            std::thread::sleep(UPLOAD_SWEEP_INTERVAL);
+            partial_upload::sweep_expired(&serve_path);
+        }
+    });
+}
+
+/// Send `len` bytes of `file` from `offset`, a plain HTTP/1 connection may send them without
+/// copying them.
+async fn set_file_body(file: fs::File, offset: u64, len: u64, res: &mut Response) {
+    let file = Arc::new(file.into_std().await);
+    *res.body_mut() = file_body(file.clone(), offset, len);
+    res.extensions_mut().insert(FileSpan { file, offset, len });
+}
+
 async fn ensure_path_parent(path: &Path) -> Result<()> {
     if let Some(parent) = path.parent() {
         if fs::symlink_metadata(parent).await.is_err() {
@@ -1719,23 +3984,72 @@ async fn ensure_path_parent(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn add_cors(res: &mut Response) {
-    res.headers_mut()
-        .typed_insert(AccessControlAllowOrigin::ANY);
-    res.headers_mut()
-        .typed_insert(AccessControlAllowCredentials);
-    res.headers_mut().insert(
-        "Access-Control-Allow-Methods",
-        HeaderValue::from_static("*"),
-    );
-    res.headers_mut().insert(
-        "Access-Control-Allow-Headers",
-        HeaderValue::from_static("Authorization,*"),
-    );
-    res.headers_mut().insert(
-        "Access-Control-Expose-Headers",
-        HeaderValue::from_static("Authorization,*"),
-    );
+/// Set `Access-Control-Allow-Origin`, returns false if the origin is not allowed.
+///
+/// Only origins listed explicitly are reflected and get credentials, any other origin allowed by
+/// `*` gets `*`, which browsers never combine with credentials.
+fn set_cors_origin(cors: &Cors, origin: Option<&HeaderValue>, res: &mut Response) -> bool {
+    add_vary(res, "Origin");
+    let headers = res.headers_mut();
+    match origin {
+        Some(origin) if origin.to_str().is_ok_and(|v| cors.list_origin(v)) => {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+            if cors.allow_credentials {
+                headers.insert(
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
+        }
+        _ if cors.allow_any_origin() => {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn add_cors(cors: &Cors, origin: Option<&HeaderValue>, res: &mut Response) {
+    if res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+        return;
+    }
+    if !set_cors_origin(cors, origin, res) {
+        return;
+    }
+    if let Ok(value) = HeaderValue::from_str(&cors.expose_headers.join(",")) {
+        if !value.is_empty() {
+            res.headers_mut()
+                .insert(ACCESS_CONTROL_EXPOSE_HEADERS, value);
+        }
+    }
+}
+
+fn set_cors_preflight(cors: &Cors, headers: &HeaderMap<HeaderValue>, res: &mut Response) {
+    *res.status_mut() = StatusCode::NO_CONTENT;
+    if !set_cors_origin(cors, headers.get(ORIGIN), res) {
+        return;
+    }
+    let res_headers = res.headers_mut();
+    for name in [
+        ACCESS_CONTROL_REQUEST_METHOD,
+        ACCESS_CONTROL_REQUEST_HEADERS,
+    ] {
+        res_headers.append(VARY, HeaderValue::from_name(name));
+    }
+    if let Ok(value) = HeaderValue::from_str(&cors.allow_methods.join(",")) {
+        res_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, value);
+    }
+    let allow_headers = if cors.allow_headers.is_empty() {
+        headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned()
+    } else {
+        HeaderValue::from_str(&cors.allow_headers.join(",")).ok()
+    };
+    if let Some(value) = allow_headers {
+        res_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, value);
+    }
+    if let Some(max_age) = cors.max_age {
+        res_headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.into());
+    }
 }
 
 fn res_multistatus(res: &mut Response, content: &str) {
@@ -1752,31 +4066,50 @@ fn res_multistatus(res: &mut Response, content: &str) {
     ));
 }
 
-async fn zip_dir<W: AsyncWrite + Unpin>(
+/// Write `paths` as an archive of `format`, entries are named relative to `base`. Tars keep modes
+/// and mtimes, symlinks are stored as links with `keep_symlinks` instead of their targets.
+async fn write_archive<W: AsyncWrite + Unpin + Send>(
     writer: &mut W,
-    dir: &Path,
-    access_paths: AccessPaths,
-    hidden: &[String],
+    format: ArchiveFormat,
+    base: &Path,
+    paths: Vec<PathBuf>,
+    compression: Compression,
+    keep_symlinks: bool,
+) -> Result<()> {
+    match format {
+        ArchiveFormat::Zip => write_zip(writer, base, paths, compression).await?,
+        ArchiveFormat::TarGz => {
+            let mut encoder = GzipEncoder::new(writer);
+            write_tar(&mut encoder, base, paths, keep_symlinks).await?;
+            encoder.shutdown().await?;
+        }
+        ArchiveFormat::TarZst => {
+            let mut encoder = ZstdEncoder::new(writer);
+            write_tar(&mut encoder, base, paths, keep_symlinks).await?;
+            encoder.shutdown().await?;
+        }
+        ArchiveFormat::Tar => write_tar(writer, base, paths, keep_symlinks).await?,
+    }
+    Ok(())
+}
+
+async fn write_zip<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    base: &Path,
+    paths: Vec<PathBuf>,
     compression: Compression,
-    follow_symlinks: bool,
-    serve_path: PathBuf,
-    running: Arc<AtomicBool>,
 ) -> Result<()> {
     let mut writer = ZipFileWriter::with_tokio(writer);
-    let hidden = Arc::new(hidden.to_vec());
-    let zip_paths = tokio::task::spawn(collect_dir_entries(
-        access_paths,
-        running,
-        dir.to_path_buf(),
-        hidden,
-        follow_symlinks,
-        serve_path,
-        move |x| x.path().symlink_metadata().is_ok() && x.file_type().is_file(),
-    ))
-    .await?;
-    for zip_path in zip_paths.into_iter() {
+    for zip_path in paths.into_iter() {
+        if !fs::metadata(&zip_path)
+            .await
+            .map(|v| v.is_file())
+            .unwrap_or_default()
+        {
+            continue;
+        }
         let filename = match zip_path
-            .strip_prefix(dir)
+            .strip_prefix(base)
             .ok()
             .and_then(|v| v.to_str())
             .map(|v| v.replace(MAIN_SEPARATOR, "/"))
@@ -1785,9 +4118,10 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
             None => continue,
         };
         let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
-        let builder = ZipEntryBuilder::new(filename.into(), compression)
-            .unix_permissions(mode)
-            .last_modification_date(ZipDateTime::from_chrono(&datetime));
+        let builder =
+            ZipEntryBuilder::new(filename.into(), entry_compression(&zip_path, compression))
+                .unix_permissions(mode)
+                .last_modification_date(ZipDateTime::from_chrono(&datetime));
         let mut file = File::open(&zip_path).await?;
         let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
         io::copy(&mut file, &mut file_writer).await?;
@@ -1797,6 +4131,81 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
     Ok(())
 }
 
+async fn write_tar<W: AsyncWrite + Unpin + Send>(
+    writer: &mut W,
+    dir: &Path,
+    paths: Vec<PathBuf>,
+    keep_symlinks: bool,
+) -> io::Result<()> {
+    let mut builder = tokio_tar::Builder::new_non_terminated(writer);
+    builder.follow_symlinks(!keep_symlinks);
+    // The walk follows symlinked dirs, their entries are covered by the link itself
+    let mut link_dirs: Vec<PathBuf> = vec![];
+    for path in paths {
+        if link_dirs.iter().any(|v| path.starts_with(v)) {
+            continue;
+        }
+        let Ok(name) = path.strip_prefix(dir) else {
+            continue;
+        };
+        if keep_symlinks && fs::symlink_metadata(&path).await?.is_symlink() && path.is_dir() {
+            link_dirs.push(path.clone());
+        }
+        builder.append_path_with_name(&path, name).await?;
+    }
+    builder.finish().await
+}
+
+/// Already compressed formats that aren't recognized by their MIME type
+const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "br", "docx", "epub", "jar", "lz", "lz4", "lzma", "odp", "ods", "odt", "pptx",
+    "tgz", "txz", "whl", "woff", "woff2", "xlsx", "zst",
+];
+
+/// Entries that are already compressed are stored, another pass burns CPU for nothing.
+fn entry_compression(path: &Path, compression: Compression) -> Compression {
+    let Some(ext) = path
+        .extension()
+        .and_then(|v| v.to_str())
+        .map(|v| v.to_ascii_lowercase())
+    else {
+        return compression;
+    };
+    if INCOMPRESSIBLE_EXTENSIONS.contains(&ext.as_str()) {
+        return Compression::Stored;
+    }
+    let Some(mime) = mime_guess::from_ext(&ext).first() else {
+        return compression;
+    };
+    let subtype = mime.subtype().as_str();
+    let incompressible = match mime.type_().as_str() {
+        "video" => true,
+        "audio" => !matches!(subtype, "wav" | "x-wav" | "vnd.wave" | "aiff" | "x-aiff"),
+        "image" => matches!(
+            subtype,
+            "jpeg" | "png" | "gif" | "webp" | "avif" | "heic" | "heif" | "jxl"
+        ),
+        "application" => matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "zstd"
+                | "x-7z-compressed"
+                | "vnd.rar"
+                | "x-rar-compressed"
+        ),
+        _ => false,
+    };
+    if incompressible {
+        Compression::Stored
+    } else {
+        compression
+    }
+}
+
 fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
     let mtime = meta.modified().ok().or_else(|| meta.created().ok())?;
     let timestamp = to_timestamp(&mtime);
@@ -1806,6 +4215,21 @@ fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
     Some((etag, last_modified))
 }
 
+/// Tell caches that the response depends on the request header `name`, once.
+fn add_vary(res: &mut Response, name: &'static str) {
+    let present = res
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case(name));
+    if !present {
+        res.headers_mut()
+            .append(VARY, HeaderValue::from_static(name));
+    }
+}
+
 fn status_forbid(res: &mut Response) {
     *res.status_mut() = StatusCode::FORBIDDEN;
     *res.body_mut() = body_full("Forbidden");
@@ -1820,6 +4244,19 @@ fn status_no_content(res: &mut Response) {
     *res.status_mut() = StatusCode::NO_CONTENT;
 }
 
+fn status_payload_too_large(res: &mut Response, limit: &UploadLimit) {
+    *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    let max_size = limit.max_size.unwrap_or_default();
+    *res.body_mut() = body_full(format!(
+        "File too large, the upload limit is {max_size} bytes"
+    ));
+}
+
+fn status_unsupported_type(res: &mut Response, name: &str) {
+    *res.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+    *res.body_mut() = body_full(format!("File type of `{name}` is not allowed"));
+}
+
 fn status_bad_request(res: &mut Response, body: &str) {
     *res.status_mut() = StatusCode::BAD_REQUEST;
     if !body.is_empty() {
@@ -1827,6 +4264,20 @@ fn status_bad_request(res: &mut Response, body: &str) {
     }
 }
 
+/// Content types a browser may execute scripts in when rendered from our origin.
+fn is_active_content(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    matches!(
+        mime.as_str(),
+        "text/html" | "application/xhtml+xml" | "image/svg+xml" | "text/xml" | "application/xml"
+    )
+}
+
 fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) -> Result<()> {
     let kind = if inline { "inline" } else { "attachment" };
     let filename: String = filename
@@ -1853,6 +4304,9 @@ fn set_content_disposition(res: &mut Response, inline: bool, filename: &str) ->
 }
 
 fn is_hidden(hidden: &[String], file_name: &str, is_dir: bool) -> bool {
+    if file_name.starts_with(UPLOAD_TEMP_PREFIX) {
+        return true;
+    }
     hidden.iter().any(|v| {
         if is_dir {
             if let Some(x) = v.strip_suffix('/') {
@@ -1863,6 +4317,32 @@ fn is_hidden(hidden: &[String], file_name: &str, is_dir: bool) -> bool {
     })
 }
 
+const TUS_RESUMABLE: &str = "tus-resumable";
+
+/// Answer with `412 Precondition Failed` if the client speaks another tus version.
+fn check_tus_resumable(headers: &HeaderMap<HeaderValue>, res: &mut Response) -> bool {
+    res.headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    if headers.get(TUS_RESUMABLE).is_some_and(|v| v == TUS_VERSION) {
+        return true;
+    }
+    *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+    res.headers_mut()
+        .insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    false
+}
+
+fn set_tus_headers(res: &mut Response) {
+    let headers = res.headers_mut();
+    headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    headers.insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+    headers.insert("tus-extension", HeaderValue::from_static(TUS_EXTENSIONS));
+    headers.insert(
+        "tus-checksum-algorithm",
+        HeaderValue::from_static(TUS_CHECKSUM_ALGORITHMS),
+    );
+}
+
 fn set_webdav_headers(res: &mut Response) {
     res.headers_mut().insert(
         "Allow",
@@ -1874,6 +4354,44 @@ fn set_webdav_headers(res: &mut Response) {
         .insert("DAV", HeaderValue::from_static("1, 2, 3"));
 }
 
+const SNIFF_SIZE: usize = 1024;
+
+/// Keep the first bytes of an upload to sniff its content type.
+fn fill_head(head: &mut Vec<u8>, chunk: &[u8]) {
+    let len = SNIFF_SIZE.saturating_sub(head.len()).min(chunk.len());
+    head.extend_from_slice(&chunk[..len]);
+}
+
+/// Check the upload allow and deny lists, before the body arrives `head` is `None` and the
+/// content type is only guessed from the name.
+fn allow_upload_type(limit: &UploadLimit, name: &str, head: Option<&[u8]>) -> bool {
+    if limit.allow.is_empty() && limit.deny.is_empty() {
+        return true;
+    }
+    let mime = mime_guess::from_path(name).first();
+    let mime = match head {
+        None => match mime {
+            Some(mime) => mime.essence_str().to_string(),
+            // Can't tell yet, extension patterns still apply
+            None => {
+                return limit.allow_type(name, "") || limit.allow.iter().any(|v| v.contains('/'))
+            }
+        },
+        Some(head) => {
+            let is_text = content_inspector::inspect(head).is_text();
+            match mime {
+                Some(mime) if mime.type_() == mime_guess::mime::TEXT && !is_text => {
+                    "application/octet-stream".to_string()
+                }
+                Some(mime) => mime.essence_str().to_string(),
+                None if is_text => "text/plain".to_string(),
+                None => "application/octet-stream".to_string(),
+            }
+        }
+    };
+    limit.allow_type(name, &mime)
+}
+
 async fn get_content_type(path: &Path) -> Result<String> {
     let mut buffer: Vec<u8> = vec![];
     fs::File::open(path)
@@ -1917,21 +4435,89 @@ fn parse_upload_offset(headers: &HeaderMap<HeaderValue>, size: u64) -> Result<Op
     Ok(Some(*start))
 }
 
-async fn sha256_file(path: &Path) -> Result<String> {
-    let mut file = fs::File::open(path).await?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
+/// Folder archive requested with `?zip`, `?tar`, `?tgz`, `?tzst` or `?archive=<format>`.
+/// The archive and the path inside it that are requested, either with a `!` segment like
+/// `releases/build.zip/!/bin/app` or with `releases/build.zip?archive-path=bin/app`.
+fn archive_entry_target(
+    relative_path: &str,
+    is_miss: bool,
+    is_file: bool,
+    query_params: &HashMap<String, String>,
+) -> Option<(String, String)> {
+    if is_file {
+        let name = query_params.get("archive-path")?;
+        return Some((relative_path.to_string(), name.clone()));
+    }
+    // A real path containing `!` is served as is
+    if !is_miss {
+        return None;
+    }
+    let (archive, name) = match relative_path.strip_suffix("/!") {
+        Some(v) => (v, ""),
+        None => relative_path.split_once("/!/")?,
+    };
+    Some((archive.to_string(), name.to_string()))
+}
 
-    loop {
-        let bytes_read = file.read(&mut buffer).await?;
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
+fn archive_pathitem(entry: &ArchiveEntry, name: String) -> PathItem {
+    PathItem {
+        path_type: if entry.is_dir {
+            PathType::Dir
+        } else {
+            PathType::File
+        },
+        name,
+        mtime: entry.mtime,
+        size: entry.size,
+    }
+}
+
+/// The `Depth` of a PROPFIND, only 0 and 1 are supported.
+fn propfind_depth(headers: &HeaderMap<HeaderValue>, res: &mut Response) -> Option<u32> {
+    match headers.get("depth") {
+        Some(v) => match v.to_str().ok().and_then(|v| v.parse().ok()) {
+            Some(0) => Some(0),
+            Some(1) => Some(1),
+            _ => {
+                status_bad_request(res, "Invalid depth: only 0 and 1 are allowed.");
+                None
+            }
+        },
+        None => Some(1),
     }
+}
+
+/// Whether a POST asks for an archive of a selection rather than uploading.
+fn is_archive_selection_query(
+    query_params: &HashMap<String, String>,
+    headers: &HeaderMap<HeaderValue>,
+) -> bool {
+    archive_query(query_params).is_some()
+        && !query_params.contains_key("tus")
+        && !query_params.contains_key("session")
+        && !headers.contains_key(TUS_RESUMABLE)
+}
+
+fn archive_query(query_params: &HashMap<String, String>) -> Option<&str> {
+    ["zip", "tar", "tgz", "tzst"]
+        .into_iter()
+        .find(|v| has_query_flag(query_params, v))
+        .or_else(|| query_params.get("archive").map(|v| v.as_str()))
+}
 
-    let result = hasher.finalize();
-    Ok(hex::encode(result))
+/// Split a path selected for an archive download, it must stay inside the requested folder.
+fn selected_parts(name: &str) -> Option<Vec<String>> {
+    let mut parts = vec![];
+    for comp in Path::new(name.trim_matches('/')).components() {
+        match comp {
+            Component::Normal(v) => parts.push(v.to_string_lossy().to_string()),
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts)
 }
 
 fn has_query_flag(query_params: &HashMap<String, String>, name: &str) -> bool {
@@ -1954,6 +4540,7 @@ where
     F: Fn(&DirEntry) -> bool,
 {
     let mut paths: Vec<PathBuf> = vec![];
+    let write_only_paths = access_paths.write_only_paths(&path);
     for dir in access_paths.entry_paths(&path) {
         let mut it = WalkDir::new(&dir).follow_links(true).into_iter();
         it.next();
@@ -1974,6 +4561,10 @@ where
                 }
                 continue;
             }
+            if is_dir && write_only_paths.iter().any(|v| v == entry_path) {
+                it.skip_current_dir();
+                continue;
+            }
 
             if !follow_symlinks
                 && !fs::canonicalize(entry_path)
@@ -0,0 +1,428 @@
+//! Uncompressed zip archives whose layout is known before a single byte is written, so folder
+//! downloads get an exact `Content-Length` and can be served by range. CRCs are only known once
+//! a file has been read, they go into data descriptors after each entry and into the central
+//! directory. Zip64 records are emitted past 4GB or 65535 entries.
+//!
+//! The CRCs of files whose data falls outside the requested range come from the hash cache, so
+//! a range near the end of the archive doesn't read the whole folder again.
+
+use crate::hash_cache::HashCache;
+use crate::utils::get_file_mtime_and_mode;
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use sha2::{Digest, Sha256};
+use std::fs::Metadata;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::Arc;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const LOCAL_HEADER_LEN: u64 = 30;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const ZIP64_EXTRA_LEN: u64 = 28;
+const ZIP64_END_LEN: u64 = 56;
+const ZIP64_LOCATOR_LEN: u64 = 20;
+const END_LEN: u64 = 22;
+
+/// Data descriptor follows the data (bit 3), names are UTF-8 (bit 11)
+const FLAGS: u16 = 0x0808;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Made by unix, so that the external attributes hold the file mode
+const MADE_BY_UNIX: u16 = 3 << 8;
+
+const UINT16_MAX: u64 = 0xffff;
+const UINT32_MAX: u64 = 0xffffffff;
+const BUF_SIZE: usize = 65536;
+
+pub struct StoredZip {
+    entries: Vec<Entry>,
+    hashes: Arc<HashCache>,
+    central_dir_offset: u64,
+    central_dir_size: u64,
+    len: u64,
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    meta: Metadata,
+    size: u64,
+    mtime: DateTime<Utc>,
+    mode: u16,
+    offset: u64,
+}
+
+impl StoredZip {
+    /// Lay out the files among `paths`, named relative to `base`. Anything else is skipped.
+    pub async fn new(base: &Path, paths: Vec<PathBuf>, hashes: Arc<HashCache>) -> Result<Self> {
+        let mut entries = vec![];
+        let mut offset = 0;
+        for path in paths {
+            let meta = match fs::metadata(&path).await {
+                Ok(meta) if meta.is_file() => meta,
+                _ => continue,
+            };
+            let name = match path
+                .strip_prefix(base)
+                .ok()
+                .and_then(|v| v.to_str())
+                .map(|v| v.replace(MAIN_SEPARATOR, "/"))
+            {
+                Some(v) => v,
+                None => continue,
+            };
+            let (mtime, mode) = get_file_mtime_and_mode(&path).await?;
+            let entry = Entry {
+                path,
+                name,
+                size: meta.len(),
+                meta,
+                mtime,
+                mode,
+                offset,
+            };
+            offset = entry.end();
+            entries.push(entry);
+        }
+        let central_dir_size = entries.iter().map(|v| v.central_header_len()).sum();
+        let mut zip = Self {
+            entries,
+            hashes,
+            central_dir_offset: offset,
+            central_dir_size,
+            len: 0,
+        };
+        zip.len = offset + central_dir_size + zip.end_len();
+        Ok(zip)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Strong validator of the layout, it changes whenever a name, size or mtime does.
+    pub fn etag(&self) -> String {
+        let mut hasher = Sha256::new();
+        for entry in &self.entries {
+            hasher.update(entry.name.as_bytes());
+            hasher.update([0]);
+            hasher.update(entry.size.to_le_bytes());
+            hasher.update(entry.mtime.timestamp_millis().to_le_bytes());
+            hasher.update(entry.mode.to_le_bytes());
+        }
+        let digest = hasher.finalize();
+        format!(r#""{}""#, hex::encode(&digest[..16]))
+    }
+
+    /// Write the bytes from `start` to `end` (inclusive) of the archive.
+    pub async fn write_range<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        start: u64,
+        end: u64,
+    ) -> io::Result<()> {
+        let range = (start, end + 1);
+        let mut crcs: Vec<Option<u32>> = vec![None; self.entries.len()];
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.end() <= range.0 {
+                continue;
+            }
+            if entry.offset >= range.1 {
+                break;
+            }
+            write_slice(writer, &entry.local_header(), entry.offset, range).await?;
+            let data_offset = entry.data_offset();
+            let from = range.0.max(data_offset);
+            let to = range.1.min(data_offset + entry.size);
+            if from < to {
+                crcs[i] = entry
+                    .copy_data(writer, from - data_offset, to - data_offset)
+                    .await?;
+            }
+            let descriptor_offset = data_offset + entry.size;
+            if descriptor_offset < range.1 {
+                let crc = self.entry_crc(&mut crcs, i).await?;
+                write_slice(writer, &entry.descriptor(crc), descriptor_offset, range).await?;
+            }
+        }
+        if range.1 <= self.central_dir_offset {
+            return Ok(());
+        }
+        let mut offset = self.central_dir_offset;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let len = entry.central_header_len();
+            if offset + len > range.0 && offset < range.1 {
+                let crc = self.entry_crc(&mut crcs, i).await?;
+                write_slice(writer, &entry.central_header(crc), offset, range).await?;
+            }
+            offset += len;
+        }
+        write_slice(writer, &self.end_records(), offset, range).await?;
+        Ok(())
+    }
+
+    /// CRC of an entry, looked up in the hash cache when its data wasn't part of the range
+    async fn entry_crc(&self, crcs: &mut [Option<u32>], index: usize) -> io::Result<u32> {
+        if let Some(crc) = crcs[index] {
+            return Ok(crc);
+        }
+        let entry = &self.entries[index];
+        let crc = self
+            .hashes
+            .crc32(&entry.path, &entry.meta)
+            .await
+            .map_err(io::Error::other)?;
+        crcs[index] = Some(crc);
+        Ok(crc)
+    }
+
+    fn is_zip64(&self) -> bool {
+        self.entries.len() as u64 >= UINT16_MAX
+            || self.central_dir_offset >= UINT32_MAX
+            || self.central_dir_size >= UINT32_MAX
+    }
+
+    fn end_len(&self) -> u64 {
+        if self.is_zip64() {
+            ZIP64_END_LEN + ZIP64_LOCATOR_LEN + END_LEN
+        } else {
+            END_LEN
+        }
+    }
+
+    fn end_records(&self) -> Vec<u8> {
+        let count = self.entries.len() as u64;
+        let mut buf = vec![];
+        if self.is_zip64() {
+            let zip64_end_offset = self.central_dir_offset + self.central_dir_size;
+            put_u32(&mut buf, ZIP64_END_SIGNATURE);
+            put_u64(&mut buf, ZIP64_END_LEN - 12);
+            put_u16(&mut buf, MADE_BY_UNIX | VERSION_ZIP64);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u32(&mut buf, 0);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, self.central_dir_size);
+            put_u64(&mut buf, self.central_dir_offset);
+
+            put_u32(&mut buf, ZIP64_LOCATOR_SIGNATURE);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, zip64_end_offset);
+            put_u32(&mut buf, 1);
+        }
+        put_u32(&mut buf, END_SIGNATURE);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, count.min(UINT16_MAX) as u16);
+        put_u16(&mut buf, count.min(UINT16_MAX) as u16);
+        put_u32(&mut buf, self.central_dir_size.min(UINT32_MAX) as u32);
+        put_u32(&mut buf, self.central_dir_offset.min(UINT32_MAX) as u32);
+        put_u16(&mut buf, 0);
+        buf
+    }
+}
+
+impl Entry {
+    /// Sizes don't fit the data descriptor, readers tell by the zip64 extra in the central header
+    fn is_zip64(&self) -> bool {
+        self.size >= UINT32_MAX
+    }
+
+    fn has_zip64_extra(&self) -> bool {
+        self.is_zip64() || self.offset >= UINT32_MAX
+    }
+
+    fn version(&self) -> u16 {
+        if self.has_zip64_extra() {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        }
+    }
+
+    fn data_offset(&self) -> u64 {
+        self.offset + LOCAL_HEADER_LEN + self.name.len() as u64
+    }
+
+    fn descriptor_len(&self) -> u64 {
+        if self.is_zip64() {
+            24
+        } else {
+            16
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.data_offset() + self.size + self.descriptor_len()
+    }
+
+    fn central_header_len(&self) -> u64 {
+        let extra_len = if self.has_zip64_extra() {
+            ZIP64_EXTRA_LEN
+        } else {
+            0
+        };
+        CENTRAL_HEADER_LEN + self.name.len() as u64 + extra_len
+    }
+
+    fn local_header(&self) -> Vec<u8> {
+        let (time, date) = dos_datetime(&self.mtime);
+        let mut buf = vec![];
+        put_u32(&mut buf, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut buf, self.version());
+        put_u16(&mut buf, FLAGS);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, time);
+        put_u16(&mut buf, date);
+        // CRC and sizes are in the data descriptor
+        put_u32(&mut buf, 0);
+        put_u32(&mut buf, 0);
+        put_u32(&mut buf, 0);
+        put_u16(&mut buf, self.name.len() as u16);
+        put_u16(&mut buf, 0);
+        buf.extend_from_slice(self.name.as_bytes());
+        buf
+    }
+
+    fn descriptor(&self, crc: u32) -> Vec<u8> {
+        let mut buf = vec![];
+        put_u32(&mut buf, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut buf, crc);
+        if self.is_zip64() {
+            put_u64(&mut buf, self.size);
+            put_u64(&mut buf, self.size);
+        } else {
+            put_u32(&mut buf, self.size as u32);
+            put_u32(&mut buf, self.size as u32);
+        }
+        buf
+    }
+
+    fn central_header(&self, crc: u32) -> Vec<u8> {
+        let (time, date) = dos_datetime(&self.mtime);
+        let zip64 = self.has_zip64_extra();
+        let mut buf = vec![];
+        put_u32(&mut buf, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut buf, MADE_BY_UNIX | self.version());
+        put_u16(&mut buf, self.version());
+        put_u16(&mut buf, FLAGS);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, time);
+        put_u16(&mut buf, date);
+        put_u32(&mut buf, crc);
+        if zip64 {
+            put_u32(&mut buf, UINT32_MAX as u32);
+            put_u32(&mut buf, UINT32_MAX as u32);
+        } else {
+            put_u32(&mut buf, self.size as u32);
+            put_u32(&mut buf, self.size as u32);
+        }
+        put_u16(&mut buf, self.name.len() as u16);
+        put_u16(&mut buf, if zip64 { ZIP64_EXTRA_LEN as u16 } else { 0 });
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u32(&mut buf, (self.mode as u32) << 16);
+        put_u32(&mut buf, self.offset.min(UINT32_MAX) as u32);
+        buf.extend_from_slice(self.name.as_bytes());
+        if zip64 {
+            put_u16(&mut buf, ZIP64_EXTRA_ID);
+            put_u16(&mut buf, ZIP64_EXTRA_LEN as u16 - 4);
+            put_u64(&mut buf, self.size);
+            put_u64(&mut buf, self.size);
+            put_u64(&mut buf, self.offset);
+        }
+        buf
+    }
+
+    /// Copy the data from `from` to `to` (exclusive), returning the CRC if it covered the file.
+    async fn copy_data<W: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut W,
+        from: u64,
+        to: u64,
+    ) -> io::Result<Option<u32>> {
+        let mut file = File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(from)).await?;
+        let mut reader = file.take(to - from);
+        let mut hasher = Hasher::new();
+        let mut buf = vec![0; BUF_SIZE];
+        let mut remaining = to - from;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            writer.write_all(&buf[..n]).await?;
+            remaining -= n as u64;
+        }
+        if remaining > 0 {
+            return Err(changed_error(&self.path));
+        }
+        Ok((from == 0 && to == self.size).then(|| hasher.finalize()))
+    }
+}
+
+/// Write the part of `data`, found at `offset` of the archive, that falls in `range`.
+async fn write_slice<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+    offset: u64,
+    range: (u64, u64),
+) -> io::Result<()> {
+    let from = range.0.max(offset);
+    let to = range.1.min(offset + data.len() as u64);
+    if from < to {
+        writer
+            .write_all(&data[(from - offset) as usize..(to - offset) as usize])
+            .await?;
+    }
+    Ok(())
+}
+
+fn changed_error(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!("{} shrank while being archived", path.display()),
+    )
+}
+
+/// MS-DOS time and date, which can only hold 1980 to 2107
+fn dos_datetime(datetime: &DateTime<Utc>) -> (u16, u16) {
+    let year = datetime.year();
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    if year > 2107 {
+        return ((23 << 11) | (59 << 5) | 29, (127 << 9) | (12 << 5) | 31);
+    }
+    let time = (datetime.hour() << 11) | (datetime.minute() << 5) | (datetime.second() / 2);
+    let date = (((year - 1980) as u32) << 9) | (datetime.month() << 5) | datetime.day();
+    (time as u16, date as u16)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,226 @@
+//! Bandwidth limits for downloads and uploads, per connection, per user and for the whole process.
+//!
+//! Every limit is a token bucket refilled at its rate. Bytes are taken from the buckets before
+//! they are passed on and a bucket may run into debt, the transfer then sleeps until the debt is
+//! paid back. Chunks are cut into slices of at most a tenth of a second worth of data so the
+//! pace stays even instead of bursting a whole chunk and idling.
+
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::{Stream, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::body::Frame;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Smallest slice a chunk is cut into, however low the rate
+const MIN_SLICE: u64 = 4096;
+
+pub struct TokenBucket {
+    /// Bytes per second
+    rate: u64,
+    /// Most bytes that can go out at once after idling
+    burst: u64,
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+        let burst = (rate / 10).max(MIN_SLICE);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take `n` bytes, returns how long to wait before sending them.
+    fn take(&self, n: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.rate as f64;
+        state.tokens = (state.tokens + refill).min(self.burst as f64) - n as f64;
+        state.updated = now;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate as f64)
+        }
+    }
+}
+
+/// The limits configured for the process, shared by all sites.
+#[derive(Default)]
+pub struct RateLimits {
+    global: Option<Arc<TokenBucket>>,
+    user: Option<u64>,
+    connection: Option<u64>,
+    /// Buckets of the users with transfers going on
+    users: Mutex<HashMap<String, Weak<TokenBucket>>>,
+}
+
+impl RateLimits {
+    pub fn new(global: Option<u64>, user: Option<u64>, connection: Option<u64>) -> Self {
+        Self {
+            global: global.map(|v| Arc::new(TokenBucket::new(v))),
+            user,
+            connection,
+            users: Default::default(),
+        }
+    }
+
+    /// A bucket for a new connection
+    pub fn connection(&self) -> Option<ConnectionLimit> {
+        self.connection
+            .map(|v| ConnectionLimit(Arc::new(TokenBucket::new(v))))
+    }
+
+    /// The throttle of a request. Anonymous users are told apart by their address.
+    pub fn throttle(
+        &self,
+        user: Option<&str>,
+        addr: Option<IpAddr>,
+        connection: Option<&ConnectionLimit>,
+    ) -> Throttle {
+        let mut buckets = vec![];
+        if let Some(bucket) = connection {
+            buckets.push(bucket.0.clone());
+        }
+        if let Some(rate) = self.user {
+            let key = match (user, addr) {
+                (Some(user), _) => format!("user:{user}"),
+                (None, Some(addr)) => format!("addr:{addr}"),
+                (None, None) => String::new(),
+            };
+            let mut users = self.users.lock().unwrap();
+            let bucket = match users.get(&key).and_then(|v| v.upgrade()) {
+                Some(bucket) => bucket,
+                None => {
+                    users.retain(|_, v| v.strong_count() > 0);
+                    let bucket = Arc::new(TokenBucket::new(rate));
+                    users.insert(key, Arc::downgrade(&bucket));
+                    bucket
+                }
+            };
+            buckets.push(bucket);
+        }
+        if let Some(bucket) = &self.global {
+            buckets.push(bucket.clone());
+        }
+        Throttle { buckets }
+    }
+}
+
+/// The bucket of a connection, passed to its requests as an extension.
+#[derive(Clone)]
+pub struct ConnectionLimit(Arc<TokenBucket>);
+
+/// The buckets a transfer draws from.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    pub fn is_unlimited(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Pace the chunks of `stream` to the limits.
+    pub fn stream<S, E>(&self, stream: S) -> BoxStream<'static, Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Send + 'static,
+    {
+        if self.is_unlimited() {
+            return stream.boxed();
+        }
+        self.pace(stream).boxed()
+    }
+
+    /// Pace a response body to the limits.
+    pub fn body(&self, body: BoxBody<Bytes, anyhow::Error>) -> BoxBody<Bytes, anyhow::Error> {
+        if self.is_unlimited() {
+            return body;
+        }
+        let stream = self.pace(body.into_data_stream());
+        BodyExt::boxed(StreamBody::new(stream.map(|v| v.map(Frame::data))))
+    }
+
+    fn pace<S, E>(&self, stream: S) -> impl Stream<Item = Result<Bytes, E>>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        let buckets = self.buckets.clone();
+        let slice = buckets.iter().map(|v| v.burst).min().unwrap_or(u64::MAX) as usize;
+        async_stream::stream! {
+            futures_util::pin_mut!(stream);
+            while let Some(chunk) = stream.next().await {
+                let mut chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        yield Err(err);
+                        break;
+                    }
+                };
+                while !chunk.is_empty() {
+                    let part = chunk.split_to(chunk.len().min(slice));
+                    let wait = buckets
+                        .iter()
+                        .map(|v| v.take(part.len() as u64))
+                        .max()
+                        .unwrap_or_default();
+                    if !wait.is_zero() {
+                        tokio::time::sleep(wait).await;
+                    }
+                    yield Ok(part);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(100_000);
+        // A tenth of a second worth of bytes goes out at once
+        assert_eq!(bucket.take(10_000), Duration::ZERO);
+        let wait = bucket.take(50_000);
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500));
+        // Debts add up
+        let wait = bucket.take(50_000);
+        assert!(wait > Duration::from_millis(950) && wait <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_user_buckets() {
+        let limits = RateLimits::new(None, Some(1000), None);
+        let addr = "127.0.0.1".parse().ok();
+        let a = limits.throttle(Some("alice"), addr, None);
+        let b = limits.throttle(Some("alice"), None, None);
+        let c = limits.throttle(None, addr, None);
+        assert!(Arc::ptr_eq(&a.buckets[0], &b.buckets[0]));
+        assert!(!Arc::ptr_eq(&a.buckets[0], &c.buckets[0]));
+        drop((a, b));
+        let a = limits.throttle(Some("alice"), addr, None);
+        assert_eq!(Arc::strong_count(&a.buckets[0]), 1);
+        assert!(RateLimits::default()
+            .throttle(None, addr, None)
+            .is_unlimited());
+    }
+}
@@ -0,0 +1,125 @@
+//! State of uploads made with the tus resumable upload protocol, see <https://tus.io/protocols/resumable-upload>.
+//!
+//! Pending uploads are staged next to their target by a [`PartialStore`] and renamed into place
+//! once complete.
+
+use crate::args::ConflictPolicy;
+use crate::partial_upload::{PartialStore, PartialUpload};
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+pub const TUS_CHECKSUM_ALGORITHMS: &str = "sha1,sha256,md5";
+pub const TUS_CONTENT_TYPE: &str = "application/offset+octet-stream";
+const TUS_EXPIRATION_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TusUpload {
+    /// Path of the uploaded file relative to the serve path
+    pub target: String,
+    pub length: u64,
+    pub metadata: Option<String>,
+    pub expires: i64,
+    /// Applied again when the upload completes, the name may have been taken meanwhile
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+impl TusUpload {
+    pub fn new(
+        target: String,
+        length: u64,
+        metadata: Option<String>,
+        conflict: ConflictPolicy,
+    ) -> Self {
+        Self {
+            target,
+            length,
+            metadata,
+            expires: Utc::now().timestamp() + TUS_EXPIRATION_SECS,
+            conflict,
+        }
+    }
+
+    /// Value of the `Upload-Expires` header
+    pub fn expires_header(&self) -> String {
+        Utc.timestamp_opt(self.expires, 0)
+            .single()
+            .unwrap_or_default()
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+}
+
+impl PartialUpload for TusUpload {
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn expires(&self) -> i64 {
+        self.expires
+    }
+}
+
+pub type TusStore = PartialStore<TusUpload>;
+
+/// Parse the `filename` (or `name`) entry of `Upload-Metadata`.
+pub fn metadata_filename(metadata: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut parts = pair.trim().splitn(2, ' ');
+        let key = parts.next()?;
+        if key != "filename" && key != "name" {
+            return None;
+        }
+        let value = STANDARD.decode(parts.next()?.trim()).ok()?;
+        String::from_utf8(value).ok().filter(|v| !v.is_empty())
+    })
+}
+
+/// Incremental verifier of the `Upload-Checksum` header.
+pub enum TusChecksum {
+    Sha1(Sha1, Vec<u8>),
+    Sha256(Sha256, Vec<u8>),
+    Md5(md5::Context, Vec<u8>),
+}
+
+impl TusChecksum {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (algorithm, checksum) = value
+            .trim()
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Invalid Upload-Checksum"))?;
+        let checksum = STANDARD
+            .decode(checksum.trim())
+            .map_err(|_| anyhow!("Invalid Upload-Checksum"))?;
+        let ret = match algorithm {
+            "sha1" => Self::Sha1(Sha1::new(), checksum),
+            "sha256" => Self::Sha256(Sha256::new(), checksum),
+            "md5" => Self::Md5(md5::Context::new(), checksum),
+            _ => bail!("Unsupported checksum algorithm `{algorithm}`"),
+        };
+        Ok(ret)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha1(hasher, _) => hasher.update(data),
+            Self::Sha256(hasher, _) => hasher.update(data),
+            Self::Md5(ctx, _) => ctx.consume(data),
+        }
+    }
+
+    pub fn verify(self) -> bool {
+        match self {
+            Self::Sha1(hasher, expect) => hasher.finalize().as_slice() == expect,
+            Self::Sha256(hasher, expect) => hasher.finalize().as_slice() == expect,
+            Self::Md5(ctx, expect) => ctx.finalize().0.as_slice() == expect,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Upload sessions: a client opens a session with the total size of a file, writes numbered
+//! chunks at their offsets in parallel and in any order, then commits it once every byte arrived.
+//!
+//! Like tus uploads, the partial file is staged next to its target and the received ranges are
+//! tracked in its record, sessions idle for longer than a day are dropped.
+
+use crate::args::ConflictPolicy;
+use crate::partial_upload::{PartialStore, PartialUpload};
+
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+const SESSION_EXPIRATION_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadSession {
+    /// Path of the uploaded file relative to the serve path
+    pub target: String,
+    pub length: u64,
+    pub conflict: Option<ConflictPolicy>,
+    /// Sorted and merged byte ranges received so far, end exclusive
+    pub received: Vec<(u64, u64)>,
+    pub expires: i64,
+}
+
+impl UploadSession {
+    pub fn new(target: String, length: u64, conflict: Option<ConflictPolicy>) -> Self {
+        Self {
+            target,
+            length,
+            conflict,
+            received: vec![],
+            expires: Utc::now().timestamp() + SESSION_EXPIRATION_SECS,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.length == 0 || self.received == [(0, self.length)]
+    }
+
+    /// Value of the `X-Upload-Received` header, inclusive ranges like `0-1023,2048-4095`
+    pub fn received_header(&self) -> String {
+        self.received
+            .iter()
+            .map(|(start, end)| format!("{start}-{}", end - 1))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn add_range(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        self.received.push((start, end));
+        self.received.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.received.len());
+        for &(start, end) in &self.received {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.received = merged;
+    }
+}
+
+impl PartialUpload for UploadSession {
+    fn target(&self) -> &str {
+        &self.target
+    }
+
+    fn expires(&self) -> i64 {
+        self.expires
+    }
+}
+
+pub type SessionStore = PartialStore<UploadSession>;
+
+impl SessionStore {
+    /// Record that the bytes `start..end` were written, returns the updated session.
+    pub async fn add_range(
+        &self,
+        target: &str,
+        id: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Option<UploadSession>> {
+        self.update(target, id, |session| {
+            session.add_range(start, end);
+            session.expires = Utc::now().timestamp() + SESSION_EXPIRATION_SECS;
+        })
+        .await
+    }
+}
@@ -46,6 +46,19 @@ pub async fn get_file_mtime_and_mode(path: &Path) -> Result<(DateTime<Utc>, u16)
     Ok((datetime, 0o644))
 }
 
+/// Set the modification time of a file, opened for writing as Windows requires it.
+pub async fn set_file_mtime(path: &Path, mtime: SystemTime) -> std::io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_modified(mtime)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
 pub fn try_get_file_name(path: &Path) -> Result<&str> {
     path.file_name()
         .and_then(|v| v.to_str())
@@ -98,6 +111,46 @@ pub fn load_private_key<T: AsRef<Path>>(file_name: T) -> Result<PrivateKeyDer<'s
     })
 }
 
+// Generate a self-signed certificate and its private key in PEM format.
+#[cfg(feature = "tls")]
+pub fn generate_self_signed_cert(subject_alt_names: Vec<String>) -> Result<(String, String)> {
+    let mut params = rcgen::CertificateParams::new(subject_alt_names)
+        .with_context(|| "Invalid subject alternative names")?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "dufs self-signed");
+    let key_pair = rcgen::KeyPair::generate().with_context(|| "Failed to generate key pair")?;
+    let cert = params
+        .self_signed(&key_pair)
+        .with_context(|| "Failed to generate self-signed certificate")?;
+    Ok((cert.pem(), key_pair.serialize_pem()))
+}
+
+// Parse certificates and private key from PEM strings.
+#[cfg(feature = "tls")]
+pub fn parse_pem_cert_and_key(
+    cert_pem: &str,
+    key_pem: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_slice_iter(cert_pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| "Invalid certificate data")?;
+    let key =
+        PrivateKeyDer::from_pem_slice(key_pem.as_bytes()).with_context(|| "Invalid key data")?;
+    Ok((certs, key))
+}
+
+// Colon separated uppercase hex of the certificate's SHA-256 digest.
+#[cfg(feature = "tls")]
+pub fn cert_fingerprint(cert: &CertificateDer) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|v| format!("{v:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
 pub fn parse_range(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
     let (unit, ranges) = range.split_once('=')?;
     if unit != "bytes" {
@@ -109,7 +162,7 @@ pub fn parse_range(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
         let (start, end) = range.trim().split_once('-')?;
         if start.is_empty() {
             let offset = end.parse::<u64>().ok()?;
-            if offset <= size {
+            if offset > 0 && offset <= size {
                 result.push((size - offset, size - 1));
             } else {
                 return None;
@@ -136,6 +189,40 @@ pub fn parse_range(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
     Some(result)
 }
 
+/// Sort ranges and merge the ones that overlap or touch, a client can't make a part be sent twice.
+pub fn coalesce_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Parse a size such as `512`, `64K`, `10M` or `1G`, units are powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (num, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, ""),
+    };
+    let scale: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" | "KI" => 1 << 10,
+        "M" | "MI" => 1 << 20,
+        "G" | "GI" => 1 << 30,
+        "T" | "TI" => 1 << 40,
+        _ => return Err(anyhow!("Invalid size `{value}`")),
+    };
+    num.parse::<u64>()
+        .ok()
+        .and_then(|v| v.checked_mul(scale))
+        .ok_or_else(|| anyhow!("Invalid size `{value}`"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,5 +267,32 @@ mod tests {
         assert_eq!(parse_range("bytes=0-500", 500), None);
         assert_eq!(parse_range("bytes=0-199,", 500), None);
         assert_eq!(parse_range("bytes=0-199, 500-", 500), None);
+        assert_eq!(parse_range("bytes=-0", 500), None);
+    }
+
+    #[test]
+    fn test_coalesce_ranges() {
+        assert_eq!(
+            coalesce_ranges(vec![(400, 499), (0, 199), (100, 299)]),
+            vec![(0, 299), (400, 499)]
+        );
+        assert_eq!(
+            coalesce_ranges(vec![(0, 99), (100, 199), (201, 299)]),
+            vec![(0, 199), (201, 299)]
+        );
+        assert_eq!(coalesce_ranges(vec![(0, 499); 1000]), vec![(0, 499)]);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("10m").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1GiB").unwrap(), 1024 * 1024 * 1024);
+        assert_eq!(parse_size("2 MB").unwrap(), 2 * 1024 * 1024);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("-1").is_err());
     }
 }
@@ -0,0 +1,189 @@
+//! Zero-copy file downloads over plain HTTP/1 connections.
+//!
+//! A file is sent as placeholder frames, slices of a static buffer that stand for ranges of the
+//! file. When hyper writes one of them to a [`ZeroCopyStream`], the range is sent with `sendfile`
+//! instead, straight from the page cache to the socket, the file is never read into the process.
+//! Hyper writes the frames of a response in order and one response at a time, so the ranges are
+//! queued in the same order.
+
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
+use hyper::body::Frame;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+
+/// What the placeholder frames are sliced from, also the most a frame stands for
+static PLACEHOLDER: [u8; 1024 * 1024] = [0; 1024 * 1024];
+
+/// A range of a file to send, the body of a response for a whole file or a single range.
+#[derive(Debug, Clone)]
+pub struct FileSpan {
+    pub file: Arc<File>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Handle to the connection of a request that can send files without copying.
+#[derive(Debug, Clone, Default)]
+pub struct ZeroCopy {
+    /// Ranges of the placeholder frames not sent yet
+    spans: Arc<Mutex<VecDeque<FileSpan>>>,
+}
+
+impl ZeroCopy {
+    /// Placeholder body for `span`, only the connection this handle belongs to can send it.
+    pub fn body(&self, span: FileSpan) -> BoxBody<Bytes, anyhow::Error> {
+        let spans = self.spans.clone();
+        let end = span.offset + span.len;
+        let stream = async_stream::stream! {
+            let mut position = span.offset;
+            while position < end {
+                let len = (end - position).min(PLACEHOLDER.len() as u64);
+                spans.lock().unwrap().push_back(FileSpan {
+                    file: span.file.clone(),
+                    offset: position,
+                    len,
+                });
+                position += len;
+                let data = Bytes::from_static(&PLACEHOLDER[..len as usize]);
+                yield Ok::<_, anyhow::Error>(Frame::data(data));
+            }
+        };
+        StreamBody::new(stream).boxed()
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use self::stream::ZeroCopyStream;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod stream {
+    use super::{ZeroCopy, PLACEHOLDER};
+    use std::io::{self, IoSlice};
+    use std::os::fd::AsRawFd;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf};
+    use tokio::net::TcpStream;
+
+    /// A TCP connection that sends placeholder frames as the file ranges they stand for.
+    pub struct ZeroCopyStream {
+        inner: TcpStream,
+        zero_copy: ZeroCopy,
+    }
+
+    impl ZeroCopyStream {
+        pub fn new(inner: TcpStream) -> Self {
+            Self {
+                inner,
+                zero_copy: ZeroCopy::default(),
+            }
+        }
+
+        pub fn zero_copy(&self) -> ZeroCopy {
+            self.zero_copy.clone()
+        }
+
+        /// Send the range the placeholder `buf` stands for, or a part of it.
+        fn poll_send_file(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let mut spans = self.zero_copy.spans.lock().unwrap();
+            let Some(span) = spans.front_mut().filter(|v| v.len == buf.len() as u64) else {
+                return Poll::Ready(Err(io::Error::other("Placeholder without a file range")));
+            };
+            loop {
+                ready!(self.inner.poll_write_ready(cx))?;
+                let ret = self.inner.try_io(Interest::WRITABLE, || {
+                    send_file(&self.inner, &span.file, span.offset, buf.len())
+                });
+                match ret {
+                    Ok(0) => {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "File shrank while being sent",
+                        )))
+                    }
+                    Ok(n) => {
+                        span.offset += n as u64;
+                        span.len -= n as u64;
+                        if span.len == 0 {
+                            spans.pop_front();
+                        }
+                        return Poll::Ready(Ok(n));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) => return Poll::Ready(Err(err)),
+                }
+            }
+        }
+    }
+
+    fn is_placeholder(buf: &[u8]) -> bool {
+        !buf.is_empty() && PLACEHOLDER.as_ptr_range().contains(&buf.as_ptr())
+    }
+
+    fn send_file(
+        socket: &TcpStream,
+        file: &std::fs::File,
+        offset: u64,
+        count: usize,
+    ) -> io::Result<usize> {
+        let mut offset = libc::off_t::try_from(offset).map_err(io::Error::other)?;
+        let n = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    impl AsyncRead for ZeroCopyStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for ZeroCopyStream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            if is_placeholder(buf) {
+                return this.poll_send_file(cx, buf);
+            }
+            Pin::new(&mut this.inner).poll_write(cx, buf)
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            // The bytes before a placeholder are written as they are, the placeholder on its own
+            match bufs.iter().position(|v| is_placeholder(v)) {
+                Some(0) => this.poll_send_file(cx, &bufs[0]),
+                Some(i) => Pin::new(&mut this.inner).poll_write_vectored(cx, &bufs[..i]),
+                None => Pin::new(&mut this.inner).poll_write_vectored(cx, bufs),
+            }
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            // Hyper then queues the frames as they are instead of copying them into one buffer
+            true
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        }
+    }
+}
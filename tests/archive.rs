@@ -0,0 +1,395 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Map entry paths of a tar archive to their headers
+fn tar_entries(data: impl Read) -> Result<HashMap<String, tar::Header>, Error> {
+    let mut archive = tar::Archive::new(data);
+    let mut entries = HashMap::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        entries.insert(path, entry.header().clone());
+    }
+    Ok(entries)
+}
+
+#[rstest]
+#[case("tar", "application/x-tar", "dir1.tar")]
+#[case("tgz", "application/gzip", "dir1.tar.gz")]
+#[case("tzst", "application/zstd", "dir1.tar.zst")]
+#[case("archive=tar.gz", "application/gzip", "dir1.tar.gz")]
+fn get_dir_tar(
+    #[with(&["-A"])] server: TestServer,
+    #[case] query: &str,
+    #[case] content_type: &str,
+    #[case] filename: &str,
+) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = server.path().join("dir1/test.txt");
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o750))?;
+    }
+    let resp = reqwest::blocking::get(format!("{}dir1/?{query}", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), content_type);
+    assert!(resp
+        .headers()
+        .get("content-disposition")
+        .unwrap()
+        .to_str()?
+        .contains(filename));
+    let body = resp.bytes()?;
+    let entries = match content_type {
+        "application/gzip" => tar_entries(flate2::read::GzDecoder::new(&body[..]))?,
+        "application/zstd" => tar_entries(zstd::Decoder::new(&body[..])?)?,
+        _ => tar_entries(&body[..])?,
+    };
+    assert!(entries.contains_key("test.txt"));
+    assert!(entries.contains_key("index.html"));
+
+    let meta = std::fs::metadata(server.path().join("dir1/test.txt"))?;
+    let mtime = meta
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let header = &entries["test.txt"];
+    assert_eq!(header.mtime()?, mtime);
+    #[cfg(unix)]
+    assert_eq!(header.mode()? & 0o7777, 0o750);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_tar_hidden(
+    #[with(&["-A", "--hidden", ".git,*.html"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?tar", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let entries = tar_entries(&resp.bytes()?[..])?;
+    assert!(entries.contains_key("dir1/test.txt"));
+    assert!(!entries.keys().any(|v| v.starts_with(".git")));
+    assert!(!entries.keys().any(|v| v.ends_with(".html")));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_tar_access_paths(
+    #[with(&["-a", "@/dir1:ro,/dir2:wo", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?tar", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let entries = tar_entries(&resp.bytes()?[..])?;
+    assert!(entries.contains_key("dir1/test.txt"));
+    assert!(!entries
+        .keys()
+        .any(|v| v.starts_with("dir2/") || v.starts_with("dir3/")));
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn get_dir_tar_symlinks(
+    #[with(&["-A", "--allow-symlink"])] server: TestServer,
+) -> Result<(), Error> {
+    std::os::unix::fs::symlink("test.txt", server.path().join("dir1/link.txt"))?;
+    let resp = reqwest::blocking::get(format!("{}dir1/?tar", server.url()))?;
+    let entries = tar_entries(&resp.bytes()?[..])?;
+    assert!(entries["link.txt"].entry_type().is_file());
+
+    let resp = reqwest::blocking::get(format!("{}dir1/?tar&symlinks", server.url()))?;
+    let entries = tar_entries(&resp.bytes()?[..])?;
+    let header = &entries["link.txt"];
+    assert!(header.entry_type().is_symlink());
+    assert_eq!(header.link_name()?.unwrap().to_str(), Some("test.txt"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_archive_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?archive=rar", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_tar_not_allow(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?tar", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+/// Read every entry of a zip, their CRCs are checked
+fn zip_entries(data: Vec<u8>) -> Result<HashMap<String, Vec<u8>>, Error> {
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let reader = async_zip::base::read::mem::ZipFileReader::new(data).await?;
+        let mut entries = HashMap::new();
+        for (i, entry) in reader.file().entries().iter().enumerate() {
+            let name = entry.filename().as_str()?.to_string();
+            let mut buf = vec![];
+            reader
+                .reader_with_entry(i)
+                .await?
+                .read_to_end_checked(&mut buf)
+                .await?;
+            entries.insert(name, buf);
+        }
+        Ok(entries)
+    })
+}
+
+#[rstest]
+fn get_dir_zip_stored(
+    #[with(&["-A", "--compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/?zip", server.url());
+    let resp = fetch!(b"HEAD", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    let content_length = resp.headers().get("content-length").unwrap().clone();
+
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+    assert!(resp.headers().contains_key("etag"));
+    assert_eq!(
+        resp.headers().get("content-length").unwrap(),
+        content_length
+    );
+    let body = resp.bytes()?.to_vec();
+    assert_eq!(body.len().to_string(), content_length.to_str()?);
+    let entries = zip_entries(body)?;
+    assert_eq!(entries["test.txt"], b"This is dir1/test.txt");
+    assert!(entries.contains_key("index.html"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_stored_range(
+    #[with(&["-A", "--compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}?zip", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let body = resp.bytes()?;
+    let size = body.len();
+
+    // Resume the download in pieces, each of which reads a data descriptor or the central
+    // directory without having streamed the files before it
+    let mut pieces = vec![];
+    for range in [
+        "0-99".to_string(),
+        format!("100-{}", size / 2),
+        format!("{}-{}", size / 2 + 1, size - 30),
+        format!("{}-", size - 29),
+    ] {
+        let resp = fetch!(b"GET", &url)
+            .header("range", format!("bytes={range}"))
+            .header("if-range", etag.clone())
+            .send()?;
+        assert_eq!(resp.status(), 206);
+        assert!(resp
+            .headers()
+            .get("content-range")
+            .unwrap()
+            .to_str()?
+            .ends_with(&format!("/{size}")));
+        pieces.extend_from_slice(&resp.bytes()?);
+    }
+    assert_eq!(pieces, body);
+
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=0-99")
+        .header("if-range", "\"outdated\"")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?.len(), size);
+
+    let resp = fetch!(b"GET", &url)
+        .header("range", format!("bytes={size}-"))
+        .send()?;
+    assert_eq!(resp.status(), 416);
+
+    let resp = fetch!(b"GET", &url).header("if-none-match", etag).send()?;
+    assert_eq!(resp.status(), 304);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_store_compressed(
+    #[with(&["-A", "--compress", "high"])] server: TestServer,
+) -> Result<(), Error> {
+    for name in ["photo.JPG", "movie.mp4", "data.zst"] {
+        std::fs::write(server.path().join("dir1").join(name), [0u8; 1024])?;
+    }
+    let body = reqwest::blocking::get(format!("{}dir1/?zip", server.url()))?
+        .bytes()?
+        .to_vec();
+    let compressions = tokio::runtime::Runtime::new()?.block_on(async {
+        let reader = async_zip::base::read::mem::ZipFileReader::new(body).await?;
+        let mut compressions = HashMap::new();
+        for entry in reader.file().entries() {
+            compressions.insert(entry.filename().as_str()?.to_string(), entry.compression());
+        }
+        Ok::<_, Error>(compressions)
+    })?;
+    assert_eq!(compressions["photo.JPG"], async_zip::Compression::Stored);
+    assert_eq!(compressions["movie.mp4"], async_zip::Compression::Stored);
+    assert_eq!(compressions["data.zst"], async_zip::Compression::Stored);
+    assert_eq!(compressions["test.txt"], async_zip::Compression::Xz);
+    assert_eq!(compressions["index.html"], async_zip::Compression::Xz);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_archive_jobs(
+    #[with(&["-A", "--archive-jobs", "1"])] server: TestServer,
+) -> Result<(), Error> {
+    // Downloads beyond the cap wait for their turn
+    let handles: Vec<_> = ["zip", "tgz", "tzst", "tar"]
+        .into_iter()
+        .map(|format| {
+            let url = format!("{}?{format}", server.url());
+            std::thread::spawn(move || -> Option<usize> {
+                let resp = reqwest::blocking::get(url).ok()?;
+                if resp.status() != 200 {
+                    return None;
+                }
+                Some(resp.bytes().ok()?.len())
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap().unwrap() > 0);
+    }
+    let entries =
+        tar_entries(&reqwest::blocking::get(format!("{}?tar", server.url()))?.bytes()?[..])?;
+    assert!(entries.contains_key("dir1/test.txt"));
+    Ok(())
+}
+
+fn post_selection(url: String, paths: &[&str]) -> Result<reqwest::blocking::Response, Error> {
+    let body = paths
+        .iter()
+        .map(|v| format!("path={}", urlencoding(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    Ok(fetch!(b"POST", url)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()?)
+}
+
+fn urlencoding(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+#[rstest]
+fn post_selection_tar(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = post_selection(
+        format!("{}?tar", server.url()),
+        &["dir1", "dir2/test.txt", "test.html", "dir1/test.txt"],
+    )?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/x-tar"
+    );
+    let entries = tar_entries(&resp.bytes()?[..])?;
+    assert!(entries.contains_key("dir1/test.txt"));
+    assert!(entries.contains_key("dir1/index.html"));
+    assert!(entries.contains_key("dir2/test.txt"));
+    assert!(entries.contains_key("test.html"));
+    assert!(!entries.contains_key("dir2/index.html"));
+    assert!(!entries.contains_key("test.txt"));
+    Ok(())
+}
+
+#[rstest]
+fn post_selection_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = post_selection(format!("{}dir1/?zip", server.url()), &["test.txt"])?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/zip"
+    );
+    let body = resp.bytes()?;
+    // A single local file header, named relative to the requested folder
+    assert_eq!(body.windows(4).filter(|v| v == b"PK\x03\x04").count(), 1);
+    assert!(body.windows(8).any(|v| v == b"test.txt"));
+    Ok(())
+}
+
+#[rstest]
+fn post_selection_read_only(
+    #[with(&["-a", "user:pass@/:rw", "-a", "@/dir1:ro", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    // Anonymous users can only read dir1, a POST of the selection is still a download
+    let resp = post_selection(format!("{}dir1/?tar", server.url()), &["test.txt"])?;
+    assert_eq!(resp.status(), 200);
+    let resp = post_selection(format!("{}?tar", server.url()), &["dir1/test.txt"])?;
+    assert_eq!(resp.status(), 200);
+    let resp = post_selection(format!("{}?tar", server.url()), &["dir1", "test.txt"])?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn archive_query_keeps_uploads_guarded(
+    #[with(&["-a", "user:pass@/:rw", "-a", "@/:ro", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    // A tus upload created with an archive query
+    let resp = fetch!(b"POST", format!("{}?zip", server.url()))
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", "0")
+        .header("Upload-Metadata", "filename dGVzdC50eHQ=")
+        .header("Upload-Conflict", "keep-both")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    // An upload session opened on a file with an archive query
+    let resp = fetch!(b"POST", format!("{}test.txt?session&zip", server.url()))
+        .header("X-Upload-Length", "0")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    // A file is never a selection
+    let resp = fetch!(b"POST", format!("{}test.txt?zip", server.url())).send()?;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("test.txt"))?,
+        "This is test.txt"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn post_selection_forbidden(
+    #[with(&["-a", "@/:ro,/dir1:wo", "--hidden", ".git", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}?tar", server.url());
+    let resp = post_selection(url.clone(), &["dir1"])?;
+    assert_eq!(resp.status(), 403);
+    let resp = post_selection(url.clone(), &["dir1/test.txt"])?;
+    assert_eq!(resp.status(), 403);
+    let resp = post_selection(url.clone(), &[".git"])?;
+    assert_eq!(resp.status(), 404);
+    let resp = post_selection(url.clone(), &["404.txt"])?;
+    assert_eq!(resp.status(), 404);
+    let resp = post_selection(url.clone(), &["../test.txt"])?;
+    assert_eq!(resp.status(), 400);
+    let resp = post_selection(url.clone(), &[])?;
+    assert_eq!(resp.status(), 400);
+    let resp = post_selection(format!("{}dir1/?tar", server.url()), &["test.txt"])?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn post_selection_not_allow(server: TestServer) -> Result<(), Error> {
+    let resp = post_selection(format!("{}?tar", server.url()), &["test.txt"])?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
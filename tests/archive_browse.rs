@@ -0,0 +1,209 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::io::Write;
+use utils::{retrieve_index_paths, retrieve_json};
+
+/// Save the zip download of `dir1` next to it
+fn save_dir1_zip(server: &TestServer) -> Result<(), Error> {
+    let zip = fetch!(b"GET", format!("{}dir1/?zip", server.url()))
+        .send()?
+        .bytes()?;
+    std::fs::write(server.path().join("dir1.zip"), zip)?;
+    Ok(())
+}
+
+fn make_tar() -> Result<Vec<u8>, Error> {
+    let mut builder = tar::Builder::new(vec![]);
+    for (name, data) in [("docs/a.txt", "Hello World"), ("b.txt", "Bye")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(1700000000);
+        header.set_cksum();
+        builder.append_data(&mut header, name, data.as_bytes())?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+#[rstest]
+fn browse_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    save_dir1_zip(&server)?;
+    let resp = reqwest::blocking::get(format!("{}dir1.zip/!/", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let text = resp.text()?;
+    let paths = retrieve_index_paths(&text);
+    assert!(paths.contains("test.txt"));
+    assert!(paths.contains("index.html"));
+    let data = retrieve_json(&text).unwrap();
+    assert_eq!(data["href"], "/dir1.zip/!");
+    assert_eq!(data["in_archive"], true);
+    assert_eq!(data["allow_upload"], false);
+    assert_eq!(data["allow_delete"], false);
+    assert_eq!(data["allow_archive"], false);
+    Ok(())
+}
+
+#[rstest]
+#[case("dir1.zip/!/test.txt")]
+#[case("dir1.zip?archive-path=test.txt")]
+#[case("dir1.zip?archive-path=/test.txt")]
+fn get_zip_entry(#[with(&["-A"])] server: TestServer, #[case] url: &str) -> Result<(), Error> {
+    save_dir1_zip(&server)?;
+    let resp = reqwest::blocking::get(format!("{}{url}", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+    assert_eq!(resp.text()?, "This is dir1/test.txt");
+    Ok(())
+}
+
+#[rstest]
+fn browse_tar_implied_dirs(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = reqwest::blocking::get(format!("{}docs.tar/!/?simple", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "docs/\nb.txt\n");
+    let resp = reqwest::blocking::get(format!("{}docs.tar/!/docs?json", server.url()))?;
+    let data: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(data["paths"][0]["name"], "a.txt");
+    assert_eq!(data["paths"][0]["size"], 11);
+    assert_eq!(data["paths"][0]["mtime"], 1700000000000u64);
+    Ok(())
+}
+
+#[rstest]
+fn get_tar_entry_range(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = fetch!(b"GET", format!("{}docs.tar/!/docs/a.txt", server.url()))
+        .header("range", "bytes=6-")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 6-10/11"
+    );
+    assert_eq!(resp.text()?, "World");
+    Ok(())
+}
+
+#[rstest]
+fn get_compressed_entry_range(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&make_tar()?)?;
+    std::fs::write(server.path().join("docs.tgz"), encoder.finish()?)?;
+    let resp = fetch!(b"GET", format!("{}docs.tgz/!/docs/a.txt", server.url()))
+        .header("range", "bytes=6-")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("accept-ranges").unwrap(), "none");
+    assert_eq!(resp.text()?, "Hello World");
+    Ok(())
+}
+
+#[rstest]
+#[case(server(&["-A", "--extract-max-entries", "1"]), "docs.tar", "too many entries")]
+#[case(server(&["-A", "--extract-max-size", "10"]), "docs.tgz", "expands too large")]
+fn browse_archive_limits(
+    #[case] server: TestServer,
+    #[case] name: &str,
+    #[case] message: &str,
+) -> Result<(), Error> {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&make_tar()?)?;
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    std::fs::write(server.path().join("docs.tgz"), encoder.finish()?)?;
+    let resp = reqwest::blocking::get(format!("{}{name}/!/", server.url()))?;
+    assert_eq!(resp.status(), 413);
+    assert!(resp.text()?.contains(message));
+    Ok(())
+}
+
+#[rstest]
+fn browse_archive_changed(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = reqwest::blocking::get(format!("{}docs.tar/!/b.txt", server.url()))?;
+    assert_eq!(resp.text()?, "Bye");
+    // The listing of the previous archive must not be served from the cache
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(7);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "b.txt", &b"Goodbye"[..])?;
+    std::fs::write(server.path().join("docs.tar"), builder.into_inner()?)?;
+    let resp = reqwest::blocking::get(format!("{}docs.tar/!/b.txt", server.url()))?;
+    assert_eq!(resp.text()?, "Goodbye");
+    let resp = reqwest::blocking::get(format!("{}docs.tar/!/docs/a.txt", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn propfind_archive(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = fetch!(b"PROPFIND", format!("{}docs.tar/!/docs", server.url())).send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/docs.tar/%21/docs/</D:href>"));
+    assert!(body.contains("<D:href>/docs.tar/%21/docs/a.txt</D:href>"));
+    assert!(body.contains("<D:getcontentlength>11</D:getcontentlength>"));
+    Ok(())
+}
+
+#[rstest]
+fn archive_read_only(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = fetch!(b"PUT", format!("{}docs.tar/!/new.txt", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 405);
+    let resp = fetch!(b"DELETE", format!("{}docs.tar/!/b.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 405);
+    assert_eq!(std::fs::read(server.path().join("docs.tar"))?, make_tar()?);
+    Ok(())
+}
+
+#[rstest]
+#[case("docs.tar/!/missing.txt")]
+#[case("docs.tar?archive-path=../b.txt")]
+#[case("index.html/!/")]
+#[case("missing.zip/!/a.txt")]
+fn archive_entry_not_found(
+    #[with(&["-A"])] server: TestServer,
+    #[case] url: &str,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("docs.tar"), make_tar()?)?;
+    let resp = reqwest::blocking::get(format!("{}{url}", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn browse_archive_hidden(
+    #[with(&["-A", "--hidden", "*.html"])] server: TestServer,
+) -> Result<(), Error> {
+    save_dir1_zip(&server)?;
+    let resp = reqwest::blocking::get(format!("{}dir1.zip/!/", server.url()))?;
+    let paths = retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("test.txt"));
+    assert!(!paths.contains("index.html"));
+    Ok(())
+}
+
+#[rstest]
+fn browse_archive_auth(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "@/dir1", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let zip = fetch!(b"GET", format!("{}dir1/?zip", server.url()))
+        .send()?
+        .bytes()?;
+    std::fs::write(server.path().join("dir1/copy.zip"), &zip)?;
+    std::fs::write(server.path().join("copy.zip"), &zip)?;
+    let resp = reqwest::blocking::get(format!("{}dir1/copy.zip/!/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}copy.zip/!/test.txt", server.url()))?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
@@ -0,0 +1,143 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::fixture::TempDir;
+use fixtures::{port, tmpdir, wait_for_port, Error};
+use rstest::rstest;
+use serde_json::Value;
+use std::process::{Child, Command, Stdio};
+
+fn spawn_with_audit_log(tmpdir: &TempDir, port: u16, args: &[&str]) -> Result<Child, Error> {
+    let child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path().join("dir1"))
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-A")
+        .arg("--audit-log")
+        .arg(tmpdir.path().join("audit.log"))
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+    Ok(child)
+}
+
+fn read_records(tmpdir: &TempDir, name: &str) -> Result<Vec<Value>, Error> {
+    let content = std::fs::read_to_string(tmpdir.path().join(name))?;
+    let records = content
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()?;
+    Ok(records)
+}
+
+#[rstest]
+fn audit_log(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_audit_log(&tmpdir, port, &["--auth", "user:pass@/:rw"])?;
+    let url = format!("http://localhost:{port}");
+
+    let resp = fetch!(b"PUT", format!("{url}/new.txt"))
+        .basic_auth("user", Some("pass"))
+        .body("Hello")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"MOVE", format!("{url}/new.txt"))
+        .basic_auth("user", Some("pass"))
+        .header("Destination", format!("{url}/moved.txt"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"DELETE", format!("{url}/moved.txt"))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"DELETE", format!("{url}/index.html"))
+        .basic_auth("user", Some("wrong"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", format!("{url}/index.html"))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    // Downloading a selection is no upload
+    let resp = fetch!(b"POST", format!("{url}/?zip"))
+        .basic_auth("user", Some("pass"))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("path=test.txt")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+
+    let records = read_records(&tmpdir, "audit.log")?;
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0]["action"], "upload");
+    assert_eq!(records[0]["user"], "user");
+    assert_eq!(records[0]["ip"], "127.0.0.1");
+    assert_eq!(records[0]["path"], "/new.txt");
+    assert_eq!(records[0]["bytes"], 5);
+    assert_eq!(records[0]["status"], 201);
+    assert_eq!(records[0]["result"], "success");
+    assert_eq!(records[1]["action"], "move");
+    assert_eq!(records[1]["path"], "/new.txt");
+    assert_eq!(records[1]["dest"], "/moved.txt");
+    assert_eq!(records[2]["action"], "delete");
+    assert_eq!(records[2]["path"], "/moved.txt");
+    assert_eq!(records[3]["action"], "auth_failure");
+    assert!(records[3]["user"].is_null());
+    assert_eq!(records[3]["claimed_user"], "user");
+    assert_eq!(records[3]["status"], 401);
+    assert_eq!(records[3]["result"], "failure");
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn audit_log_unverified_user(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_audit_log(&tmpdir, port, &[])?;
+    // Without auth the name in the header proves nothing
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/new.txt"))
+        .header("Authorization", "Basic YWRtaW46")
+        .body("Hello")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let records = read_records(&tmpdir, "audit.log")?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["action"], "upload");
+    assert!(records[0]["user"].is_null());
+    assert!(records[0]["claimed_user"].is_null());
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn audit_log_rotate(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_audit_log(
+        &tmpdir,
+        port,
+        &[
+            "--auth",
+            "user:pass@/:rw",
+            "--audit-log-max-size",
+            "300",
+            "--audit-log-max-files",
+            "1",
+        ],
+    )?;
+    for i in 0..6 {
+        let resp = fetch!(b"MKCOL", format!("http://localhost:{port}/folder{i}"))
+            .basic_auth("user", Some("pass"))
+            .send()?;
+        assert_eq!(resp.status(), 201);
+    }
+
+    let current = read_records(&tmpdir, "audit.log")?;
+    let rotated = read_records(&tmpdir, "audit.log.1")?;
+    assert!(!tmpdir.path().join("audit.log.2").exists());
+    assert_eq!(current.last().unwrap()["path"], "/folder5");
+    assert!(rotated.iter().all(|v| v["action"] == "mkcol"));
+    assert!(std::fs::metadata(tmpdir.path().join("audit.log"))?.len() <= 300);
+
+    child.kill()?;
+    Ok(())
+}
@@ -2,7 +2,7 @@ mod fixtures;
 mod utils;
 
 use chrono::{DateTime, Duration};
-use fixtures::{server, Error, TestServer};
+use fixtures::{server, tmpdir, Error, TestServer};
 use reqwest::header::{
     HeaderName, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE,
     LAST_MODIFIED,
@@ -78,3 +78,94 @@ fn get_file_with_etag_match(
     assert_eq!(resp.status(), expected_code);
     Ok(())
 }
+
+#[rstest]
+fn content_etag(#[with(&["--etag", "content"])] server: TestServer) -> Result<(), Error> {
+    // The sha256 of `index.html`
+    let sha256 = "c8dd395e3202674b9512f7b7f956e0d96a8ba8f572e785b0d5413ab83766dbc4";
+    let resp = fetch!(b"GET", format!("{}index.html", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let etag = resp.headers().get(ETAG).unwrap().clone();
+    assert_eq!(etag, format!(r#""{sha256}""#).as_str());
+
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header(IF_NONE_MATCH, &etag)
+        .send()?;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // Same content elsewhere, same ETag
+    std::fs::copy(
+        server.path().join("index.html"),
+        server.path().join("copy.html"),
+    )?;
+    let resp = fetch!(b"HEAD", format!("{}copy.html", server.url())).send()?;
+    assert_eq!(resp.headers().get(ETAG).unwrap(), &etag);
+
+    // Same size, new content
+    std::fs::write(server.path().join("index.html"), "This is ndex.html!")?;
+    let resp = fetch!(b"HEAD", format!("{}index.html", server.url())).send()?;
+    assert_ne!(resp.headers().get(ETAG).unwrap(), &etag);
+    Ok(())
+}
+
+#[rstest]
+fn content_etag_shares_hash(
+    #[with(&["--etag", "content", "--allow-hash"])] server: TestServer,
+) -> Result<(), Error> {
+    let path = server.path().join("index.html");
+    let mtime = std::fs::metadata(&path)?.modified()?;
+    let resp = fetch!(b"HEAD", format!("{}index.html", server.url())).send()?;
+    let etag = resp.headers().get(ETAG).unwrap().to_str()?.to_string();
+
+    // Rewritten in place with the same size and modified time, the recorded hash is kept
+    std::fs::write(&path, "This is ndex.html!")?;
+    std::fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_modified(mtime)?;
+    let resp = reqwest::blocking::get(format!("{}index.html?hash", server.url()))?;
+    assert_eq!(format!(r#""{}""#, resp.text()?), etag);
+    Ok(())
+}
+
+#[rstest]
+fn content_etag_cache_dir() -> Result<(), Error> {
+    let cache = assert_fs::TempDir::new()?;
+    let cache_dir = cache.path().join("dufs");
+    let server = server(&[
+        "--etag",
+        "content",
+        "--cache-dir",
+        cache_dir.to_str().unwrap(),
+    ]);
+    let resp = fetch!(b"HEAD", format!("{}index.html", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let records = std::fs::read_dir(cache_dir.join("hashes"))?
+        .flat_map(|v| std::fs::read_dir(v.unwrap().path()).unwrap())
+        .count();
+    assert_eq!(records, 1);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&cache_dir)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn cache_dir_symlink_refused(tmpdir: assert_fs::TempDir) -> Result<(), Error> {
+    let cache_dir = tmpdir.path().join("cache");
+    std::os::unix::fs::symlink(tmpdir.path().join("dir1"), &cache_dir)?;
+    assert_cmd::cargo::cargo_bin_cmd!()
+        .arg(tmpdir.path())
+        .arg("--cache-dir")
+        .arg(&cache_dir)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "must be a directory owned by the current user",
+        ));
+    Ok(())
+}
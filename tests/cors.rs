@@ -1,33 +1,197 @@
 mod fixtures;
 mod utils;
 
-use fixtures::{server, Error, TestServer};
+use assert_fs::{fixture::TempDir, prelude::*};
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer};
 use rstest::rstest;
+use std::process::{Child, Command, Stdio};
 
 #[rstest]
 fn cors(#[with(&["--enable-cors"])] server: TestServer) -> Result<(), Error> {
-    let resp = reqwest::blocking::get(server.url())?;
+    let resp = fetch!(b"GET", server.url())
+        .header("Origin", "https://example.com")
+        .send()?;
     assert_eq!(
         resp.headers().get("access-control-allow-origin").unwrap(),
         "*"
     );
+    assert!(resp
+        .headers()
+        .get("access-control-allow-credentials")
+        .is_none());
+    assert_eq!(resp.headers().get("vary").unwrap(), "Origin");
+    let expose_headers = resp
+        .headers()
+        .get("access-control-expose-headers")
+        .unwrap()
+        .to_str()?;
+    assert!(expose_headers.contains("Authorization"));
+    assert!(expose_headers.contains("Content-Range"));
+    Ok(())
+}
+
+#[rstest]
+fn cors_preflight(
+    #[with(&["--enable-cors", "--auth", "user:pass@/:rw"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"OPTIONS", format!("{}dir1/file1", server.url()))
+        .header("Origin", "https://example.com")
+        .header("Access-Control-Request-Method", "PUT")
+        .header("Access-Control-Request-Headers", "authorization,x-custom")
+        .send()?;
+    assert_eq!(resp.status(), 204);
     assert_eq!(
-        resp.headers()
-            .get("access-control-allow-credentials")
-            .unwrap(),
-        "true"
-    );
-    assert_eq!(
-        resp.headers().get("access-control-allow-methods").unwrap(),
+        resp.headers().get("access-control-allow-origin").unwrap(),
         "*"
     );
+    let allow_methods = resp
+        .headers()
+        .get("access-control-allow-methods")
+        .unwrap()
+        .to_str()?;
+    assert!(allow_methods.split(',').any(|v| v == "PUT"));
     assert_eq!(
         resp.headers().get("access-control-allow-headers").unwrap(),
-        "Authorization,*"
+        "authorization,x-custom"
+    );
+    assert!(resp.headers().get("access-control-max-age").is_none());
+    Ok(())
+}
+
+#[rstest]
+fn cors_config(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_cors(
+        &tmpdir,
+        port,
+        r#"
+cors:
+  allow-origins:
+    - https://example.com
+    - https://*.example.org
+  allow-methods: [GET, PUT]
+  allow-headers: Authorization
+  expose-headers: ETag
+  allow-credentials: false
+  max-age: 600
+"#,
+    )?;
+    let url = format!("http://localhost:{port}/index.html");
+
+    let resp = fetch!(b"OPTIONS", &url)
+        .header("Origin", "https://www.example.org")
+        .header("Access-Control-Request-Method", "PUT")
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let headers = resp.headers();
+    assert_eq!(
+        headers.get("access-control-allow-origin").unwrap(),
+        "https://www.example.org"
+    );
+    assert!(headers.get("access-control-allow-credentials").is_none());
+    assert_eq!(
+        headers.get("access-control-allow-methods").unwrap(),
+        "GET,PUT"
+    );
+    assert_eq!(
+        headers.get("access-control-allow-headers").unwrap(),
+        "Authorization"
+    );
+    assert_eq!(headers.get("access-control-max-age").unwrap(), "600");
+
+    let resp = fetch!(b"GET", &url)
+        .header("Origin", "https://example.com")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("access-control-allow-origin").unwrap(),
+        "https://example.com"
     );
     assert_eq!(
         resp.headers().get("access-control-expose-headers").unwrap(),
-        "Authorization,*"
+        "ETag"
+    );
+
+    let resp = fetch!(b"GET", &url)
+        .header("Origin", "https://evil.com")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn cors_credentials(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_cors(
+        &tmpdir,
+        port,
+        r#"
+cors:
+  allow-origins: ["*", https://example.com]
+  allow-credentials: true
+"#,
+    )?;
+    let url = format!("http://localhost:{port}/index.html");
+
+    let resp = fetch!(b"GET", &url)
+        .header("Origin", "https://example.com")
+        .send()?;
+    let headers = resp.headers();
+    assert_eq!(
+        headers.get("access-control-allow-origin").unwrap(),
+        "https://example.com"
+    );
+    assert_eq!(
+        headers.get("access-control-allow-credentials").unwrap(),
+        "true"
     );
+
+    // Origins only matched by the wildcard never get credentials
+    let resp = fetch!(b"GET", &url)
+        .header("Origin", "https://evil.com")
+        .send()?;
+    let headers = resp.headers();
+    assert_eq!(headers.get("access-control-allow-origin").unwrap(), "*");
+    assert!(headers.get("access-control-allow-credentials").is_none());
+
+    child.kill()?;
     Ok(())
 }
+
+#[rstest]
+fn cors_rejected_preflight(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_with_cors(
+        &tmpdir,
+        port,
+        r#"
+cors:
+  allow-origins: https://example.com
+"#,
+    )?;
+    let resp = fetch!(b"OPTIONS", format!("http://localhost:{port}/index.html"))
+        .header("Origin", "https://evil.com")
+        .header("Access-Control-Request-Method", "PUT")
+        .send()?;
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+    let vary: Vec<_> = resp.headers().get_all("vary").iter().collect();
+    assert_eq!(vary, ["Origin"]);
+
+    child.kill()?;
+    Ok(())
+}
+
+fn spawn_with_cors(tmpdir: &TempDir, port: u16, config: &str) -> Result<Child, Error> {
+    let config_file = tmpdir.child("cors.yaml");
+    config_file.write_str(config)?;
+    let child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--config")
+        .arg(config_file.path())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+    Ok(child)
+}
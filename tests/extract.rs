@@ -0,0 +1,188 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::io::Write;
+
+/// Build a ustar archive, names are written as is so that bad paths can be tested.
+fn make_tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut output = vec![];
+    for (name, data) in entries {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000644");
+        header[108..115].copy_from_slice(b"0000000");
+        header[116..123].copy_from_slice(b"0000000");
+        header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+        header[136..147].copy_from_slice(b"00000000000");
+        header[156] = if name.ends_with('/') { b'5' } else { b'0' };
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|v| *v as u32).sum();
+        header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+        output.extend_from_slice(&header);
+        output.extend_from_slice(data);
+        output.resize(output.len().div_ceil(512) * 512, 0);
+    }
+    output.resize(output.len() + 1024, 0);
+    output
+}
+
+fn extract(server: &TestServer, name: &str) -> Result<reqwest::blocking::Response, Error> {
+    Ok(fetch!(b"POST", format!("{}{name}?extract", server.url())).send()?)
+}
+
+#[rstest]
+fn extract_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let zip = fetch!(b"GET", format!("{}dir1/?zip", server.url()))
+        .send()?
+        .bytes()?;
+    std::fs::write(server.path().join("archive.zip"), zip)?;
+    let resp = extract(&server, "archive.zip")?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("location").unwrap(), "/archive/");
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("archive/test.txt"))?,
+        "This is dir1/test.txt"
+    );
+    assert!(server.path().join("archive.zip").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_tar(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let tar = make_tar(&[
+        ("docs/", b""),
+        ("docs/a.txt", b"Hello"),
+        ("./b.txt", b"World"),
+    ]);
+    std::fs::write(server.path().join("plain.tar"), &tar)?;
+
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&tar)?;
+    std::fs::write(server.path().join("gzip.tar.gz"), encoder.finish()?)?;
+    std::fs::write(
+        server.path().join("zstd.tzst"),
+        zstd::encode_all(tar.as_slice(), 0)?,
+    )?;
+
+    for (name, dir) in [
+        ("plain.tar", "plain"),
+        ("gzip.tar.gz", "gzip"),
+        ("zstd.tzst", "zstd"),
+    ] {
+        let resp = extract(&server, name)?;
+        assert_eq!(resp.status(), 201);
+        let dir = server.path().join(dir);
+        assert_eq!(std::fs::read_to_string(dir.join("docs/a.txt"))?, "Hello");
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt"))?, "World");
+    }
+    Ok(())
+}
+
+#[rstest]
+fn extract_path_traversal(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let tar = make_tar(&[("a.txt", b"Hello"), ("../evil.txt", b"Evil")]);
+    std::fs::write(server.path().join("dir1/evil.tar"), tar)?;
+    let resp = extract(&server, "dir1/evil.tar")?;
+    assert_eq!(resp.status(), 400);
+    assert!(!server.path().join("evil.txt").exists());
+    assert!(!server.path().join("dir1/evil").exists());
+    let leftovers = std::fs::read_dir(server.path().join("dir1"))?
+        .flatten()
+        .any(|v| v.file_name().to_string_lossy().starts_with(".dufs-upload-"));
+    assert!(!leftovers);
+    Ok(())
+}
+
+#[rstest]
+fn extract_max_entries(
+    #[with(&["-A", "--extract-max-entries", "2"])] server: TestServer,
+) -> Result<(), Error> {
+    let tar = make_tar(&[("a", b"a"), ("b", b"b"), ("c", b"c")]);
+    std::fs::write(server.path().join("many.tar"), tar)?;
+    let resp = extract(&server, "many.tar")?;
+    assert_eq!(resp.status(), 413);
+    assert!(!server.path().join("many").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_max_size(
+    #[with(&["-A", "--extract-max-size", "1K"])] server: TestServer,
+) -> Result<(), Error> {
+    let data = vec![0u8; 100 * 1024];
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::best());
+    encoder.write_all(&make_tar(&[("bomb", &data)]))?;
+    std::fs::write(server.path().join("bomb.tgz"), encoder.finish()?)?;
+    let resp = extract(&server, "bomb.tgz")?;
+    assert_eq!(resp.status(), 413);
+    assert!(!server.path().join("bomb").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_conflict(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(
+        server.path().join("dir1.tar"),
+        make_tar(&[("new.txt", b"new")]),
+    )?;
+    let resp = fetch!(
+        b"POST",
+        format!("{}dir1.tar?extract&conflict=fail", server.url())
+    )
+    .send()?;
+    assert_eq!(resp.status(), 409);
+
+    let resp = fetch!(b"POST", format!("{}dir1.tar?extract", server.url()))
+        .header("X-Upload-Conflict", "rename")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        resp.headers().get("x-upload-name").unwrap(),
+        "dir1%20%282%29"
+    );
+    assert!(server.path().join("dir1 (2)/new.txt").exists());
+    assert!(server.path().join("dir1/test.txt").exists());
+
+    let resp = extract(&server, "dir1.tar")?;
+    assert_eq!(resp.status(), 201);
+    assert!(server.path().join("dir1/new.txt").exists());
+    assert!(!server.path().join("dir1/test.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_not_allowed(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("a.tar"), make_tar(&[("a.txt", b"a")]))?;
+    let resp = extract(&server, "a.tar")?;
+    assert_eq!(resp.status(), 403);
+    assert!(!server.path().join("a").exists());
+
+    let resp = extract(&server, "index.html")?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn extract_cors_any_origin(
+    #[with(&["-A", "--enable-cors"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("a.tar"), make_tar(&[("a.txt", b"a")]))?;
+    // `*` lets other sites read, not extract on behalf of the user
+    let resp = fetch!(b"POST", format!("{}a.tar?extract", server.url()))
+        .header("Origin", "https://evil.com")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!server.path().join("a").exists());
+    Ok(())
+}
+
+#[rstest]
+fn extract_unsupported(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = extract(&server, "index.html")?;
+    assert_eq!(resp.status(), 415);
+    Ok(())
+}
@@ -1,9 +1,18 @@
 mod fixtures;
 mod utils;
 
-use fixtures::{server, Error, TestServer, BIN_FILE};
+use assert_fs::{fixture::TempDir, prelude::*};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer, BIN_FILE};
+use reqwest::blocking::multipart::{Form, Part};
 use rstest::rstest;
 use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
 use utils::retrieve_edit_file;
 
 #[rstest]
@@ -92,6 +101,7 @@ fn get_dir_noscript(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     );
     let text = resp.text().unwrap();
     assert!(text.contains(r#"<td><a href="index.html">index.html</a></td>"#));
+    assert!(text.contains(r#"<form method="post" enctype="multipart/form-data">"#));
     Ok(())
 }
 
@@ -327,6 +337,41 @@ fn put_file_create_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error>
     Ok(())
 }
 
+#[rstest]
+fn put_file_mtime(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .header("X-OC-Mtime", "1700000000")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("x-oc-mtime").unwrap(), "accepted");
+    let mtime = std::fs::metadata(server.path().join("file1"))?.modified()?;
+    assert_eq!(
+        mtime.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+        1700000000
+    );
+
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .header("X-Mtime", "1600000000.5")
+        .body(b"def".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let mtime = std::fs::metadata(server.path().join("file1"))?.modified()?;
+    assert_eq!(
+        mtime.duration_since(std::time::UNIX_EPOCH)?.as_millis(),
+        1600000000500
+    );
+
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Mtime", "yesterday")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
 #[rstest]
 fn put_file_conflict_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}dir1", server.url());
@@ -397,3 +442,226 @@ fn resumable_upload(#[with(&["--allow-upload"])] server: TestServer) -> Result<(
     assert_eq!(resp.text().unwrap(), "abc123");
     Ok(())
 }
+
+#[rstest]
+fn put_file_atomic(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/index.html", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "abc");
+    assert!(!has_upload_temp_files(&server.path().join("dir1"))?);
+    Ok(())
+}
+
+#[rstest]
+fn put_file_interrupted(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(("localhost", server.port()))?;
+    stream.write_all(
+        b"PUT /dir1/index.html HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\nabc",
+    )?;
+    stream.shutdown(Shutdown::Both)?;
+    sleep(Duration::from_millis(500));
+
+    let resp = reqwest::blocking::get(format!("{}dir1/index.html", server.url()))?;
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+    assert!(!has_upload_temp_files(&server.path().join("dir1"))?);
+    Ok(())
+}
+
+#[rstest]
+fn sweep_upload_temp_files(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let temp_file = tmpdir.child("dir1/.dufs-upload-0123456789abcdef");
+    temp_file.write_str("partial")?;
+    // Looks like an upload that began after the server started
+    let inflight_file = tmpdir.child("dir1/.dufs-upload-fedcba9876543210");
+    inflight_file.write_str("partial")?;
+    std::fs::File::options()
+        .write(true)
+        .open(inflight_file.path())?
+        .set_modified(std::time::SystemTime::now() + Duration::from_secs(3600))?;
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--allow-upload")
+        .stdout(Stdio::null())
+        .spawn()?;
+    wait_for_port(port);
+    sleep(Duration::from_millis(500));
+
+    assert!(!temp_file.exists());
+    assert!(inflight_file.exists());
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir1/?json"))?;
+    assert!(!resp.text()?.contains(".dufs-upload-"));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn put_file_with_digest(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/index.html", server.url());
+    let digest = format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(b"abc")));
+    let resp = fetch!(b"PUT", &url)
+        .header("Content-Digest", &digest)
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("content-digest").unwrap(), &digest);
+
+    let resp = fetch!(b"PUT", &url)
+        .header("Content-MD5", STANDARD.encode(md5::compute(b"xyz").0))
+        .body(b"abc!".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "abc");
+    assert!(!has_upload_temp_files(&server.path().join("dir1"))?);
+
+    let resp = fetch!(b"PUT", &url)
+        .header("Want-Content-Digest", "sha-512=1")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        resp.headers().get("content-digest").unwrap().to_str()?,
+        format!("sha-512=:{}:", STANDARD.encode(Sha512::digest(b"abc")))
+    );
+    Ok(())
+}
+
+#[rstest]
+fn patch_file_with_digest(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}dir1/index.html", server.url());
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .header("Content-Digest", "sha-256=:AAAA:")
+        .body(b"123".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "bytes=0-")
+        .header(
+            "Content-Digest",
+            format!("sha-256=:{}:", STANDARD.encode(Sha256::digest(b"That"))),
+        )
+        .body(b"That".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "That is dir1/index.html");
+    assert!(!has_upload_temp_files(&server.path().join("dir1"))?);
+    Ok(())
+}
+
+#[rstest]
+fn patch_file_with_fsync(
+    #[with(&["-A", "--upload-fsync"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/index.html", server.url());
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"123".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.text()?, "This is dir1/index.html123");
+    Ok(())
+}
+
+#[rstest]
+fn post_form_upload(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let form = Form::new()
+        .text("note", "ignored")
+        .part("file", Part::bytes(b"abc".to_vec()).file_name("file1.txt"))
+        .part(
+            "file",
+            Part::bytes(vec![0; 100_000]).file_name("sub/dir/file2.bin"),
+        )
+        .part("file", Part::bytes(vec![]).file_name(""));
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = reqwest::blocking::get(format!("{}dir1/file1.txt", server.url()))?;
+    assert_eq!(resp.text()?, "abc");
+    let resp = reqwest::blocking::get(format!("{}dir1/sub/dir/file2.bin", server.url()))?;
+    assert_eq!(resp.bytes()?.len(), 100_000);
+
+    let form = Form::new().part(
+        "file",
+        Part::bytes(b"abc".to_vec()).file_name("../escape.txt"),
+    );
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    assert!(!server.path().join("escape.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn post_form_upload_noscript(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let form = Form::new().part("file", Part::bytes(b"abc".to_vec()).file_name("file1.txt"));
+    let resp = reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?
+        .post(format!("{}dir1/?noscript", server.url()))
+        .header("Accept", "text/html")
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 303);
+    assert_eq!(resp.headers().get("location").unwrap(), "/dir1/?noscript");
+
+    let form = Form::new().part("file", Part::bytes(b"abc".to_vec()).file_name("file2.txt"));
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .header("Origin", "https://evil.com")
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn post_form_upload_cors_any_origin(
+    #[with(&["-A", "--enable-cors"])] server: TestServer,
+) -> Result<(), Error> {
+    // `*` lets other sites read, not upload with the user's credentials
+    let form = Form::new().part("file", Part::bytes(b"abc".to_vec()).file_name("file1.txt"));
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .header("Origin", "https://evil.com")
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(!server.path().join("dir1/file1.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn post_form_upload_not_allowed(server: TestServer) -> Result<(), Error> {
+    let form = Form::new().part("file", Part::bytes(b"abc".to_vec()).file_name("file1.txt"));
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+fn has_upload_temp_files(dir: &std::path::Path) -> Result<bool, Error> {
+    for entry in std::fs::read_dir(dir)? {
+        if entry?
+            .file_name()
+            .to_string_lossy()
+            .starts_with(".dufs-upload-")
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
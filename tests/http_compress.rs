@@ -0,0 +1,173 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer, BIN_FILE};
+use rstest::rstest;
+use std::io::Read;
+
+fn large_text() -> String {
+    (0..1000).map(|v| format!("line {v}\n")).collect()
+}
+
+#[rstest]
+#[case("gzip")]
+#[case("zstd")]
+#[case("br")]
+#[case("br;q=0.5, zstd;q=0.8, gzip;q=0.5")]
+fn compress_text(server: TestServer, #[case] accept_encoding: &str) -> Result<(), Error> {
+    std::fs::write(server.path().join("large.txt"), large_text())?;
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url()))
+        .header("accept-encoding", accept_encoding)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("vary").unwrap(), "accept-encoding");
+    assert!(resp.headers().get("content-length").is_none());
+    assert!(resp
+        .headers()
+        .get("etag")
+        .unwrap()
+        .to_str()?
+        .starts_with("W/"));
+    let encoding = resp.headers().get("content-encoding").unwrap().to_str()?;
+    let body = match encoding {
+        "gzip" => {
+            let mut body = String::new();
+            flate2::read::GzDecoder::new(&resp.bytes()?[..]).read_to_string(&mut body)?;
+            body
+        }
+        "zstd" => String::from_utf8(zstd::decode_all(&resp.bytes()?[..])?)?,
+        "br" => {
+            let mut body = String::new();
+            brotli::Decompressor::new(&resp.bytes()?[..], 4096).read_to_string(&mut body)?;
+            body
+        }
+        _ => panic!("unexpected encoding {encoding}"),
+    };
+    assert_eq!(body, large_text());
+    Ok(())
+}
+
+#[rstest]
+fn compress_not_modified(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("large.txt"), large_text())?;
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .header("if-none-match", etag)
+        .send()?;
+    assert_eq!(resp.status(), 304);
+    Ok(())
+}
+
+#[rstest]
+fn compress_skipped(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("large.txt"), large_text())?;
+    std::fs::write(server.path().join("large.bin"), large_text())?;
+    // Not accepted, too small, not text or a range
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url())).send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.headers().get("vary").unwrap(), "accept-encoding");
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    for name in ["large.bin", BIN_FILE] {
+        let resp = fetch!(b"GET", format!("{}{name}", server.url()))
+            .header("accept-encoding", "gzip")
+            .send()?;
+        assert!(resp.headers().get("content-encoding").is_none());
+    }
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .header("range", "bytes=0-9")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text()?, "line 0\nlin");
+    Ok(())
+}
+
+#[rstest]
+fn compress_disabled(
+    #[with(&["--http-compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("large.txt"), large_text())?;
+    let resp = fetch!(b"GET", format!("{}large.txt", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text()?, large_text());
+    Ok(())
+}
+
+#[rstest]
+#[case("br, gzip", Some("br"), "app.js.br")]
+#[case("gzip, deflate", Some("gzip"), "app.js.gz")]
+#[case("zstd", None, "app.js")]
+#[case("", None, "app.js")]
+fn precompressed(
+    #[with(&["--render-index", "--http-compress", "none"])] server: TestServer,
+    #[case] accept_encoding: &str,
+    #[case] encoding: Option<&str>,
+    #[case] file: &str,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("app.js"), "console.log(1)")?;
+    std::fs::write(server.path().join("app.js.br"), "br content")?;
+    std::fs::write(server.path().join("app.js.gz"), "gzip content")?;
+    let resp = fetch!(b"GET", format!("{}app.js", server.url()))
+        .header("accept-encoding", accept_encoding)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("vary").unwrap(), "accept-encoding");
+    assert_eq!(
+        resp.headers()
+            .get("content-encoding")
+            .map(|v| v.to_str().unwrap()),
+        encoding
+    );
+    assert!(resp
+        .headers()
+        .get("content-type")
+        .unwrap()
+        .to_str()?
+        .contains("javascript"));
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        r#"inline; filename="app.js""#
+    );
+    let etag = resp.headers().get("etag").unwrap().clone();
+    assert_eq!(
+        resp.bytes()?,
+        std::fs::read(server.path().join(file))?.as_slice()
+    );
+
+    let resp = fetch!(b"GET", format!("{}app.js", server.url()))
+        .header("accept-encoding", accept_encoding)
+        .header("if-none-match", etag)
+        .send()?;
+    assert_eq!(resp.status(), 304);
+    Ok(())
+}
+
+#[rstest]
+fn precompressed_stale(
+    #[with(&["--render-index", "--http-compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("app.js.gz"), "gzip content")?;
+    std::fs::write(server.path().join("app.js"), "console.log(2)")?;
+    // The copy predates the current version of the file
+    std::fs::File::options()
+        .write(true)
+        .open(server.path().join("app.js.gz"))?
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(60))?;
+    let resp = fetch!(b"GET", format!("{}app.js", server.url()))
+        .header("accept-encoding", "gzip")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("content-encoding").is_none());
+    assert_eq!(resp.text()?, "console.log(2)");
+    Ok(())
+}
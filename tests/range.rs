@@ -62,10 +62,16 @@ fn parse_multipart_body<'a>(body: &'a str, boundary: &str) -> Vec<(HeaderMap, &'
 #[rstest]
 fn get_file_multipart_range(server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"GET", format!("{}index.html", server.url()))
-        .header("range", HeaderValue::from_static("bytes=0-11, 6-17"))
+        .header("range", HeaderValue::from_static("bytes=8-17, 0-6"))
         .send()?;
     assert_eq!(resp.status(), 206);
     assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+    let content_length: usize = resp
+        .headers()
+        .get("content-length")
+        .unwrap()
+        .to_str()?
+        .parse()?;
 
     let content_type = resp
         .headers()
@@ -79,16 +85,17 @@ fn get_file_multipart_range(server: TestServer) -> Result<(), Error> {
     assert!(!boundary.is_empty());
 
     let body = resp.text()?;
+    assert_eq!(body.len(), content_length);
     let parts = parse_multipart_body(&body, boundary);
     assert_eq!(parts.len(), 2);
 
     let (headers, body) = &parts[0];
-    assert_eq!(headers.get("content-range").unwrap(), "bytes 0-11/18");
-    assert_eq!(*body, "This is inde");
+    assert_eq!(headers.get("content-range").unwrap(), "bytes 0-6/18");
+    assert_eq!(*body, "This is");
 
     let (headers, body) = &parts[1];
-    assert_eq!(headers.get("content-range").unwrap(), "bytes 6-17/18");
-    assert_eq!(*body, "s index.html");
+    assert_eq!(headers.get("content-range").unwrap(), "bytes 8-17/18");
+    assert_eq!(*body, "index.html");
 
     Ok(())
 }
@@ -126,3 +133,66 @@ fn get_file_multipart_range_reversed(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
     Ok(())
 }
+
+#[rstest]
+fn get_file_multipart_range_coalesce(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"GET", format!("{}index.html", server.url()))
+        .header(
+            "range",
+            HeaderValue::from_static("bytes=0-11, 6-17, 0-, 12-"),
+        )
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        "bytes 0-17/18"
+    );
+    assert_eq!(resp.text()?, "This is index.html");
+    Ok(())
+}
+
+#[rstest]
+fn get_file_multipart_range_too_many(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("large.bin"), vec![b'a'; 1000])?;
+    let ranges = (0..150)
+        .map(|v| format!("{}-{}", v * 2, v * 2))
+        .collect::<Vec<_>>()
+        .join(",");
+    let resp = fetch!(b"GET", format!("{}large.bin", server.url()))
+        .header("range", format!("bytes={ranges}"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?.len(), 1000);
+
+    let ranges = vec!["0-"; 1000].join(",");
+    let resp = fetch!(b"GET", format!("{}large.bin", server.url()))
+        .header("range", format!("bytes={ranges}"))
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.bytes()?.len(), 1000);
+    Ok(())
+}
+
+#[rstest]
+fn get_large_file(server: TestServer) -> Result<(), Error> {
+    // Spans several frames of a download sent without copying
+    let data: Vec<u8> = (0..3 * 1024 * 1024 + 7).map(|v| (v % 251) as u8).collect();
+    std::fs::write(server.path().join("large.bin"), &data)?;
+    let url = format!("{}large.bin", server.url());
+    // Requests share the connection, each gets only its own bytes
+    let client = reqwest::blocking::Client::new();
+    for range in [None, Some((1000, 2 * 1024 * 1024 + 500)), None] {
+        let mut req = client.get(&url);
+        if let Some((start, end)) = range {
+            req = req.header("range", format!("bytes={start}-{end}"));
+        }
+        let resp = req.send()?;
+        let expected = match range {
+            Some((start, end)) => &data[start..=end],
+            None => &data[..],
+        };
+        assert_eq!(resp.status(), if range.is_some() { 206 } else { 200 });
+        assert_eq!(&resp.bytes()?[..], expected);
+    }
+    Ok(())
+}
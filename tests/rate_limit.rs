@@ -0,0 +1,93 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use std::time::{Duration, Instant};
+
+const DATA_SIZE: usize = 200 * 1024;
+
+fn data() -> Vec<u8> {
+    (0..DATA_SIZE).map(|v| (v % 251) as u8).collect()
+}
+
+/// Download `data.bin`, at 200K per second it takes about a second less the burst
+fn download_slowly(server: &TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("data.bin"), data())?;
+    let now = Instant::now();
+    let resp = reqwest::blocking::get(format!("{}data.bin", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?, data());
+    assert!(now.elapsed() >= Duration::from_millis(800));
+    Ok(())
+}
+
+#[rstest]
+fn download_rate_limit(#[with(&["--rate-limit", "200K"])] server: TestServer) -> Result<(), Error> {
+    download_slowly(&server)
+}
+
+#[rstest]
+fn download_rate_limit_user(
+    #[with(&["--rate-limit-user", "200K"])] server: TestServer,
+) -> Result<(), Error> {
+    download_slowly(&server)
+}
+
+#[rstest]
+fn download_rate_limit_conn(
+    #[with(&["--rate-limit-conn", "200K"])] server: TestServer,
+) -> Result<(), Error> {
+    download_slowly(&server)
+}
+
+#[rstest]
+fn upload_rate_limit(
+    #[with(&["-A", "--rate-limit-conn", "200K"])] server: TestServer,
+) -> Result<(), Error> {
+    let now = Instant::now();
+    let resp = fetch!(b"PUT", format!("{}data.bin", server.url()))
+        .body(data())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(now.elapsed() >= Duration::from_millis(800));
+    assert_eq!(std::fs::read(server.path().join("data.bin"))?, data());
+    Ok(())
+}
+
+#[rstest]
+fn rate_limit_shared_by_user(
+    #[with(&["--auth", "user:pass@/:rw", "--rate-limit-user", "400K"])] server: TestServer,
+) -> Result<(), Error> {
+    std::fs::write(server.path().join("data.bin"), data())?;
+    let now = Instant::now();
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let url = format!("{}data.bin", server.url());
+            std::thread::spawn(move || {
+                reqwest::blocking::Client::new()
+                    .get(url)
+                    .basic_auth("user", Some("pass"))
+                    .send()
+                    .and_then(|v| v.bytes())
+                    .map(|v| v.len())
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap()?, DATA_SIZE);
+    }
+    // Two downloads of the same user split its rate
+    assert!(now.elapsed() >= Duration::from_millis(800));
+    Ok(())
+}
+
+#[rstest]
+fn rate_limit_unset(server: TestServer) -> Result<(), Error> {
+    std::fs::write(server.path().join("data.bin"), data())?;
+    let now = Instant::now();
+    let resp = reqwest::blocking::get(format!("{}data.bin", server.url()))?;
+    assert_eq!(resp.bytes()?, data());
+    assert!(now.elapsed() < Duration::from_millis(800));
+    Ok(())
+}
@@ -0,0 +1,108 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use regex::Regex;
+use rstest::rstest;
+
+#[rstest]
+fn security_headers(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    let headers = resp.headers().clone();
+    assert_eq!(headers.get("x-content-type-options").unwrap(), "nosniff");
+    assert_eq!(headers.get("x-frame-options").unwrap(), "SAMEORIGIN");
+    assert_eq!(headers.get("referrer-policy").unwrap(), "same-origin");
+    assert!(headers.get("strict-transport-security").is_none());
+
+    let csp = headers
+        .get("content-security-policy")
+        .unwrap()
+        .to_str()?
+        .to_string();
+    let nonce = Regex::new(r"'nonce-([0-9a-f]+)'")?
+        .captures(&csp)
+        .unwrap()
+        .get(1)
+        .unwrap()
+        .as_str()
+        .to_string();
+    let text = resp.text()?;
+    assert!(text.contains(&format!(r#"<script nonce="{nonce}""#)));
+
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert!(resp.headers().get("content-security-policy").is_none());
+    assert_eq!(resp.headers().get("x-frame-options").unwrap(), "SAMEORIGIN");
+    Ok(())
+}
+
+#[rstest]
+fn security_headers_custom(
+    #[with(&[
+        "--csp", "default-src 'none'",
+        "--frame-options", "",
+        "--referrer-policy", "no-referrer",
+        "--hsts", "3600",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(server.url())?;
+    let headers = resp.headers();
+    assert_eq!(
+        headers.get("content-security-policy").unwrap(),
+        "default-src 'none'"
+    );
+    assert!(headers.get("x-frame-options").is_none());
+    assert_eq!(headers.get("referrer-policy").unwrap(), "no-referrer");
+    assert_eq!(
+        headers.get("strict-transport-security").unwrap(),
+        "max-age=3600"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn active_content_inline(server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "inline; filename=\"index.html\""
+    );
+    Ok(())
+}
+
+#[rstest]
+fn active_content_attachment(
+    #[with(&["--active-content", "attachment"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "attachment; filename=\"index.html\""
+    );
+
+    let resp = reqwest::blocking::get(format!("{}test.txt", server.url()))?;
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "inline; filename=\"test.txt\""
+    );
+    Ok(())
+}
+
+#[rstest]
+fn active_content_sandbox(
+    #[with(&["--active-content", "sandbox"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-security-policy").unwrap(),
+        "sandbox"
+    );
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        "inline; filename=\"index.html\""
+    );
+    Ok(())
+}
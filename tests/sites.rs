@@ -0,0 +1,121 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::{fixture::TempDir, prelude::*};
+use fixtures::{port, tmpdir, wait_for_port, Error};
+use predicates::str::contains;
+use reqwest::blocking::ClientBuilder;
+use rstest::rstest;
+use std::net::SocketAddr;
+use std::process::{Child, Command, Stdio};
+
+fn spawn_with_config(tmpdir: &TempDir, port: u16, config: &str) -> Result<Child, Error> {
+    let config_file = tmpdir.child("sites.yaml");
+    config_file.write_str(config)?;
+    let child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--config")
+        .arg(config_file.path())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    wait_for_port(port);
+    Ok(child)
+}
+
+#[rstest]
+fn sites_select_by_host(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config = format!(
+        r#"
+sites:
+  - hosts: a.example
+    serve-path: {}
+    allow-upload: true
+  - hosts:
+      - "*.b.example"
+    serve-path: {}
+    path-prefix: files
+"#,
+        tmpdir.path().join("dir1").display(),
+        tmpdir.path().join("dir2").display(),
+    );
+    let mut child = spawn_with_config(&tmpdir, port, &config)?;
+
+    let url = format!("http://localhost:{port}/");
+    let resp = fetch!(b"GET", &url).header("Host", "a.example").send()?;
+    assert_resp_paths!(resp);
+
+    let url = format!("http://localhost:{port}/upload.txt");
+    let resp = fetch!(b"PUT", &url)
+        .header("Host", format!("A.example:{port}"))
+        .body("Hello")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert!(tmpdir.path().join("dir1/upload.txt").exists());
+
+    let resp = fetch!(b"PUT", &url).body("Hello").send()?;
+    assert_eq!(resp.status(), 403);
+
+    let url = format!("http://localhost:{port}/files/index.html");
+    let resp = fetch!(b"GET", &url)
+        .header("Host", "www.b.example")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir2/index.html");
+
+    let url = format!("http://localhost:{port}/?simple");
+    let resp = fetch!(b"GET", &url).header("Host", "c.example").send()?;
+    let text = resp.text()?;
+    assert!(text.split('\n').any(|v| v == "dir1/"));
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn sites_select_cert_by_sni(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config = format!(
+        r#"
+sites:
+  - hosts: a.example
+    serve-path: {}
+    tls-cert: tests/data/cert.pem
+    tls-key: tests/data/key_pkcs8.pem
+"#,
+        tmpdir.path().join("dir1").display(),
+    );
+    let mut child = spawn_with_config(&tmpdir, port, &config)?;
+
+    let addr: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    let client = ClientBuilder::new()
+        .tls_danger_accept_invalid_certs(true)
+        .resolve("a.example", addr)
+        .build()?;
+    let resp = client
+        .get(format!("https://a.example:{port}/index.html"))
+        .send()?;
+    assert_eq!(resp.text()?, "This is dir1/index.html");
+
+    // No certificate is configured for names other than `a.example`
+    assert!(client
+        .get(format!("https://localhost:{port}/"))
+        .send()
+        .is_err());
+
+    child.kill()?;
+    Ok(())
+}
+
+#[rstest]
+fn sites_require_hosts(tmpdir: TempDir) -> Result<(), Error> {
+    let config_file = tmpdir.child("sites.yaml");
+    config_file.write_str("sites:\n  - hosts: []\n    allow-upload: true\n")?;
+    assert_cmd::cargo::cargo_bin_cmd!()
+        .arg("--config")
+        .arg(config_file.path())
+        .assert()
+        .failure()
+        .stderr(contains("Invalid site, no hosts set"));
+    Ok(())
+}
@@ -1,10 +1,12 @@
 mod fixtures;
 mod utils;
 
-use fixtures::{server, Error, TestServer};
+use assert_fs::TempDir;
+use fixtures::{server, tmpdir, wait_for_port, Error, TestServer};
 use predicates::str::contains;
 use reqwest::blocking::ClientBuilder;
 use rstest::rstest;
+use std::process::{Command, Stdio};
 
 use crate::fixtures::port;
 
@@ -22,6 +24,7 @@ use crate::fixtures::port;
         "--tls-cert", "tests/data/cert_ecdsa.pem",
         "--tls-key", "tests/data/key_ecdsa.pem",
 ]))]
+#[case(server(&["--tls-self-signed"]))]
 fn tls_works(#[case] server: TestServer) -> Result<(), Error> {
     let client = ClientBuilder::new()
         .tls_danger_accept_invalid_certs(true)
@@ -70,3 +73,39 @@ fn wrong_path_key() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Self-signed certificate is cached at the given paths and its fingerprint is printed.
+#[rstest]
+fn tls_self_signed(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let certdir = TempDir::new()?;
+    let cert_file = certdir.path().join("cert.pem");
+    let key_file = certdir.path().join("key.pem");
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .args([
+            "-b",
+            "127.0.0.1",
+            "-p",
+            &port.to_string(),
+            "--tls-self-signed",
+        ])
+        .arg("--tls-cert")
+        .arg(&cert_file)
+        .arg("--tls-key")
+        .arg(&key_file)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+    child.kill()?;
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+
+    assert!(stdout.contains(&format!("https://127.0.0.1:{port}/")));
+    let re =
+        regex::Regex::new(r"Certificate SHA-256 fingerprint:\n  ([0-9A-F]{2}:){31}[0-9A-F]{2}")?;
+    assert!(re.is_match(&stdout));
+    assert!(cert_file.exists());
+    assert!(key_file.exists());
+    Ok(())
+}
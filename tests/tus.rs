@@ -0,0 +1,223 @@
+mod fixtures;
+mod utils;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use sha1::{Digest, Sha1};
+
+fn create_upload(server: &TestServer, name: &str, length: usize) -> Result<String, Error> {
+    let resp = fetch!(b"POST", format!("{}dir1/", server.url()))
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", length.to_string())
+        .header(
+            "Upload-Metadata",
+            format!(
+                "filename {},filetype dGV4dC9wbGFpbg==",
+                STANDARD.encode(name)
+            ),
+        )
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("tus-resumable").unwrap(), "1.0.0");
+    assert!(resp.headers().contains_key("upload-expires"));
+    let location = resp.headers().get("location").unwrap().to_str()?;
+    Ok(format!(
+        "{}{}",
+        server.url(),
+        location.trim_start_matches('/')
+    ))
+}
+
+fn patch_upload(
+    url: &str,
+    offset: usize,
+    data: &'static [u8],
+) -> reqwest::blocking::RequestBuilder {
+    reqwest::blocking::Client::new()
+        .patch(url)
+        .header("Tus-Resumable", "1.0.0")
+        .header("Content-Type", "application/offset+octet-stream")
+        .header("Upload-Offset", offset.to_string())
+        .body(data)
+}
+
+#[rstest]
+fn tus_options(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"OPTIONS", server.url()).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("tus-version").unwrap(), "1.0.0");
+    let extensions = resp.headers().get("tus-extension").unwrap().to_str()?;
+    assert!(extensions.split(',').any(|v| v == "creation"));
+    assert!(resp.headers().contains_key("tus-checksum-algorithm"));
+    Ok(())
+}
+
+#[rstest]
+fn tus_upload(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = create_upload(&server, "tus file.txt", 11)?;
+    assert!(url.contains("/dir1/tus%20file.txt?tus="));
+
+    let resp = fetch!(b"HEAD", &url)
+        .header("Tus-Resumable", "1.0.0")
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("upload-offset").unwrap(), "0");
+    assert_eq!(resp.headers().get("upload-length").unwrap(), "11");
+    assert_eq!(resp.headers().get("cache-control").unwrap(), "no-store");
+
+    let checksum = STANDARD.encode(Sha1::digest(b"Hello"));
+    let resp = patch_upload(&url, 0, b"Hello")
+        .header("Upload-Checksum", format!("sha1 {checksum}"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.headers().get("upload-offset").unwrap(), "5");
+
+    let resp = fetch!(b"GET", format!("{}dir1/tus%20file.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 404);
+
+    let resp = patch_upload(&url, 5, b" World").send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(resp.headers().get("upload-offset").unwrap(), "11");
+
+    let resp = fetch!(b"GET", format!("{}dir1/tus%20file.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "Hello World");
+
+    let resp = fetch!(b"HEAD", &url)
+        .header("Tus-Resumable", "1.0.0")
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn tus_upload_conflicts(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = create_upload(&server, "file.txt", 10)?;
+
+    let resp = patch_upload(&url, 3, b"Hello").send()?;
+    assert_eq!(resp.status(), 409);
+
+    let checksum = STANDARD.encode(Sha1::digest(b"World"));
+    let resp = patch_upload(&url, 0, b"Hello")
+        .header("Upload-Checksum", format!("sha1 {checksum}"))
+        .send()?;
+    assert_eq!(resp.status(), 460);
+
+    let resp = fetch!(b"HEAD", &url)
+        .header("Tus-Resumable", "1.0.0")
+        .send()?;
+    assert_eq!(resp.headers().get("upload-offset").unwrap(), "0");
+
+    let resp = patch_upload(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
+#[rstest]
+#[case("overwrite", 403, None)]
+#[case("fail", 409, None)]
+#[case("rename", 204, Some("race%20%282%29.txt"))]
+fn tus_upload_name_taken(
+    #[with(&["--allow-upload"])] server: TestServer,
+    #[case] conflict: &str,
+    #[case] status: u16,
+    #[case] upload_name: Option<&str>,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}dir1/", server.url()))
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", "5")
+        .header(
+            "Upload-Metadata",
+            format!("filename {}", STANDARD.encode("race.txt")),
+        )
+        .header("X-Upload-Conflict", conflict)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let location = resp.headers().get("location").unwrap().to_str()?;
+    let url = format!("{}{}", server.url(), location.trim_start_matches('/'));
+
+    // Another upload takes the name before this one completes
+    let resp = fetch!(b"PUT", format!("{}dir1/race.txt", server.url()))
+        .body("taken")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = patch_upload(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), status);
+    assert_eq!(
+        resp.headers()
+            .get("x-upload-name")
+            .map(|v| v.to_str().unwrap()),
+        upload_name
+    );
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/race.txt"))?,
+        "taken"
+    );
+    Ok(())
+}
+
+#[rstest]
+fn tus_concurrent_patches(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = create_upload(&server, "file.txt", 10)?;
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let url = url.clone();
+            std::thread::spawn(move || patch_upload(&url, 0, b"Hello").send().unwrap().status())
+        })
+        .collect();
+    let mut statuses: Vec<_> = handles
+        .into_iter()
+        .map(|v| v.join().unwrap().as_u16())
+        .collect();
+    statuses.sort_unstable();
+    // Only one append lands at offset 0
+    assert_eq!(statuses, [204, 409, 409, 409]);
+    let resp = fetch!(b"HEAD", &url)
+        .header("Tus-Resumable", "1.0.0")
+        .send()?;
+    assert_eq!(resp.headers().get("upload-offset").unwrap(), "5");
+
+    // Staged next to the target
+    let staged = std::fs::read_dir(server.path().join("dir1"))?
+        .filter_map(|v| v.ok())
+        .filter(|v| v.file_name().to_string_lossy().starts_with(".dufs-upload-"))
+        .count();
+    assert_eq!(staged, 2);
+    Ok(())
+}
+
+#[rstest]
+fn tus_terminate(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = create_upload(&server, "file.txt", 10)?;
+    let resp = fetch!(b"DELETE", &url)
+        .header("Tus-Resumable", "1.0.0")
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = patch_upload(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn tus_version_mismatch(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}dir1/", server.url()))
+        .header("Tus-Resumable", "0.2.2")
+        .header("Upload-Length", "5")
+        .send()?;
+    assert_eq!(resp.status(), 412);
+    assert_eq!(resp.headers().get("tus-version").unwrap(), "1.0.0");
+    Ok(())
+}
+
+#[rstest]
+fn tus_not_allowed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}dir1/", server.url()))
+        .header("Tus-Resumable", "1.0.0")
+        .header("Upload-Length", "5")
+        .header("Upload-Metadata", "filename dGVzdC50eHQ=")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
@@ -0,0 +1,119 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::{fixture::TempDir, prelude::*};
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer};
+use reqwest::blocking::multipart::{Form, Part};
+use rstest::rstest;
+use std::process::{Command, Stdio};
+
+#[rstest]
+fn upload_conflict_default(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Conflict", "rename")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        resp.headers().get("x-upload-name").unwrap(),
+        "index%20%282%29.html"
+    );
+    assert_eq!(std::fs::read(server.path().join("index (2).html"))?, b"abc");
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("index.html"))?,
+        "This is index.html"
+    );
+
+    let resp = fetch!(b"PUT", &url)
+        .header("X-Upload-Conflict", "whatever")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn upload_conflict_fail(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}index.html?conflict=fail", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 409);
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("index.html"))?,
+        "This is index.html"
+    );
+
+    let url = format!("{}new.txt?conflict=fail", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("x-upload-name").unwrap(), "new.txt");
+    Ok(())
+}
+
+#[rstest]
+fn upload_conflict_keep_both(
+    #[with(&["--allow-upload", "--upload-conflict", "keep-both"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}index.html", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("x-upload-name").unwrap(), "index.html");
+    assert_eq!(std::fs::read(server.path().join("index.html"))?, b"abc");
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("index (2).html"))?,
+        "This is index.html"
+    );
+
+    let form = Form::new().part("file", Part::bytes(b"def".to_vec()).file_name("index.html"));
+    let resp = reqwest::blocking::Client::new()
+        .post(server.url())
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::read(server.path().join("index.html"))?, b"def");
+    assert_eq!(std::fs::read(server.path().join("index (3).html"))?, b"abc");
+    Ok(())
+}
+
+#[rstest]
+fn upload_conflict_per_path(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config_file = tmpdir.child("config.yaml");
+    config_file.write_str(
+        r#"
+allow-upload: true
+upload-conflict: fail
+upload-limits:
+  - path: /dir1
+    conflict: rename
+"#,
+    )?;
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--config")
+        .arg(config_file.path())
+        .stdout(Stdio::null())
+        .spawn()?;
+    wait_for_port(port);
+    let url = format!("http://localhost:{port}");
+
+    let resp = fetch!(b"PUT", format!("{url}/index.html"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 409);
+    let resp = fetch!(b"PUT", format!("{url}/dir1/test.txt"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        resp.headers().get("x-upload-name").unwrap(),
+        "test%20%282%29.txt"
+    );
+
+    child.kill()?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::{fixture::TempDir, prelude::*};
+use fixtures::{port, server, tmpdir, wait_for_port, Error, TestServer};
+use reqwest::blocking::multipart::{Form, Part};
+use rstest::rstest;
+use std::process::{Command, Stdio};
+
+#[rstest]
+fn upload_max_size(
+    #[with(&["--allow-upload", "--upload-max-size", "10"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"0123456789a".to_vec()).send()?;
+    assert_eq!(resp.status(), 413);
+    assert!(resp.text()?.contains("10 bytes"));
+    assert!(!server.path().join("file1").exists());
+
+    let body = reqwest::blocking::Body::new(std::io::Cursor::new(b"0123456789a".to_vec()));
+    let resp = fetch!(b"PUT", &url).body(body).send()?;
+    assert_eq!(resp.status(), 413);
+    assert!(!server.path().join("file1").exists());
+
+    let resp = fetch!(b"PUT", &url).body(b"0123456789".to_vec()).send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"a".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    assert_eq!(std::fs::read(server.path().join("file1"))?, b"0123456789");
+
+    let form = Form::new().part("file", Part::bytes(vec![0; 11]).file_name("file2"));
+    let resp = reqwest::blocking::Client::new()
+        .post(server.url())
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    assert!(!server.path().join("file2").exists());
+    Ok(())
+}
+
+#[rstest]
+fn upload_allow_deny(
+    #[with(&["--allow-upload", "--upload-allow", "text/*,png", "--upload-deny", "html"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}file.txt", server.url()))
+        .body(b"Hello".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{}file.png", server.url()))
+        .body(b"\x89PNG\r\n\x1a\n\x00".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = fetch!(b"PUT", format!("{}file.html", server.url()))
+        .body(b"<html></html>".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(resp.text()?.contains("file.html"));
+    let resp = fetch!(b"PUT", format!("{}file.exe", server.url()))
+        .body(b"MZ".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    // Binary content disguised as text
+    let resp = fetch!(b"PUT", format!("{}bin.txt", server.url()))
+        .body(b"\x7f\x45\x4c\x46\x02\x01\x00\x00".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+    assert!(!server.path().join("bin.txt").exists());
+    Ok(())
+}
+
+#[rstest]
+fn upload_limits_per_path(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let config_file = tmpdir.child("config.yaml");
+    config_file.write_str(
+        r#"
+allow-upload: true
+upload-max-size: 10
+upload-limits:
+  - path: /dir1
+    max-size: 5
+  - path: /dir2
+    max-size: 1K
+    deny: [text/*]
+"#,
+    )?;
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin!())
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("--config")
+        .arg(config_file.path())
+        .stdout(Stdio::null())
+        .spawn()?;
+    wait_for_port(port);
+    let url = format!("http://localhost:{port}");
+
+    let resp = fetch!(b"PUT", format!("{url}/dir1/file"))
+        .body(b"012345".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PUT", format!("{url}/dir3/file"))
+        .body(b"012345".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{url}/dir3/file2"))
+        .body(vec![0; 11])
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    let resp = fetch!(b"PUT", format!("{url}/dir2/file.bin"))
+        .body(vec![0; 100])
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{url}/dir2/file.txt"))
+        .body(b"Hello".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 415);
+
+    child.kill()?;
+    Ok(())
+}
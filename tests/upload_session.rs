@@ -0,0 +1,231 @@
+mod fixtures;
+mod utils;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use sha2::{Digest, Sha256};
+
+fn open_session(server: &TestServer, name: &str, length: usize) -> Result<String, Error> {
+    let resp = fetch!(b"POST", format!("{}{name}?session", server.url()))
+        .header("X-Upload-Length", length.to_string())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let location = resp.headers().get("location").unwrap().to_str()?;
+    Ok(format!(
+        "{}{}",
+        server.url(),
+        location.trim_start_matches('/')
+    ))
+}
+
+fn put_chunk(url: &str, start: usize, data: &'static [u8]) -> reqwest::blocking::RequestBuilder {
+    reqwest::blocking::Client::new()
+        .put(url)
+        .header(
+            "X-Update-Range",
+            format!("bytes={start}-{}", start + data.len() - 1),
+        )
+        .body(data)
+}
+
+#[rstest]
+fn upload_session(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = open_session(&server, "dir1/big%20file.txt", 15)?;
+    assert!(url.contains("/dir1/big%20file.txt?session="));
+
+    let handles: Vec<_> = [
+        (10, b"Chunk" as &'static [u8]),
+        (0, b"Hello"),
+        (5, b"World"),
+    ]
+    .into_iter()
+    .map(|(start, data)| {
+        let url = url.clone();
+        std::thread::spawn(move || put_chunk(&url, start, data).send().unwrap().status())
+    })
+    .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 204);
+    }
+
+    let resp = fetch!(b"HEAD", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-upload-length").unwrap(), "15");
+    assert_eq!(resp.headers().get("x-upload-received").unwrap(), "0-14");
+    assert!(!server.path().join("dir1/big file.txt").exists());
+    // Staged next to the target, hidden from listings
+    assert_eq!(staged_files(&server)?, 2);
+    let resp = fetch!(b"GET", format!("{}dir1/", server.url())).send()?;
+    assert!(!resp.text()?.contains(".dufs-upload-"));
+
+    let digest = format!(
+        "sha-256=:{}:",
+        STANDARD.encode(Sha256::digest(b"HelloWorldChunk"))
+    );
+    let resp = fetch!(b"POST", &url)
+        .header("X-Mtime", "1700000000")
+        .header("Repr-Digest", &digest)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(resp.headers().get("content-digest").unwrap(), &digest);
+    assert_eq!(
+        resp.headers().get("x-upload-name").unwrap(),
+        "big%20file.txt"
+    );
+    let mtime = std::fs::metadata(server.path().join("dir1/big file.txt"))?.modified()?;
+    assert_eq!(
+        mtime.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+        1700000000
+    );
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/big file.txt"))?,
+        "HelloWorldChunk"
+    );
+
+    assert_eq!(staged_files(&server)?, 0);
+
+    let resp = fetch!(b"HEAD", &url).send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+fn staged_files(server: &TestServer) -> Result<usize, Error> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(server.path().join("dir1"))? {
+        if entry?
+            .file_name()
+            .to_string_lossy()
+            .starts_with(".dufs-upload-")
+        {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[rstest]
+fn upload_session_incomplete(#[with(&["--allow-upload"])] server: TestServer) -> Result<(), Error> {
+    let url = open_session(&server, "file.txt", 15)?;
+    let resp = put_chunk(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = put_chunk(&url, 10, b"Chunk").send()?;
+    assert_eq!(resp.status(), 204);
+    assert_eq!(
+        resp.headers().get("x-upload-received").unwrap(),
+        "0-4,10-14"
+    );
+
+    let resp = fetch!(b"POST", &url).send()?;
+    assert_eq!(resp.status(), 409);
+    assert!(!server.path().join("file.txt").exists());
+
+    let resp = put_chunk(&url, 12, b"TooLong").send()?;
+    assert_eq!(resp.status(), 416);
+
+    let resp = fetch!(b"DELETE", &url).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = put_chunk(&url, 5, b"World").send()?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn upload_session_limits(
+    #[with(&["--allow-upload", "--upload-max-size", "10"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}file.txt?session", server.url()))
+        .header("X-Upload-Length", "11")
+        .send()?;
+    assert_eq!(resp.status(), 413);
+
+    let resp = fetch!(b"POST", format!("{}index.html?session", server.url()))
+        .header("X-Upload-Length", "5")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"POST", format!("{}file.txt?session", server.url())).send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn upload_session_not_allowed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}file.txt?session", server.url()))
+        .header("X-Upload-Length", "5")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn upload_session_commit_twice(
+    #[with(&["--allow-upload"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = open_session(&server, "dir1/file.txt", 5)?;
+    let resp = put_chunk(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), 204);
+
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let url = url.clone();
+            std::thread::spawn(move || {
+                reqwest::blocking::Client::new()
+                    .post(&url)
+                    .send()
+                    .unwrap()
+                    .status()
+            })
+        })
+        .collect();
+    let mut statuses: Vec<_> = handles.into_iter().map(|v| v.join().unwrap()).collect();
+    statuses.sort();
+    assert_eq!(statuses, [201, 404]);
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/file.txt"))?,
+        "Hello"
+    );
+    assert_eq!(staged_files(&server)?, 0);
+    Ok(())
+}
+
+#[rstest]
+#[case("overwrite", 403, None)]
+#[case("fail", 409, None)]
+#[case("rename", 201, Some("race%20%282%29.txt"))]
+fn upload_session_name_taken(
+    #[with(&["--allow-upload"])] server: TestServer,
+    #[case] conflict: &str,
+    #[case] status: u16,
+    #[case] upload_name: Option<&str>,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}dir1/race.txt?session", server.url()))
+        .header("X-Upload-Length", "5")
+        .header("X-Upload-Conflict", conflict)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let location = resp.headers().get("location").unwrap().to_str()?;
+    let url = format!("{}{}", server.url(), location.trim_start_matches('/'));
+    let resp = put_chunk(&url, 0, b"Hello").send()?;
+    assert_eq!(resp.status(), 204);
+
+    // Another upload takes the name before this one commits
+    let resp = fetch!(b"PUT", format!("{}dir1/race.txt", server.url()))
+        .body("taken")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let resp = fetch!(b"POST", &url).send()?;
+    assert_eq!(resp.status(), status);
+    assert_eq!(
+        resp.headers()
+            .get("x-upload-name")
+            .map(|v| v.to_str().unwrap()),
+        upload_name
+    );
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/race.txt"))?,
+        "taken"
+    );
+    Ok(())
+}
@@ -90,6 +90,47 @@ fn proppatch_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn proppatch_mtime(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:schemas-microsoft-com:">
+<D:set><D:prop>
+<D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+<Z:Win32FileAttributes>00000020</Z:Win32FileAttributes>
+</D:prop></D:set>
+</D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", format!("{}test.html", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:getlastmodified/>"));
+    assert!(body.contains("HTTP/1.1 200 OK"));
+    assert!(body.contains("HTTP/1.1 403 Forbidden"));
+    let mtime = std::fs::metadata(server.path().join("test.html"))?.modified()?;
+    assert_eq!(
+        mtime.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+        1700000000
+    );
+    Ok(())
+}
+
+#[rstest]
+fn proppatch_mtime_not_allow_upload(server: TestServer) -> Result<(), Error> {
+    let before = std::fs::metadata(server.path().join("test.html"))?.modified()?;
+    let body = r#"<D:propertyupdate xmlns:D="DAV:"><D:set><D:prop>
+<D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+</D:prop></D:set></D:propertyupdate>"#;
+    let resp = fetch!(b"PROPPATCH", format!("{}test.html", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text()?.contains("HTTP/1.1 403 Forbidden"));
+    let after = std::fs::metadata(server.path().join("test.html"))?.modified()?;
+    assert_eq!(before, after);
+    Ok(())
+}
+
 #[rstest]
 fn proppatch_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"PROPPATCH", format!("{}404", server.url())).send()?;
@@ -0,0 +1,91 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use reqwest::blocking::multipart::{Form, Part};
+use rstest::rstest;
+
+#[rstest]
+fn write_only_upload(
+    #[with(&["-a", "@/:ro,/dir1:wo", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}dir1/new.txt", server.url()))
+        .body(b"new".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::read(server.path().join("dir1/new.txt"))?, b"new");
+
+    // Existing files are never replaced, whatever the client asks for
+    let resp = fetch!(b"PUT", format!("{}dir1/test.txt", server.url()))
+        .header("X-Upload-Conflict", "overwrite")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        resp.headers().get("x-upload-name").unwrap(),
+        "test%20%282%29.txt"
+    );
+    assert_eq!(
+        std::fs::read_to_string(server.path().join("dir1/test.txt"))?,
+        "This is dir1/test.txt"
+    );
+
+    let form = Form::new().part("file", Part::bytes(b"def".to_vec()).file_name("test.txt"));
+    let resp = reqwest::blocking::Client::new()
+        .post(format!("{}dir1/", server.url()))
+        .multipart(form)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(
+        std::fs::read(server.path().join("dir1/test (3).txt"))?,
+        b"def"
+    );
+
+    let resp = fetch!(b"PATCH", format!("{}dir1/test.txt", server.url()))
+        .header("X-Update-Range", "append")
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"DELETE", format!("{}dir1/test.txt", server.url())).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn write_only_no_read(
+    #[with(&["-a", "@/:ro,/dir1:wo", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/test.txt", server.url());
+    assert_eq!(fetch!(b"GET", &url).send()?.status(), 403);
+    assert_eq!(fetch!(b"HEAD", &url).send()?.status(), 403);
+    assert_eq!(fetch!(b"PROPFIND", &url).send()?.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir1/test.txt?hash", server.url())).send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"GET", format!("{}dir1/", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    let json = utils::retrieve_json(&resp.text()?).unwrap();
+    assert_eq!(json["kind"], "Upload");
+    assert_eq!(json["allow_upload"], true);
+    assert_eq!(json["paths"].as_array().unwrap().len(), 0);
+
+    let resp = fetch!(b"PROPFIND", format!("{}dir1/", server.url())).send()?;
+    assert_eq!(resp.status(), 207);
+    assert_eq!(resp.text()?.matches("<D:response>").count(), 1);
+    Ok(())
+}
+
+#[rstest]
+fn write_only_hidden_from_parent(
+    #[with(&["-a", "@/:ro,/dir1:wo", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url()).send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir1/"));
+
+    let resp = fetch!(b"GET", format!("{}?q={}", server.url(), "test.html")).send()?;
+    let paths = utils::retrieve_index_paths(&resp.text()?);
+    assert!(paths.contains("dir2/test.html"));
+    assert!(!paths.iter().any(|v| v.starts_with("dir1/")));
+    Ok(())
+}